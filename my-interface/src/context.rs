@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use juniper::Context;
+
+use crate::{Bar, DataContextParams, Foo, Identity, Light, DEFAULT_DATASET};
+
+/// 一个数据集中的数据，不同租户的数据集相互隔离
+#[derive(Default, Clone)]
+struct DataSet {
+    foo_storage: HashMap<i32, Foo>,
+    bar_storage: HashMap<i32, Bar>,
+}
+
+#[derive(Default, Clone)]
+/// 一个模拟数据上下文状态的结构
+pub struct DataContext {
+    tenant: String,
+    dataset: String,
+    identity: Option<Identity>,
+    datasets: HashMap<String, DataSet>,
+}
+
+impl Context for DataContext {}
+
+impl DataContext {
+    pub fn new() -> Self {
+        let mut datasets = HashMap::new();
+        Self::init_master(datasets.entry("master".to_string()).or_default());
+        Self::init_dev(datasets.entry("dev".to_string()).or_default());
+        Self {
+            tenant: DEFAULT_DATASET.to_string(),
+            dataset: DEFAULT_DATASET.to_string(),
+            identity: None,
+            datasets,
+        }
+    }
+    /// 兼容原有的flag：true使用租户`true`与数据集`dev`，false使用租户`false`与数据集`master`
+    pub fn flag(&mut self, f: bool) {
+        let dataset = if f { "dev" } else { "master" };
+        self.tenant(&f.to_string(), dataset);
+    }
+    /// 设置请求的租户以及租户使用的数据集
+    pub fn tenant(&mut self, tenant: &str, dataset: &str) {
+        self.tenant = tenant.to_string();
+        self.dataset = dataset.to_string();
+    }
+    pub fn identity(&mut self, identity: Option<Identity>) {
+        self.identity = identity;
+    }
+    pub fn get_tenant(&self) -> &str {
+        &self.tenant
+    }
+    pub fn get_dataset(&self) -> &str {
+        &self.dataset
+    }
+    pub fn get_identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+    pub fn params(&self) -> DataContextParams {
+        DataContextParams {
+            tenant: self.tenant.clone(),
+            dataset: self.dataset.clone(),
+            identity: self.identity.clone(),
+        }
+    }
+    /// 根据参数重新构造数据上下文
+    pub fn from_params(params: DataContextParams) -> Self {
+        let mut context = Self::new();
+        context.tenant(&params.tenant, &params.dataset);
+        context.identity(params.identity);
+        context
+    }
+    fn init_master(data: &mut DataSet) {
+        let foos = &mut data.foo_storage;
+        foos.insert(1, Foo::new(1, "foo1".to_string(), vec![1, 2]));
+        foos.insert(2, Foo::new(1, "foo2".to_string(), vec![3, 4]));
+        let bars = &mut data.bar_storage;
+        bars.insert(1, Bar::new(1, Light::Bright));
+        bars.insert(2, Bar::new(2, Light::Dark));
+        bars.insert(3, Bar::new(3, Light::Bright));
+        bars.insert(4, Bar::new(4, Light::Dark));
+        bars.insert(6, Bar::new(6, Light::Dark));
+        bars.insert(7, Bar::new(7, Light::Bright));
+    }
+    fn init_dev(data: &mut DataSet) {
+        let foos = &mut data.foo_storage;
+        foos.insert(3, Foo::new(3, "foo3".to_string(), vec![5, 6]));
+        foos.insert(4, Foo::new(4, "foo4".to_string(), vec![7, 8]));
+        let bars = &mut data.bar_storage;
+        bars.insert(5, Bar::new(5, Light::Bright));
+        bars.insert(8, Bar::new(8, Light::Dark));
+    }
+    // 租户的数据集，未知的数据集没有数据
+    fn data(&self) -> Option<&DataSet> {
+        self.datasets.get(&self.dataset)
+    }
+    pub fn get_foos(&self) -> Vec<&Foo> {
+        self.data()
+            .map(|data| data.foo_storage.values().collect())
+            .unwrap_or_default()
+    }
+    pub fn get_foo(&self, id: i32) -> Option<&Foo> {
+        self.data()?.foo_storage.get(&id)
+    }
+    pub fn get_bars(&self) -> Vec<&Bar> {
+        self.data()
+            .map(|data| data.bar_storage.values().collect())
+            .unwrap_or_default()
+    }
+    pub fn get_bar(&self, id: i32) -> Option<&Bar> {
+        self.data()?.bar_storage.get(&id)
+    }
+    pub fn get_bars_by_ids(&self, ids: Vec<i32>) -> Vec<&Bar> {
+        self.get_bars()
+            .into_iter()
+            .filter(|v| ids.contains(&v.id))
+            .collect()
+    }
+}
@@ -0,0 +1,113 @@
+//! 编译为wasm32-wasi的插件所使用的接口
+use juniper::{http::GraphQLBatchRequest, Context, DefaultScalarValue, GraphQLType, RootNode};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    wasm::{DataRequest, WasmRequest, WasmResponse},
+    Bar, DataContextParams, Foo, Identity, PluginMetadata,
+};
+
+#[link(wasm_import_module = "my_host")]
+extern "C" {
+    fn data_request(ptr: u32, len: u32) -> u32;
+    fn data_response(ptr: u32);
+}
+
+/// wasm插件中的数据上下文，数据由主服务通过宿主函数提供
+#[derive(Debug, Clone, Default)]
+pub struct DataContext {
+    params: DataContextParams,
+}
+
+impl Context for DataContext {}
+
+impl DataContext {
+    pub fn from_params(params: DataContextParams) -> Self {
+        Self { params }
+    }
+    pub fn get_tenant(&self) -> &str {
+        &self.params.tenant
+    }
+    pub fn get_dataset(&self) -> &str {
+        &self.params.dataset
+    }
+    pub fn get_identity(&self) -> Option<&Identity> {
+        self.params.identity.as_ref()
+    }
+    pub fn get_foos(&self) -> Vec<Foo> {
+        query(DataRequest::GetFoos)
+    }
+    pub fn get_foo(&self, id: i32) -> Option<Foo> {
+        query(DataRequest::GetFoo(id))
+    }
+    pub fn get_bars(&self) -> Vec<Bar> {
+        query(DataRequest::GetBars)
+    }
+    pub fn get_bar(&self, id: i32) -> Option<Bar> {
+        query(DataRequest::GetBar(id))
+    }
+    pub fn get_bars_by_ids(&self, ids: Vec<i32>) -> Vec<Bar> {
+        query(DataRequest::GetBarsByIds(ids))
+    }
+}
+
+// 调用宿主函数获取数据，宿主返回的数据无法解析属于宿主的错误，直接panic
+fn query<T: DeserializeOwned>(request: DataRequest) -> T {
+    let request = serde_json::to_vec(&request).expect("data request is serializable");
+    let len = unsafe { data_request(request.as_ptr() as u32, request.len() as u32) };
+    let mut response = vec![0u8; len as usize];
+    unsafe { data_response(response.as_mut_ptr() as u32) };
+    serde_json::from_slice(&response).expect("invalid data response from host")
+}
+
+/// 分配内存供主服务写入请求
+pub fn alloc(len: u32) -> u32 {
+    let mut buffer = Vec::<u8>::with_capacity(len as usize);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr as u32
+}
+
+/// 处理主服务的请求，返回值高32位为响应的地址，低32位为长度。
+/// 主服务每个请求都使用新的实例，请求与响应的内存不需要释放
+pub fn handle<QueryT, MutationT, SubscriptionT>(
+    root_node: &RootNode<'static, QueryT, MutationT, SubscriptionT>,
+    metadata: PluginMetadata,
+    ptr: u32,
+    len: u32,
+) -> u64
+where
+    QueryT: GraphQLType<DefaultScalarValue, Context = DataContext, TypeInfo = ()>,
+    MutationT: GraphQLType<DefaultScalarValue, Context = DataContext, TypeInfo = ()>,
+    SubscriptionT: GraphQLType<DefaultScalarValue, Context = DataContext, TypeInfo = ()>,
+{
+    let request = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    let response = match serde_json::from_slice(request) {
+        Ok(WasmRequest::Describe) => WasmResponse::Describe {
+            id: metadata.name.clone(),
+            schema_sdl: root_node.as_schema_language(),
+            metadata,
+        },
+        Ok(WasmRequest::Execute { context, request }) => {
+            match serde_json::from_value::<GraphQLBatchRequest>(request) {
+                Ok(request) => {
+                    let context = DataContext::from_params(context);
+                    let response = request.execute_sync(root_node, &context);
+                    match serde_json::to_string(&response) {
+                        Ok(body) => WasmResponse::Executed {
+                            body,
+                            is_ok: response.is_ok(),
+                        },
+                        Err(e) => WasmResponse::Error(e.to_string()),
+                    }
+                }
+                Err(e) => WasmResponse::Error(e.to_string()),
+            }
+        }
+        Err(e) => WasmResponse::Error(e.to_string()),
+    };
+    let response = serde_json::to_vec(&response).expect("response is serializable");
+    let (ptr, len) = (response.as_ptr() as u64, response.len() as u64);
+    std::mem::forget(response);
+    (ptr << 32) | len
+}
@@ -0,0 +1,94 @@
+use std::{collections::HashMap, future::Future, panic::AssertUnwindSafe, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use dyn_clone::{clone_trait_object, DynClone};
+use futures::FutureExt;
+use juniper::{http::GraphQLBatchRequest, DefaultScalarValue};
+use warp::{filters::BoxedFilter, http, Filter, Rejection};
+
+use crate::{
+    panic_message, DataContext, FieldTracer, PluginMetadata, QueryLimits, PLUGIN_PANIC_HEADER,
+};
+
+/// 请求处理器的特型
+#[async_trait]
+pub trait GraphqlRequestHandler: DynClone {
+    fn id(&self) -> String;
+    /// 插件的元数据，主服务在执行请求前会根据其中的限制检查查询
+    fn metadata(&self) -> PluginMetadata {
+        PluginMetadata::new(self.id(), "0.0.0".to_string(), QueryLimits::default())
+    }
+    /// 插件schema的SDL，用于判断schema是否发生变化
+    fn schema_sdl(&self) -> String {
+        String::new()
+    }
+    /// 设置字段追踪器。默认实现编译在插件中，设置的是插件自己的全局追踪器
+    fn set_field_tracer(&self, tracer: Arc<dyn FieldTracer>) {
+        crate::set_field_tracer(tracer)
+    }
+    async fn get_request_handle(
+        &self,
+        context: DataContext,
+        qry: HashMap<String, String>,
+    ) -> Result<http::Response<Vec<u8>>, Rejection>;
+    async fn post_json_request_handle(
+        &self,
+        context: DataContext,
+        req: GraphQLBatchRequest<DefaultScalarValue>,
+    ) -> Result<http::Response<Vec<u8>>, Rejection>;
+    async fn post_grqphql_request_handle(
+        &self,
+        context: DataContext,
+        body: Bytes,
+    ) -> Result<http::Response<Vec<u8>>, Rejection>;
+}
+clone_trait_object!(GraphqlRequestHandler);
+
+/// 构造graphql的响应体
+pub fn build_response(response: Result<(Vec<u8>, bool), anyhow::Error>) -> http::Response<Vec<u8>> {
+    match response {
+        Ok((body, is_ok)) => http::Response::builder()
+            .status(if is_ok { 200 } else { 400 })
+            .header("content-type", "application/json")
+            .body(body)
+            .expect("response is valid"),
+        Err(_) => http::Response::builder()
+            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Vec::new())
+            .expect("status code is valid"),
+    }
+}
+
+/// 构造插件panic时的graphql错误响应
+pub fn plugin_panic_response(message: String) -> http::Response<Vec<u8>> {
+    let header = http::HeaderValue::from_str(&message)
+        .unwrap_or_else(|_| http::HeaderValue::from_static("plugin panicked"));
+    let body = serde_json::json!({
+        "errors": [{
+            "message": format!("plugin panicked: {}", message),
+            "extensions": { "code": "PLUGIN_PANIC" }
+        }]
+    });
+    http::Response::builder()
+        .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+        .header("content-type", "application/json")
+        .header(PLUGIN_PANIC_HEADER, header)
+        .body(body.to_string().into_bytes())
+        .expect("response is valid")
+}
+
+/// 在插件内部执行请求并捕获panic，供生成的处理器代码使用
+pub async fn catch_plugin_panic<F>(fut: F) -> Result<http::Response<Vec<u8>>, Rejection>
+where
+    F: Future<Output = Result<http::Response<Vec<u8>>, Rejection>>,
+{
+    match AssertUnwindSafe(fut).catch_unwind().await {
+        Ok(result) => result,
+        Err(payload) => Ok(plugin_panic_response(panic_message(payload))),
+    }
+}
+
+pub fn data_context_extractor() -> BoxedFilter<(DataContext,)> {
+    warp::any().map(DataContext::new).boxed()
+}
@@ -1,45 +1,76 @@
-use std::collections::HashMap;
-
-use async_trait::async_trait;
-use bytes::Bytes;
-use dyn_clone::{clone_trait_object, DynClone};
-use juniper::{http::GraphQLBatchRequest, Context, DefaultScalarValue, GraphQLEnum};
-use warp::{filters::BoxedFilter, http, Filter, Rejection};
-
-/// 请求处理器的特型
-#[async_trait]
-pub trait GraphqlRequestHandler: DynClone {
-    fn id(&self) -> String;
-    async fn get_request_handle(
-        &self,
-        context: DataContext,
-        qry: HashMap<String, String>,
-    ) -> Result<http::Response<Vec<u8>>, Rejection>;
-    async fn post_json_request_handle(
-        &self,
-        context: DataContext,
-        req: GraphQLBatchRequest<DefaultScalarValue>,
-    ) -> Result<http::Response<Vec<u8>>, Rejection>;
-    async fn post_grqphql_request_handle(
-        &self,
-        context: DataContext,
-        body: Bytes,
-    ) -> Result<http::Response<Vec<u8>>, Rejection>;
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+};
+
+use juniper::{graphql_value, FieldError, FieldResult, GraphQLEnum};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "native")]
+mod context;
+#[cfg(feature = "wasm-guest")]
+pub mod guest;
+#[cfg(feature = "native")]
+mod handler;
+mod name;
+mod trace;
+pub mod wasm;
+
+#[cfg(feature = "native")]
+pub use context::DataContext;
+#[cfg(all(feature = "wasm-guest", not(feature = "native")))]
+pub use guest::DataContext;
+#[cfg(feature = "native")]
+pub use handler::*;
+pub use name::*;
+pub use trace::*;
+
+/// 查询的限制，未配置的项不做限制
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryLimits {
+    pub max_depth: Option<usize>,
+    pub max_complexity: Option<usize>,
+    pub max_aliases: Option<usize>,
+    pub max_batch_size: Option<usize>,
+    /// 字段的复杂度开销，key为字段名，未配置的字段开销为1
+    #[serde(default)]
+    pub field_costs: HashMap<String, usize>,
 }
-clone_trait_object!(GraphqlRequestHandler);
-
-/// 构造graphql的响应体
-pub fn build_response(response: Result<(Vec<u8>, bool), anyhow::Error>) -> http::Response<Vec<u8>> {
-    match response {
-        Ok((body, is_ok)) => http::Response::builder()
-            .status(if is_ok { 200 } else { 400 })
-            .header("content-type", "application/json")
-            .body(body)
-            .expect("response is valid"),
-        Err(_) => http::Response::builder()
-            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Vec::new())
-            .expect("status code is valid"),
+
+/// 插件的元数据
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginMetadata {
+    pub name: String,
+    pub version: String,
+    pub limits: QueryLimits,
+}
+
+impl PluginMetadata {
+    pub fn new(name: String, version: String, limits: QueryLimits) -> Self {
+        Self {
+            name,
+            version,
+            limits,
+        }
+    }
+}
+
+/// 主服务与插件之间的接口版本。`DataContext`、`DataContextParams`、`Foo`、`Bar`等类型直接跨越动态链接包的边界，
+/// 其布局或`GraphqlRequestHandler`变化时需要递增，主服务拒绝加载接口版本不一致的插件
+pub const INTERFACE_VERSION: u32 = 1;
+
+/// 插件panic时记录panic信息的响应头。插件与主服务各自静态链接了标准库，panic无法跨越动态链接包的边界展开，
+/// 需要在插件内部捕获后通过响应传递给主服务
+pub const PLUGIN_PANIC_HEADER: &str = "x-plugin-panic";
+
+/// 获取panic的信息
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
     }
 }
 
@@ -53,111 +84,87 @@ pub fn get_lib_suffix() -> String {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Foo {
     pub id: i32,
     pub name: String,
     pub bar_ids: Vec<i32>,
-    flag: bool,
 }
 
 impl Foo {
-    pub fn new(id: i32, name: String, bar_ids: Vec<i32>, flag: bool) -> Self {
-        Self {
-            id,
-            name,
-            bar_ids,
-            flag,
-        }
+    pub fn new(id: i32, name: String, bar_ids: Vec<i32>) -> Self {
+        Self { id, name, bar_ids }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bar {
     pub id: i32,
     pub light: Light,
-    flag: bool,
 }
 
 impl Bar {
-    pub fn new(id: i32, light: Light, flag: bool) -> Self {
-        Self { id, light, flag }
+    pub fn new(id: i32, light: Light) -> Self {
+        Self { id, light }
     }
 }
 
-#[derive(GraphQLEnum, Debug, Clone)]
+#[derive(GraphQLEnum, Debug, Clone, Serialize, Deserialize)]
 pub enum Light {
     Bright,
     Dark,
 }
 
-#[derive(Default, Clone)]
-/// 一个模拟数据上下文状态的结构
-pub struct DataContext {
-    flag: bool,
-    foo_storage: HashMap<i32, Foo>,
-    bar_storage: HashMap<i32, Bar>,
+/// 调用方的身份信息，由主服务鉴权后注入
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Identity {
+    pub subject: String,
+    pub scopes: HashSet<String>,
 }
 
-impl Context for DataContext {}
+impl Identity {
+    pub fn new(subject: String, scopes: HashSet<String>) -> Self {
+        Self { subject, scopes }
+    }
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+}
 
-impl DataContext {
-    pub fn new() -> Self {
-        let mut foo_storage = HashMap::new();
-        Self::init_foo(&mut foo_storage);
-        let mut bar_storage = HashMap::new();
-        Self::init_bar(&mut bar_storage);
+/// 未指定租户时使用的数据集
+pub const DEFAULT_DATASET: &str = "master";
+
+/// 数据上下文的参数，插件在工作进程中执行时用于在进程间传递DataContext
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataContextParams {
+    /// 请求的租户
+    pub tenant: String,
+    /// 租户使用的数据集
+    pub dataset: String,
+    pub identity: Option<Identity>,
+}
+
+impl Default for DataContextParams {
+    fn default() -> Self {
         Self {
-            flag: false,
-            foo_storage: foo_storage,
-            bar_storage: bar_storage,
+            tenant: DEFAULT_DATASET.to_string(),
+            dataset: DEFAULT_DATASET.to_string(),
+            identity: None,
         }
     }
-    pub fn flag(&mut self, f: bool) {
-        self.flag = f;
-    }
-    fn init_foo(storage: &mut HashMap<i32, Foo>) {
-        storage.insert(1, Foo::new(1, "foo1".to_string(), vec![1, 2], false));
-        storage.insert(2, Foo::new(1, "foo2".to_string(), vec![3, 4], false));
-        storage.insert(3, Foo::new(3, "foo3".to_string(), vec![5, 6], true));
-        storage.insert(4, Foo::new(4, "foo4".to_string(), vec![7, 8], true));
-    }
-    fn init_bar(storage: &mut HashMap<i32, Bar>) {
-        storage.insert(1, Bar::new(1, Light::Bright, false));
-        storage.insert(2, Bar::new(2, Light::Dark, false));
-        storage.insert(3, Bar::new(3, Light::Bright, false));
-        storage.insert(4, Bar::new(4, Light::Dark, false));
-        storage.insert(5, Bar::new(5, Light::Bright, true));
-        storage.insert(6, Bar::new(6, Light::Dark, false));
-        storage.insert(7, Bar::new(7, Light::Bright, false));
-        storage.insert(8, Bar::new(8, Light::Dark, true));
-    }
-    pub fn get_foos(&self) -> Vec<&Foo> {
-        self.foo_storage
-            .values()
-            .filter(|v| v.flag == self.flag)
-            .collect()
-    }
-    pub fn get_foo(&self, id: i32) -> Option<&Foo> {
-        self.foo_storage.get(&id)
-    }
-    pub fn get_bars(&self) -> Vec<&Bar> {
-        self.bar_storage
-            .values()
-            .filter(|v| v.flag == self.flag)
-            .collect()
-    }
-    pub fn get_bar(&self, id: i32) -> Option<&Bar> {
-        self.bar_storage.get(&id)
-    }
-    pub fn get_bars_by_ids(&self, ids: Vec<i32>) -> Vec<&Bar> {
-        self.bar_storage
-            .values()
-            .filter(|v| ids.contains(&v.id) && v.flag == self.flag)
-            .collect()
-    }
 }
 
-pub fn data_context_extractor() -> BoxedFilter<(DataContext,)> {
-    warp::any().map(move || DataContext::new()).boxed()
+/// 字段级鉴权，要求调用方拥有指定的scope，供插件的resolver使用
+pub fn require_scope(context: &DataContext, scope: &str) -> FieldResult<()> {
+    match context.get_identity() {
+        Some(identity) if identity.has_scope(scope) => Ok(()),
+        Some(_) => Err(FieldError::new(
+            format!("missing required scope: {}", scope),
+            graphql_value!({ "code": "FORBIDDEN" }),
+        )),
+        None => Err(FieldError::new(
+            "unauthenticated",
+            graphql_value!({ "code": "UNAUTHENTICATED" }),
+        )),
+    }
 }
@@ -0,0 +1,140 @@
+use std::{convert::TryFrom, fmt, ops::Deref, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// 插件名称的最大长度
+pub const MAX_PLUGIN_NAME_LEN: usize = 64;
+
+/// 校验过的插件名称：小写字母开头，只包含小写字母、数字与下划线，长度不超过[`MAX_PLUGIN_NAME_LEN`]。
+/// 名称会被拼接到动态链接包、临时项目的路径以及生成项目的包名、库名中，
+/// 限制字符集后不会跳出所在的目录，也不会因为大小写或`-`与`_`的转换与其他插件冲突。
+/// 以前包含大写字母的名称在拼接路径时被转换为小写（`Foo`与`foo`是同一个插件），现在直接拒绝，需要改用小写的名称
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PluginName(String);
+
+/// 不合法的插件名称
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPluginName(pub String);
+
+impl fmt::Display for InvalidPluginName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid plugin name: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPluginName {}
+
+impl PluginName {
+    pub fn new(name: impl Into<String>) -> Result<Self, InvalidPluginName> {
+        let name = name.into();
+        if Self::is_valid(&name) {
+            Ok(Self(name))
+        } else {
+            Err(InvalidPluginName(name))
+        }
+    }
+
+    /// 是否是合法的插件名称
+    pub fn is_valid(name: &str) -> bool {
+        let mut chars = name.chars();
+        name.len() <= MAX_PLUGIN_NAME_LEN
+            && matches!(chars.next(), Some(c) if c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for PluginName {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<str> for PluginName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PluginName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for PluginName {
+    type Err = InvalidPluginName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for PluginName {
+    type Error = InvalidPluginName;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl From<PluginName> for String {
+    fn from(name: PluginName) -> Self {
+        name.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_char_must_be_lowercase_letter() {
+        for name in ["foo", "f", "f1", "f_"] {
+            assert!(PluginName::is_valid(name), "{}", name);
+        }
+        for name in ["", "1foo", "_foo", "Foo", "-foo", "éfoo"] {
+            assert!(!PluginName::is_valid(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn charset() {
+        assert!(PluginName::is_valid("foo_bar_2"));
+        // 大写字母不再转换为小写，直接拒绝
+        for name in [
+            "fooBar", "FOO", "foo-bar", "foo.bar", "foo/bar", "../foo", "foo bar", "foo\0", "fooé",
+        ] {
+            assert!(!PluginName::is_valid(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn max_length() {
+        let name = "f".repeat(MAX_PLUGIN_NAME_LEN);
+        assert!(PluginName::is_valid(&name));
+        assert!(!PluginName::is_valid(&format!("{}f", name)));
+        // 按字节计算长度
+        assert!(!PluginName::is_valid(&format!(
+            "{}é",
+            "f".repeat(MAX_PLUGIN_NAME_LEN - 1)
+        )));
+    }
+
+    #[test]
+    fn parse_and_deserialize() {
+        assert_eq!(PluginName::new("foo").unwrap().as_str(), "foo");
+        assert_eq!(
+            "Foo".parse::<PluginName>(),
+            Err(InvalidPluginName("Foo".to_string()))
+        );
+        assert!(serde_json::from_str::<PluginName>("\"foo\"").is_ok());
+        assert!(serde_json::from_str::<PluginName>("\"Foo\"").is_err());
+    }
+}
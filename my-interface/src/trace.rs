@@ -0,0 +1,27 @@
+use std::{
+    any::Any,
+    sync::{Arc, RwLock},
+};
+
+/// resolver字段的追踪器，由主服务实现。插件静态链接了自己的依赖，无法使用主服务的tracing订阅者，
+/// 因此由主服务创建span，插件只传入对象与字段的名称
+pub trait FieldTracer: Send + Sync {
+    /// 开始字段的span，返回值释放时span结束
+    fn start_field(&self, object: &str, field: &str) -> Box<dyn Any + Send>;
+}
+
+static FIELD_TRACER: RwLock<Option<Arc<dyn FieldTracer>>> = RwLock::new(None);
+
+/// 设置当前二进制中的字段追踪器
+pub fn set_field_tracer(tracer: Arc<dyn FieldTracer>) {
+    *FIELD_TRACER.write().unwrap() = Some(tracer);
+}
+
+/// 开始字段的span，未设置追踪器时不做任何事情。由my-plugin-builder自动插入到resolver中
+pub fn trace_field(object: &str, field: &str) -> Option<Box<dyn Any + Send>> {
+    FIELD_TRACER
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|tracer| tracer.start_field(object, field))
+}
@@ -0,0 +1,52 @@
+//! wasm插件与主服务之间的调用约定，请求与响应均为json
+//!
+//! * 插件导出`plugin_alloc(len: u32) -> u32`，分配内存供主服务写入请求
+//! * 插件导出`plugin_handle(ptr: u32, len: u32) -> u64`，处理`WasmRequest`，返回值高32位为`WasmResponse`的地址，低32位为长度
+//! * 主服务在`my_host`模块中提供`data_request(ptr: u32, len: u32) -> u32`，执行`DataRequest`并返回响应的长度，
+//!   插件分配好内存后调用`data_response(ptr: u32)`取回响应
+use serde::{Deserialize, Serialize};
+
+use crate::{DataContextParams, PluginMetadata};
+
+/// 宿主函数所在的模块名
+pub const HOST_MODULE: &str = "my_host";
+pub const HOST_DATA_REQUEST: &str = "data_request";
+pub const HOST_DATA_RESPONSE: &str = "data_response";
+pub const EXPORT_ALLOC: &str = "plugin_alloc";
+pub const EXPORT_HANDLE: &str = "plugin_handle";
+
+/// 主服务发送给wasm插件的请求
+#[derive(Serialize, Deserialize, Debug)]
+pub enum WasmRequest {
+    Describe,
+    /// 执行graphql请求，request为单个或批量的graphql请求
+    Execute {
+        context: DataContextParams,
+        request: serde_json::Value,
+    },
+}
+
+/// wasm插件返回的响应
+#[derive(Serialize, Deserialize, Debug)]
+pub enum WasmResponse {
+    Describe {
+        id: String,
+        metadata: PluginMetadata,
+        schema_sdl: String,
+    },
+    Executed {
+        body: String,
+        is_ok: bool,
+    },
+    Error(String),
+}
+
+/// wasm插件通过宿主函数访问DataContext的请求
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DataRequest {
+    GetFoos,
+    GetFoo(i32),
+    GetBars,
+    GetBar(i32),
+    GetBarsByIds(Vec<i32>),
+}
@@ -0,0 +1,351 @@
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use my_interface::Identity;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use warp::{Filter, Rejection};
+
+use crate::{config::Config, Error};
+
+/// 调用管理接口（编译、处理器存储器的操作、持久化查询的注册等）需要的scope
+pub const ADMIN_SCOPE: &str = "admin";
+
+/// API Key的配置
+#[derive(Deserialize, Clone, Debug)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub subject: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// 允许调用的插件，不配置表示允许调用所有插件
+    pub plugins: Option<Vec<String>>,
+    /// 允许访问的租户，不配置表示允许访问所有租户
+    pub tenants: Option<Vec<String>>,
+}
+
+/// JWT的校验密钥配置
+#[derive(Deserialize, Clone, Debug)]
+pub struct JwtKeyConfig {
+    /// 对应JWT头部的kid，不配置时会尝试匹配所有同算法的密钥
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    /// HS系列算法为密钥原文，RS/ES/EdDSA系列算法为PEM格式的公钥
+    pub key: String,
+    /// 该密钥签发的token允许调用的插件，不配置表示允许调用所有插件
+    pub plugins: Option<Vec<String>>,
+    /// 该密钥签发的token允许访问的租户，不配置表示允许访问所有租户
+    pub tenants: Option<Vec<String>>,
+}
+
+/// 鉴权配置
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct AuthConfig {
+    /// 是否强制要求鉴权，为false时允许匿名调用
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    #[serde(default)]
+    pub jwt_keys: Vec<JwtKeyConfig>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    /// 以空格分隔的scope
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    plugins: Option<Vec<String>>,
+    tenants: Option<Vec<String>>,
+}
+
+/// 鉴权通过后的调用方
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub identity: Identity,
+    plugins: Option<HashSet<String>>,
+    tenants: Option<HashSet<String>>,
+}
+
+impl Caller {
+    /// 判断调用方是否允许调用该插件
+    pub fn can_call(&self, plugin: &str) -> bool {
+        match &self.plugins {
+            Some(plugins) => plugins.contains(plugin),
+            None => true,
+        }
+    }
+
+    /// 判断调用方是否允许访问该租户
+    pub fn can_use_tenant(&self, tenant: &str) -> bool {
+        match &self.tenants {
+            Some(tenants) => tenants.contains(tenant),
+            None => true,
+        }
+    }
+}
+
+/// 请求方：鉴权后的调用方以及远端地址
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub caller: Option<Caller>,
+    pub remote: Option<SocketAddr>,
+}
+
+impl Client {
+    /// 限流时区分请求方的标识，已鉴权时为subject，否则为IP
+    pub fn rate_limit_id(&self) -> String {
+        match (&self.caller, &self.remote) {
+            (Some(caller), _) => format!("subject:{}", caller.identity.subject),
+            (None, Some(remote)) => format!("ip:{}", remote.ip()),
+            (None, None) => "anonymous".to_string(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// 从配置的`auth_config`json文件中读取鉴权配置，未配置时不开启鉴权
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        match &config.auth_config {
+            Some(path) => {
+                let content = fs::read_to_string(path).map_err(|e| -> Error {
+                    log::error!("read auth config {} failed: {}", path.display(), e);
+                    Error::AuthConfigError
+                })?;
+                serde_json::from_str(&content).map_err(|e| -> Error {
+                    log::error!("parse auth config {} failed: {}", path.display(), e);
+                    Error::AuthConfigError
+                })
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// 根据请求头中的`Authorization: Bearer <jwt>`或`X-API-Key`进行鉴权，
+    /// 两者同时存在时无法确定调用方，直接拒绝；非Bearer的Authorization在未强制鉴权时视为匿名调用
+    pub fn authenticate(
+        &self,
+        authorization: Option<String>,
+        api_key: Option<String>,
+    ) -> Result<Option<Caller>, Error> {
+        let token = authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        match (token, api_key) {
+            (Some(_), Some(_)) => {
+                log::warn!("both bearer token and api key are provided");
+                Err(Error::Unauthorized)
+            }
+            (None, Some(key)) => self.authenticate_api_key(&key).map(Some),
+            (Some(token), None) => self.authenticate_jwt(token).map(Some),
+            (None, None) if self.required => Err(Error::Unauthorized),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// 是否配置了API Key或JWT密钥
+    pub fn has_credentials(&self) -> bool {
+        !self.api_keys.is_empty() || !self.jwt_keys.is_empty()
+    }
+
+    /// 管理接口的鉴权，要求调用方带有`admin` scope，未鉴权时返回401，没有scope时返回403。
+    /// 没有配置任何凭证且未强制鉴权时不做鉴权，只用于本地开发
+    pub fn authorize_admin(
+        &self,
+        authorization: Option<String>,
+        api_key: Option<String>,
+    ) -> Result<(), Error> {
+        if !self.has_credentials() && !self.required {
+            return Ok(());
+        }
+        match self.authenticate(authorization, api_key)? {
+            Some(caller) if caller.identity.has_scope(ADMIN_SCOPE) => Ok(()),
+            Some(caller) => {
+                log::warn!(
+                    "{} is not allowed to call admin api",
+                    caller.identity.subject
+                );
+                Err(Error::Forbidden)
+            }
+            None => Err(Error::Unauthorized),
+        }
+    }
+
+    // 比较key的sha256摘要而不是原文，并遍历所有配置的key，避免比较耗时泄露key的内容
+    fn authenticate_api_key(&self, key: &str) -> Result<Caller, Error> {
+        let digest = Sha256::digest(key.as_bytes());
+        self.api_keys
+            .iter()
+            .fold(None, |found, c| {
+                let matched = Sha256::digest(c.key.as_bytes()) == digest;
+                found.or(if matched { Some(c) } else { None })
+            })
+            .map(|c| Caller {
+                identity: Identity::new(c.subject.clone(), c.scopes.iter().cloned().collect()),
+                plugins: c.plugins.as_ref().map(|p| p.iter().cloned().collect()),
+                tenants: c.tenants.as_ref().map(|t| t.iter().cloned().collect()),
+            })
+            .ok_or(Error::Unauthorized)
+    }
+
+    fn authenticate_jwt(&self, token: &str) -> Result<Caller, Error> {
+        let header = decode_header(token).map_err(|_| Error::Unauthorized)?;
+        let candidates = self.jwt_keys.iter().filter(|k| {
+            k.algorithm == header.alg
+                && match (&k.kid, &header.kid) {
+                    (Some(kid), Some(header_kid)) => kid == header_kid,
+                    _ => true,
+                }
+        });
+        for key_config in candidates {
+            let key = match decoding_key(key_config) {
+                Some(key) => key,
+                None => continue,
+            };
+            let mut validation = Validation::new(key_config.algorithm);
+            if let Some(iss) = &self.jwt_issuer {
+                validation.set_issuer(&[iss]);
+            }
+            match &self.jwt_audience {
+                Some(aud) => validation.set_audience(&[aud]),
+                None => validation.validate_aud = false,
+            }
+            if let Ok(data) = decode::<Claims>(token, &key, &validation) {
+                let claims = data.claims;
+                let mut scopes: HashSet<String> = claims.scopes.into_iter().collect();
+                if let Some(scope) = claims.scope {
+                    scopes.extend(scope.split_whitespace().map(String::from));
+                }
+                let plugins = intersect(key_config.plugins.as_ref(), claims.plugins);
+                let tenants = intersect(key_config.tenants.as_ref(), claims.tenants);
+                return Ok(Caller {
+                    identity: Identity::new(claims.sub, scopes),
+                    plugins,
+                    tenants,
+                });
+            }
+        }
+        Err(Error::Unauthorized)
+    }
+}
+
+/// 可以热加载的鉴权配置，请求鉴权时使用最近一次成功加载的配置
+pub struct Authenticator {
+    config: RwLock<Arc<AuthConfig>>,
+    // 当前配置对应的`auth_config`文件路径
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl Authenticator {
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        Ok(Self {
+            config: RwLock::new(Arc::new(AuthConfig::from_config(config)?)),
+            path: RwLock::new(config.auth_config.clone()),
+        })
+    }
+
+    /// 配置文件或`auth_config`文件修改时重新加载，加载失败时保留原来的鉴权配置
+    pub fn reload(&self, config: &Config) -> Result<(), Error> {
+        let auth = AuthConfig::from_config(config)?;
+        if !auth.has_credentials() {
+            log::warn!("no api key or jwt key configured, admin api is not protected");
+        }
+        *self.config.write().unwrap() = Arc::new(auth);
+        *self.path.write().unwrap() = config.auth_config.clone();
+        Ok(())
+    }
+
+    /// 当前生效的鉴权配置
+    pub fn current(&self) -> Arc<AuthConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// 当前生效的`auth_config`文件路径
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.read().unwrap().clone()
+    }
+}
+
+fn decoding_key(config: &JwtKeyConfig) -> Option<DecodingKey> {
+    let key = config.key.as_bytes();
+    let result = match config.algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(DecodingKey::from_secret(key)),
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(key),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(key),
+        _ => DecodingKey::from_rsa_pem(key),
+    };
+    result
+        .map_err(|e| log::error!("invalid jwt key {:?}: {}", config.kid, e))
+        .ok()
+}
+
+// 密钥配置与token声明的插件或租户范围取交集
+fn intersect(
+    configured: Option<&Vec<String>>,
+    claimed: Option<Vec<String>>,
+) -> Option<HashSet<String>> {
+    match (configured, claimed) {
+        (Some(configured), Some(claimed)) => Some(
+            claimed
+                .into_iter()
+                .filter(|p| configured.contains(p))
+                .collect(),
+        ),
+        (Some(configured), None) => Some(configured.iter().cloned().collect()),
+        (None, Some(claimed)) => Some(claimed.into_iter().collect()),
+        (None, None) => None,
+    }
+}
+
+/// 注入鉴权后的调用方，鉴权失败时拒绝请求
+pub fn with_caller(
+    auth: Arc<Authenticator>,
+) -> impl Filter<Extract = (Option<Caller>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and_then(move |authorization, api_key| {
+            let auth = auth.clone();
+            async move {
+                auth.current()
+                    .authenticate(authorization, api_key)
+                    .map_err(warp::reject::custom)
+            }
+        })
+}
+
+/// 注入请求方
+pub fn with_client(
+    auth: Arc<Authenticator>,
+) -> impl Filter<Extract = (Client,), Error = Rejection> + Clone {
+    with_caller(auth)
+        .and(warp::addr::remote())
+        .map(|caller, remote| Client { caller, remote })
+}
+
+/// 管理接口的鉴权，调用方没有`admin` scope时拒绝请求
+pub fn with_admin(
+    auth: Arc<Authenticator>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and_then(move |authorization, api_key| {
+            let auth = auth.clone();
+            async move {
+                auth.current()
+                    .authorize_admin(authorization, api_key)
+                    .map_err(warp::reject::custom)
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,119 @@
+//! 插件的金丝雀版本：稳定版本之外同时加载的其他版本，按权重、租户或请求头分配请求，
+//! 也可以按比例接收稳定版本请求的副本作为影子流量
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{health::HealthSnapshot, SharedHandler};
+
+/// 表示稳定版本的版本名，租户固定使用稳定版本时配置，不能作为金丝雀版本的版本名
+pub const STABLE: &str = "stable";
+/// 权重的总和，金丝雀版本的权重即为分配到的请求百分比，剩余的请求使用稳定版本
+pub const TOTAL_WEIGHT: u32 = 100;
+
+/// 版本名只能包含字母、数字以及`.`、`-`、`_`、`+`，长度不超过64
+pub fn is_valid_version(version: &str) -> bool {
+    !version.is_empty()
+        && version.len() <= 64
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+'))
+}
+
+/// 金丝雀版本的处理器与权重
+#[derive(Clone)]
+pub struct CanaryVersion {
+    pub handler: SharedHandler,
+    pub weight: u32,
+    /// 稳定版本的请求中复制到该版本作为影子流量的百分比
+    pub shadow: u32,
+    /// 加载时编译产物的sha256，提升时确认编译产物没有被重新编译
+    pub checksum: Option<String>,
+}
+
+/// 一个插件的所有金丝雀版本，按版本名排序
+#[derive(Clone, Default)]
+pub struct Canaries {
+    versions: BTreeMap<String, CanaryVersion>,
+    // 已分配的请求数，用于按权重分配请求
+    sequence: Arc<AtomicU64>,
+    // 稳定版本的请求数，用于按比例采样影子流量
+    shadow_sequence: Arc<AtomicU64>,
+}
+
+impl Canaries {
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+    pub fn get(&self, version: &str) -> Option<&CanaryVersion> {
+        self.versions.get(version)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &CanaryVersion)> {
+        self.versions.iter()
+    }
+    pub fn get_mut(&mut self, version: &str) -> Option<&mut CanaryVersion> {
+        self.versions.get_mut(version)
+    }
+    pub fn insert(&mut self, version: String, canary: CanaryVersion) {
+        self.versions.insert(version, canary);
+    }
+    pub fn remove(&mut self, version: &str) -> Option<CanaryVersion> {
+        self.versions.remove(version)
+    }
+    /// 除指定版本外其他金丝雀版本的权重之和
+    pub fn weight_except(&self, version: &str) -> u32 {
+        self.versions
+            .iter()
+            .filter(|(v, _)| v.as_str() != version)
+            .map(|(_, canary)| canary.weight)
+            .sum()
+    }
+    /// 按权重选择请求使用的金丝雀版本，None为稳定版本。每100个请求中各版本分配到的数量与权重一致
+    pub fn pick(&self) -> Option<&str> {
+        let bucket = bucket_of(&self.sequence);
+        let mut upper = 0;
+        for (version, canary) in self.versions.iter() {
+            upper += canary.weight;
+            if bucket < upper {
+                return Some(version);
+            }
+        }
+        None
+    }
+    /// 稳定版本的请求需要复制到的影子版本，每个版本按各自的比例采样
+    pub fn shadows(&self) -> Vec<SharedHandler> {
+        if self.versions.values().all(|canary| canary.shadow == 0) {
+            return Vec::new();
+        }
+        let bucket = bucket_of(&self.shadow_sequence);
+        self.versions
+            .values()
+            .filter(|canary| bucket < canary.shadow)
+            .map(|canary| canary.handler.clone())
+            .collect()
+    }
+}
+
+// 请求序号乘以与100互质的数打散连续的请求，每100个请求的桶号各不相同
+fn bucket_of(sequence: &AtomicU64) -> u32 {
+    let sequence = sequence.fetch_add(1, Ordering::Relaxed);
+    (sequence % TOTAL_WEIGHT as u64 * 37 % TOTAL_WEIGHT as u64) as u32
+}
+
+/// 金丝雀版本的状态，用于`/plugins/:name/health`接口展示
+#[derive(Debug, Serialize)]
+pub struct CanaryStatus {
+    pub version: String,
+    pub weight: u32,
+    pub shadow: u32,
+    #[serde(flatten)]
+    pub health: HealthSnapshot,
+}
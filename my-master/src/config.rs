@@ -0,0 +1,577 @@
+use my_interface::{PluginName, QueryLimits, DEFAULT_DATASET};
+use my_plugin_builder::BuildOptions;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{canary, ratelimit::RateLimit, wasm::WasmLimits, worker::WorkerLimits, Error};
+
+// 未通过`CONFIG_FILE`指定时读取的配置文件，不存在时使用默认配置
+const DEFAULT_CONFIG_FILE: &str = "./master.toml";
+
+/// 主服务的配置，从TOML文件读取，环境变量优先
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    /// 鉴权配置文件，不配置时不开启鉴权
+    pub auth_config: Option<PathBuf>,
+    /// 动态链接包与wasm模块所在的目录
+    pub libs_dir: PathBuf,
+    pub build: BuildConfig,
+    pub eviction: EvictionConfig,
+    /// 启动时加载的插件，动态链接包不存在时先编译，全部加载后服务才就绪
+    pub preload: Vec<String>,
+    /// 预加载失败时的处理方式
+    pub preload_failure: PreloadFailure,
+    /// 常驻的插件，启动时与`preload`一起加载，并且不会被定时清理卸载
+    pub pinned: Vec<String>,
+    /// 插件连续panic多少次后隔离，0表示不隔离
+    pub panic_threshold: u32,
+    /// 加载插件时是否要求存在sha256校验文件，校验文件与插件不一致时始终拒绝加载
+    pub require_checksum: bool,
+    /// 受信任的ed25519公钥（PKCS#8 PEM），配置后只加载由其签名的插件
+    pub trusted_keys: Vec<PathBuf>,
+    /// 检查配置文件是否修改的间隔（秒），0表示不热加载
+    pub reload_interval_secs: u64,
+    /// 按插件区分的配置，`*`为默认配置
+    pub plugins: HashMap<String, PluginConfig>,
+    /// `/api/:name/graphql`未通过请求头指定租户时使用的租户
+    pub default_tenant: String,
+    /// 租户，key为租户的名称，规则与插件名称相同
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            auth_config: None,
+            libs_dir: PathBuf::from("./libs"),
+            build: BuildConfig::default(),
+            eviction: EvictionConfig::default(),
+            preload: Vec::new(),
+            preload_failure: PreloadFailure::Warn,
+            pinned: Vec::new(),
+            panic_threshold: crate::health::DEFAULT_PANIC_THRESHOLD,
+            require_checksum: false,
+            trusted_keys: Vec::new(),
+            reload_interval_secs: 5,
+            plugins: HashMap::new(),
+            default_tenant: DEFAULT_DATASET.to_string(),
+            tenants: default_tenants(),
+        }
+    }
+}
+
+// 未配置租户时为`master`、`dev`，以及兼容原有flag路径的`false`、`true`
+fn default_tenants() -> BTreeMap<String, TenantConfig> {
+    [
+        ("master", "master"),
+        ("dev", "dev"),
+        ("false", "master"),
+        ("true", "dev"),
+    ]
+    .iter()
+    .map(|(tenant, dataset)| (tenant.to_string(), TenantConfig::with_dataset(dataset)))
+    .collect()
+}
+
+/// 租户的配置
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantConfig {
+    /// 租户使用的数据集，不配置时与租户同名
+    pub dataset: Option<String>,
+    /// 租户开启的插件，不配置时开启所有插件
+    pub plugins: Option<Vec<String>>,
+    /// 租户固定使用的插件版本，key为插件名称，`stable`表示稳定版本，不配置的插件按金丝雀版本的权重分配
+    pub versions: BTreeMap<String, String>,
+}
+
+impl TenantConfig {
+    pub fn with_dataset(dataset: &str) -> Self {
+        Self {
+            dataset: Some(dataset.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// 配置后以https提供服务
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: ([0, 0, 0, 0], 8080).into(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM格式的证书
+    pub cert: PathBuf,
+    /// PEM格式的私钥
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildConfig {
+    /// 编译插件的临时项目所在的目录
+    pub workspace: PathBuf,
+    /// my-interface的路径，临时项目以路径依赖引用
+    pub interface_path: PathBuf,
+    /// 插件允许声明的额外依赖
+    pub allowed_dependencies: Vec<String>,
+    /// `cargo vendor`生成的目录，配置后以其替换crates.io
+    pub vendor_dir: Option<PathBuf>,
+    /// 主服务的Cargo.lock，配置后插件的依赖版本与主服务一致
+    pub lockfile: Option<PathBuf>,
+    /// 以`--offline`编译，不访问网络
+    pub offline: bool,
+    /// 以`--frozen`编译，所有依赖都必须已被Cargo.lock锁定
+    pub frozen: bool,
+    /// 签名编译产物的ed25519私钥（PKCS#8 PEM）
+    pub signing_key: Option<PathBuf>,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        let options = BuildOptions::default();
+        Self {
+            workspace: options.workspace,
+            interface_path: options.interface_path,
+            allowed_dependencies: Vec::new(),
+            vendor_dir: options.vendor_dir,
+            lockfile: options.lockfile,
+            offline: options.offline,
+            frozen: options.frozen,
+            signing_key: options.signing_key,
+        }
+    }
+}
+
+/// 预加载失败时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreloadFailure {
+    /// 启动失败
+    Fail,
+    /// 记录警告后继续启动，插件在使用时再加载
+    Warn,
+}
+
+/// 处理器的卸载策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// 每次清理时卸载所有处理器
+    All,
+    /// 卸载空闲超过`idle_secs`的处理器
+    Idle,
+    /// 不卸载
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvictionConfig {
+    pub policy: EvictionPolicy,
+    /// 清理的间隔（秒）
+    pub interval_secs: u64,
+    /// `idle`策略下处理器的最长空闲时间（秒）
+    pub idle_secs: u64,
+}
+
+impl Default for EvictionConfig {
+    fn default() -> Self {
+        Self {
+            policy: EvictionPolicy::All,
+            interval_secs: 10,
+            idle_secs: 300,
+        }
+    }
+}
+
+impl EvictionConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+}
+
+/// 单个插件的配置，未配置的项使用`*`的配置
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginConfig {
+    /// 执行的超时时间（秒）
+    pub timeout_secs: Option<f64>,
+    pub rate_limit: Option<RateLimit>,
+    /// 查询限制，配置的项覆盖插件在元数据中声明的项
+    pub limits: Option<QueryLimits>,
+    /// 是否开启持久化查询白名单模式
+    pub persisted_allow_list: Option<bool>,
+    /// 是否在工作进程中执行
+    pub worker: Option<bool>,
+    pub worker_limits: Option<WorkerLimits>,
+    /// 是否编译为wasm模块执行
+    pub wasm: Option<bool>,
+    pub wasm_limits: Option<WasmLimits>,
+}
+
+impl Config {
+    /// 读取`CONFIG_FILE`指定的配置文件（默认`./master.toml`，不存在时使用默认配置），再以环境变量覆盖
+    pub fn load() -> Result<Self, Error> {
+        let mut config = match Self::file_path() {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 配置文件的路径，未指定且默认文件不存在时为None
+    pub fn file_path() -> Option<PathBuf> {
+        match std::env::var("CONFIG_FILE") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| p.exists()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path).map_err(|e| -> Error {
+            log::error!("read config {} failed: {}", path.display(), e);
+            Error::ConfigError
+        })?;
+        toml::from_str(&content).map_err(|e| -> Error {
+            log::error!("parse config {} failed: {}", path.display(), e);
+            Error::ConfigError
+        })
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for plugin in self.preload.iter().chain(self.pinned.iter()) {
+            if !PluginName::is_valid(plugin) {
+                log::error!("invalid plugin name in preload or pinned: {:?}", plugin);
+                return Err(Error::ConfigError);
+            }
+        }
+        for (tenant, settings) in self.tenants.iter() {
+            // 租户的名称会出现在路径与指标的标签中，与插件名称使用相同的规则
+            if !PluginName::is_valid(tenant) {
+                log::error!("invalid tenant name: {:?}", tenant);
+                return Err(Error::ConfigError);
+            }
+            let mut plugins = settings.plugins.iter().flatten();
+            if let Some(plugin) = plugins.find(|p| !PluginName::is_valid(p)) {
+                log::error!("invalid plugin name of tenant {}: {:?}", tenant, plugin);
+                return Err(Error::ConfigError);
+            }
+            for (plugin, version) in settings.versions.iter() {
+                if !PluginName::is_valid(plugin) || !canary::is_valid_version(version) {
+                    log::error!(
+                        "invalid version of tenant {}: {:?} = {:?}",
+                        tenant,
+                        plugin,
+                        version
+                    );
+                    return Err(Error::ConfigError);
+                }
+            }
+        }
+        if !self.tenants.contains_key(&self.default_tenant) {
+            log::error!("default tenant {:?} is not configured", self.default_tenant);
+            return Err(Error::ConfigError);
+        }
+        for (plugin, settings) in self.plugins.iter() {
+            if let Some(limit) = settings.rate_limit {
+                if !limit.is_valid() {
+                    log::error!("invalid rate limit of plugin {}: {:?}", plugin, limit);
+                    return Err(Error::ConfigError);
+                }
+            }
+            if let Some(timeout) = settings.timeout_secs {
+                if timeout <= 0.0 {
+                    log::error!("invalid timeout of plugin {}: {}", plugin, timeout);
+                    return Err(Error::ConfigError);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 兼容原有的环境变量，环境变量的配置覆盖配置文件
+    fn apply_env(&mut self) {
+        if let Some(addr) = env_value("SERVER_ADDR", |v| v.parse().ok()) {
+            self.server.addr = addr;
+        }
+        if let Ok(path) = std::env::var("AUTH_CONFIG") {
+            self.auth_config = Some(PathBuf::from(path));
+        }
+        if let Ok(path) = std::env::var("LIBS_DIR") {
+            self.libs_dir = PathBuf::from(path);
+        }
+        if let Ok(path) = std::env::var("BUILD_WORKSPACE") {
+            self.build.workspace = PathBuf::from(path);
+        }
+        if let Ok(path) = std::env::var("BUILD_VENDOR_DIR") {
+            self.build.vendor_dir = Some(PathBuf::from(path));
+        }
+        if let Ok(path) = std::env::var("BUILD_LOCKFILE") {
+            self.build.lockfile = Some(PathBuf::from(path));
+        }
+        if let Some(offline) = env_value("BUILD_OFFLINE", |v| v.parse().ok()) {
+            self.build.offline = offline;
+        }
+        if let Some(frozen) = env_value("BUILD_FROZEN", |v| v.parse().ok()) {
+            self.build.frozen = frozen;
+        }
+        if let Some(threshold) = env_value("PLUGIN_PANIC_THRESHOLD", |v| v.parse().ok()) {
+            self.panic_threshold = threshold;
+        }
+        if let Some(require) = env_value("REQUIRE_CHECKSUM", |v| v.parse().ok()) {
+            self.require_checksum = require;
+        }
+        if let Ok(path) = std::env::var("BUILD_SIGNING_KEY") {
+            self.build.signing_key = Some(PathBuf::from(path));
+        }
+        if std::env::var("TRUSTED_KEYS").is_ok() {
+            self.trusted_keys = plugin_list_from_env("TRUSTED_KEYS")
+                .into_iter()
+                .map(PathBuf::from)
+                .collect();
+        }
+        if std::env::var("TENANTS").is_ok() {
+            self.tenants = tenants_from_env("TENANTS");
+        }
+        if let Ok(tenant) = std::env::var("DEFAULT_TENANT") {
+            self.default_tenant = tenant;
+        }
+        if std::env::var("PRELOAD_PLUGINS").is_ok() {
+            self.preload = plugin_list_from_env("PRELOAD_PLUGINS");
+        }
+        if std::env::var("PINNED_PLUGINS").is_ok() {
+            self.pinned = plugin_list_from_env("PINNED_PLUGINS");
+        }
+        if let Some(failure) = env_value("PRELOAD_FAILURE", |v| match v {
+            "fail" => Some(PreloadFailure::Fail),
+            "warn" => Some(PreloadFailure::Warn),
+            _ => None,
+        }) {
+            self.preload_failure = failure;
+        }
+        for plugin in plugin_list_from_env("PERSISTED_QUERY_ALLOW_LIST") {
+            self.plugin_mut(&plugin).persisted_allow_list = Some(true);
+        }
+        for plugin in plugin_list_from_env("PLUGIN_WORKERS") {
+            self.plugin_mut(&plugin).worker = Some(true);
+        }
+        for plugin in plugin_list_from_env("WASM_PLUGINS") {
+            self.plugin_mut(&plugin).wasm = Some(true);
+        }
+        let timeout = |v: &str| v.parse::<f64>().ok().filter(|secs| *secs > 0.0);
+        for (plugin, value) in plugin_settings_from_env("PLUGIN_TIMEOUTS", timeout) {
+            self.plugin_mut(&plugin).timeout_secs = Some(value);
+        }
+        for (plugin, value) in plugin_settings_from_env("RATE_LIMITS", RateLimit::parse) {
+            self.plugin_mut(&plugin).rate_limit = Some(value);
+        }
+        for (plugin, value) in plugin_settings_from_env("WORKER_LIMITS", WorkerLimits::parse) {
+            self.plugin_mut(&plugin).worker_limits = Some(value);
+        }
+        for (plugin, value) in plugin_settings_from_env("WASM_LIMITS", WasmLimits::parse) {
+            self.plugin_mut(&plugin).wasm_limits = Some(value);
+        }
+    }
+
+    fn plugin_mut(&mut self, plugin: &str) -> &mut PluginConfig {
+        self.plugins.entry(plugin.to_string()).or_default()
+    }
+
+    /// 按插件区分的配置项，返回`*`的默认值以及各插件的值
+    pub fn plugin_values<T>(
+        &self,
+        get: impl Fn(&PluginConfig) -> Option<T>,
+    ) -> (Option<T>, HashMap<String, T>) {
+        let mut default_value = None;
+        let mut plugin_values = HashMap::new();
+        for (plugin, settings) in self.plugins.iter() {
+            match (plugin.as_str(), get(settings)) {
+                ("*", value) => default_value = value,
+                (_, Some(value)) => {
+                    plugin_values.insert(plugin.clone(), value);
+                }
+                (_, None) => {}
+            }
+        }
+        (default_value, plugin_values)
+    }
+
+    /// 开启了某个开关的插件，`*`表示所有插件
+    pub fn plugin_list(&self, get: impl Fn(&PluginConfig) -> Option<bool>) -> HashSet<String> {
+        self.plugins
+            .iter()
+            .filter(|(_, settings)| get(settings) == Some(true))
+            .map(|(plugin, _)| plugin.clone())
+            .collect()
+    }
+
+    /// 启动时需要加载的插件，包括预加载与常驻的插件
+    pub fn startup_plugins(&self) -> Vec<PluginName> {
+        let mut plugins: Vec<PluginName> = Vec::new();
+        for plugin in self.preload.iter().chain(self.pinned.iter()) {
+            // 名称已在加载配置时校验
+            if let Ok(name) = PluginName::new(plugin.as_str()) {
+                if !plugins.contains(&name) {
+                    plugins.push(name);
+                }
+            }
+        }
+        plugins
+    }
+
+    /// 配置中出现的插件：按插件区分的配置、预加载与常驻的插件以及租户开启的插件
+    pub fn configured_plugins(&self) -> HashSet<String> {
+        let tenant_plugins = self.tenants.values().flat_map(|tenant| {
+            tenant
+                .plugins
+                .iter()
+                .flatten()
+                .chain(tenant.versions.keys())
+        });
+        self.plugins
+            .keys()
+            .filter(|plugin| plugin.as_str() != "*")
+            .chain(self.preload.iter())
+            .chain(self.pinned.iter())
+            .chain(tenant_plugins)
+            .cloned()
+            .collect()
+    }
+
+    /// 编译插件使用的目录
+    pub fn build_options(&self) -> BuildOptions {
+        BuildOptions {
+            workspace: self.build.workspace.clone(),
+            libs_dir: self.libs_dir.clone(),
+            interface_path: self.build.interface_path.clone(),
+            allowed_dependencies: self.build.allowed_dependencies.iter().cloned().collect(),
+            vendor_dir: self.build.vendor_dir.clone(),
+            lockfile: self.build.lockfile.clone(),
+            offline: self.build.offline,
+            frozen: self.build.frozen,
+            signing_key: self.build.signing_key.clone(),
+            version: None,
+        }
+    }
+
+    /// 与新配置相比，需要重启才能生效的配置项
+    pub fn restart_required_changes(&self, other: &Config) -> Vec<&'static str> {
+        let process_settings = |config: &Config| {
+            let mut plugins: Vec<_> = config
+                .plugins
+                .iter()
+                .map(|(plugin, s)| {
+                    (
+                        plugin.clone(),
+                        s.worker,
+                        s.worker_limits,
+                        s.wasm,
+                        s.wasm_limits,
+                    )
+                })
+                .collect();
+            plugins.sort_by(|a, b| a.0.cmp(&b.0));
+            plugins
+        };
+        let mut changes = Vec::new();
+        if self.server != other.server {
+            changes.push("server");
+        }
+        if self.libs_dir != other.libs_dir {
+            changes.push("libs_dir");
+        }
+        if self.build != other.build {
+            changes.push("build");
+        }
+        if self.panic_threshold != other.panic_threshold {
+            changes.push("panic_threshold");
+        }
+        if self.trusted_keys != other.trusted_keys {
+            changes.push("trusted_keys");
+        }
+        if self.reload_interval_secs != other.reload_interval_secs {
+            changes.push("reload_interval_secs");
+        }
+        if process_settings(self) != process_settings(other) {
+            changes.push("plugins.worker/wasm");
+        }
+        changes
+    }
+}
+
+fn env_value<T>(var: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let value = std::env::var(var).ok()?;
+    let parsed = parse(value.trim());
+    if parsed.is_none() {
+        log::warn!("invalid {}: {}", var, value);
+    }
+    parsed
+}
+
+// 解析`插件=值`格式的环境变量，逗号分隔，`*`为默认配置
+fn plugin_settings_from_env<T>(var: &str, parse: impl Fn(&str) -> Option<T>) -> Vec<(String, T)> {
+    let mut values = Vec::new();
+    if let Ok(value) = std::env::var(var) {
+        for item in value.split(',').filter(|s| !s.trim().is_empty()) {
+            let parsed = item.split_once('=').and_then(|(plugin, value)| {
+                Some((plugin.trim().to_string(), parse(value.trim())?))
+            });
+            match parsed {
+                Some(value) => values.push(value),
+                None => log::warn!("invalid {} item: {}", var, item),
+            }
+        }
+    }
+    values
+}
+
+// 解析插件列表的环境变量，逗号分隔，`*`表示所有插件
+fn plugin_list_from_env(var: &str) -> Vec<String> {
+    std::env::var(var)
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 解析租户列表的环境变量，逗号分隔，`租户=数据集`指定数据集，只写租户时数据集与租户同名
+fn tenants_from_env(var: &str) -> BTreeMap<String, TenantConfig> {
+    plugin_list_from_env(var)
+        .into_iter()
+        .map(|item| match item.split_once('=') {
+            Some((tenant, dataset)) => (
+                tenant.trim().to_string(),
+                TenantConfig::with_dataset(dataset.trim()),
+            ),
+            None => (item, TenantConfig::default()),
+        })
+        .collect()
+}
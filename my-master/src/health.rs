@@ -0,0 +1,192 @@
+use futures::FutureExt;
+use my_interface::panic_message;
+use serde::Serialize;
+use std::{
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::canary::CanaryStatus;
+
+/// 未配置时连续panic多少次后隔离插件
+pub const DEFAULT_PANIC_THRESHOLD: u32 = 3;
+
+/// 插件的健康状态，插件卸载、重新加载后依然保留
+#[derive(Default)]
+pub struct PluginHealth {
+    panic_threshold: u32,
+    panic_count: AtomicU64,
+    consecutive_panics: AtomicU32,
+    quarantined: AtomicBool,
+    last_panic: Mutex<Option<String>>,
+    last_error: Mutex<Option<String>>,
+    generation: AtomicU64,
+    in_flight: AtomicU64,
+    last_used: AtomicU64,
+}
+
+/// 插件健康状态的快照
+#[derive(Debug, Serialize)]
+pub struct HealthSnapshot {
+    pub panic_count: u64,
+    pub consecutive_panics: u32,
+    pub quarantined: bool,
+    pub last_panic: Option<String>,
+    /// 最近一次加载失败或panic的信息
+    pub last_error: Option<String>,
+    /// 插件被加载的次数，每次加载或重新加载加1
+    pub generation: u64,
+    /// 正在执行的请求数
+    pub in_flight: u64,
+    /// 最近一次执行请求的时间，unix时间戳（秒），从未执行过时为None
+    pub last_used: Option<u64>,
+}
+
+/// 插件的加载状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadState {
+    Loaded,
+    Unloaded,
+    Quarantined,
+}
+
+/// 插件的状态，用于`/plugins/:name/health`接口展示
+#[derive(Debug, Serialize)]
+pub struct PluginStatus {
+    pub name: String,
+    pub state: LoadState,
+    /// 已加载的插件版本
+    pub version: Option<String>,
+    pub target: &'static str,
+    /// 是否为常驻插件
+    pub pinned: bool,
+    #[serde(flatten)]
+    pub health: HealthSnapshot,
+    /// 同时加载的金丝雀版本
+    pub canaries: Vec<CanaryStatus>,
+}
+
+/// 正在执行的请求，释放时减少插件的执行中请求数
+pub struct InFlightGuard<'a> {
+    health: &'a PluginHealth,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.health.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl PluginHealth {
+    pub fn new(panic_threshold: u32) -> Self {
+        Self {
+            panic_threshold,
+            ..Default::default()
+        }
+    }
+
+    pub fn record_success(&self) {
+        self.consecutive_panics.store(0, Ordering::Relaxed);
+    }
+
+    /// 记录一次加载，返回加载的代数
+    pub fn record_load(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// 插件被加载的次数
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    pub fn record_load_error(&self, message: String) {
+        *self.last_error.lock().unwrap() = Some(message);
+    }
+
+    /// 开始执行请求，记录执行中的请求数与最近使用时间
+    pub fn start_request(&self) -> InFlightGuard<'_> {
+        self.last_used.store(unix_now(), Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { health: self }
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// 最近一次执行请求的时间，unix时间戳（秒），从未执行过时为0
+    pub fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
+
+    /// 记录一次panic，连续panic次数达到阈值时隔离插件并返回true
+    pub fn record_panic(&self, message: String) -> bool {
+        self.panic_count.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(message.clone());
+        *self.last_panic.lock().unwrap() = Some(message);
+        let consecutive = self.consecutive_panics.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.panic_threshold > 0 && consecutive >= self.panic_threshold {
+            !self.quarantined.swap(true, Ordering::Relaxed)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    /// 清空panic与错误的记录并解除隔离，执行中的请求数与最近使用时间不变
+    pub fn reset(&self) {
+        self.panic_count.store(0, Ordering::Relaxed);
+        *self.last_panic.lock().unwrap() = None;
+        *self.last_error.lock().unwrap() = None;
+        self.release();
+    }
+
+    /// 解除隔离
+    pub fn release(&self) {
+        self.consecutive_panics.store(0, Ordering::Relaxed);
+        self.quarantined.store(false, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HealthSnapshot {
+        HealthSnapshot {
+            panic_count: self.panic_count.load(Ordering::Relaxed),
+            consecutive_panics: self.consecutive_panics.load(Ordering::Relaxed),
+            quarantined: self.is_quarantined(),
+            last_panic: self.last_panic.lock().unwrap().clone(),
+            last_error: self.last_error.lock().unwrap().clone(),
+            generation: self.generation.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            last_used: Some(self.last_used.load(Ordering::Relaxed)).filter(|t| *t > 0),
+        }
+    }
+}
+
+/// 当前的unix时间戳（秒）
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 调用插件的同步函数，捕获其中的panic
+pub fn catch_panic_sync<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(panic_message)
+}
+
+/// 执行插件的异步函数，捕获其中的panic
+pub async fn catch_panic<F: Future>(fut: F) -> Result<F::Output, String> {
+    AssertUnwindSafe(fut)
+        .catch_unwind()
+        .await
+        .map_err(panic_message)
+}
@@ -1,10 +1,50 @@
+use canary::{Canaries, CanaryStatus, CanaryVersion, STABLE, TOTAL_WEIGHT};
+use config::{Config, EvictionConfig, EvictionPolicy};
+use health::{catch_panic_sync, unix_now, LoadState, PluginHealth, PluginStatus};
+use libloading::Library;
 use log::error;
-use my_interface::GraphqlRequestHandler;
-use my_plugin_builder::errors::BuildError;
-use std::{collections::HashMap, convert::Infallible};
-use warp::{http::StatusCode, Rejection};
+use metrics::{LifecycleEvent, Metrics};
+use my_interface::{GraphqlRequestHandler, PluginName, INTERFACE_VERSION};
+use my_plugin_builder::{
+    errors::BuildError, install::sha256_file, signing::TrustedKeys, BuildOptions, PluginTarget,
+};
+use persisted::sha256_hex;
+use shadow::ShadowStore;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fs, io,
+    ops::Deref,
+    os::unix::fs::PermissionsExt as _,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::Instant,
+};
+use tempfile::TempDir;
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use warp::{
+    http::{self, StatusCode},
+    Rejection, Reply,
+};
+use wasm::WasmSettings;
+use worker::WorkerSettings;
 
+pub mod auth;
+pub mod canary;
+pub mod config;
+pub mod health;
+pub mod limits;
+pub mod metrics;
+pub mod persisted;
+pub mod ratelimit;
 pub mod route;
+pub mod schema_diff;
+pub mod shadow;
+pub mod telemetry;
+pub mod tenant;
+pub mod timeout;
+pub mod wasm;
+pub mod worker;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -12,25 +52,69 @@ pub enum Error {
     HandlerNotFound,
     #[error("demo not support")]
     DemoNotSupport,
+    #[error("invalid plugin name")]
+    InvalidPluginName,
     #[error("load lib error")]
     LoadLibError,
     #[error("load plugin error")]
     LoadPluginError,
     #[error("no such plugin error")]
     NoSuchPluginError,
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("auth config error")]
+    AuthConfigError,
+    #[error("config error")]
+    ConfigError,
+    #[error("plugin checksum error")]
+    ChecksumError,
+    #[error("plugin signature error")]
+    SignatureError,
+    #[error("schema error")]
+    SchemaError,
+    #[error("plugin quarantined")]
+    PluginQuarantined,
+    #[error("no such tenant")]
+    NoSuchTenant,
+    #[error("plugin not enabled for tenant")]
+    PluginNotEnabled,
+    #[error("no such version")]
+    NoSuchVersion,
+    #[error("invalid version")]
+    InvalidVersion,
+    #[error("version already loaded")]
+    VersionConflict,
+    #[error("invalid canary weight")]
+    InvalidCanaryWeight,
+    #[error("invalid shadow sample rate")]
+    InvalidShadowRate,
+    #[error("plugin worker error")]
+    WorkerError,
+    #[error("wasm plugin error")]
+    WasmError,
+    #[error("plugin interface version mismatch")]
+    InterfaceVersionMismatch,
+    #[error("too many requests, retry after {0} seconds")]
+    RateLimited(u64),
     #[error(transparent)]
     BuildError(#[from] BuildError),
 }
 
 impl warp::reject::Reject for Error {}
 
-pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl warp::Reply, Infallible> {
-    let (code, message) = if err.is_not_found() {
+/// 拒绝对应的响应状态码与信息
+pub fn rejection_status(err: &Rejection) -> (StatusCode, String) {
+    if err.is_not_found() {
         (StatusCode::NOT_FOUND, "Not Found".to_string())
     } else if let Some(err) = err.find::<Error>() {
         match err {
             Error::HandlerNotFound => (StatusCode::NOT_FOUND, "handler not found".to_string()),
             Error::DemoNotSupport => (StatusCode::BAD_REQUEST, "demo not support".to_string()),
+            Error::InvalidPluginName => {
+                (StatusCode::BAD_REQUEST, "invalid plugin name".to_string())
+            }
             Error::LoadPluginError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "load plugin error".to_string(),
@@ -43,6 +127,60 @@ pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl warp::
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "no such plugin error".to_string(),
             ),
+            Error::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized".to_string()),
+            Error::Forbidden => (StatusCode::FORBIDDEN, "forbidden".to_string()),
+            Error::AuthConfigError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "auth config error".to_string(),
+            ),
+            Error::ConfigError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "config error".to_string(),
+            ),
+            Error::ChecksumError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "plugin checksum error".to_string(),
+            ),
+            Error::SignatureError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "plugin signature error".to_string(),
+            ),
+            Error::SchemaError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "schema error".to_string(),
+            ),
+            Error::PluginQuarantined => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "plugin quarantined".to_string(),
+            ),
+            Error::NoSuchTenant => (StatusCode::NOT_FOUND, "no such tenant".to_string()),
+            Error::PluginNotEnabled => (
+                StatusCode::NOT_FOUND,
+                "plugin not enabled for tenant".to_string(),
+            ),
+            Error::NoSuchVersion => (StatusCode::NOT_FOUND, "no such version".to_string()),
+            Error::InvalidVersion => (StatusCode::BAD_REQUEST, "invalid version".to_string()),
+            Error::VersionConflict => (StatusCode::CONFLICT, "version already loaded".to_string()),
+            Error::InvalidCanaryWeight => {
+                (StatusCode::BAD_REQUEST, "invalid canary weight".to_string())
+            }
+            Error::InvalidShadowRate => (
+                StatusCode::BAD_REQUEST,
+                "invalid shadow sample rate".to_string(),
+            ),
+            Error::WorkerError => (StatusCode::BAD_GATEWAY, "plugin worker error".to_string()),
+            Error::WasmError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "wasm plugin error".to_string(),
+            ),
+            Error::InterfaceVersionMismatch => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "plugin interface version mismatch".to_string(),
+            ),
+            Error::RateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many requests".to_string(),
+            ),
             Error::BuildError(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e)),
         }
     } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
@@ -56,27 +194,457 @@ pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl warp::
             StatusCode::INTERNAL_SERVER_ERROR,
             "Internal Server Error".to_string(),
         )
-    };
+    }
+}
+
+pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl warp::Reply, Infallible> {
+    let (code, message) = rejection_status(&err);
+    let mut response = warp::reply::with_status(message, code).into_response();
+    if let Some(Error::RateLimited(retry_after)) = err.find::<Error>() {
+        response
+            .headers_mut()
+            .insert(http::header::RETRY_AFTER, (*retry_after).into());
+    }
+    Ok(response)
+}
 
-    Ok(warp::reply::with_status(message, code))
+/// 构造graphql的错误响应
+pub fn graphql_error_response(
+    status: StatusCode,
+    code: &str,
+    message: String,
+) -> http::Response<Vec<u8>> {
+    let body = serde_json::json!({
+        "errors": [{
+            "message": message,
+            "extensions": { "code": code }
+        }]
+    });
+    http::Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.to_string().into_bytes())
+        .expect("response is valid")
 }
 
-/// 处理器的存储容器
+/// 已加载的处理器，持有其所在的动态链接包。字段按声明顺序释放，保证处理器先于动态链接包释放
+pub struct PluginHandler {
+    handler: Box<dyn GraphqlRequestHandler + Send + Sync>,
+    health: Arc<PluginHealth>,
+    version: String,
+    // schema的sha256，用于判断插件重新加载后schema是否发生变化
+    schema_hash: String,
+    // 加载时的unix时间戳（秒）
+    loaded_at: u64,
+    _library: Option<Library>,
+}
+
+impl PluginHandler {
+    fn new(
+        handler: Box<dyn GraphqlRequestHandler + Send + Sync>,
+        health: Arc<PluginHealth>,
+        version: String,
+        schema_sdl: &str,
+        library: Option<Library>,
+    ) -> Self {
+        Self {
+            handler,
+            health,
+            version,
+            schema_hash: sha256_hex(schema_sdl.as_bytes()),
+            loaded_at: unix_now(),
+            _library: library,
+        }
+    }
+    pub fn health(&self) -> &PluginHealth {
+        &self.health
+    }
+    /// 版本名，稳定版本为加载时插件元数据中的版本，金丝雀版本为加载时指定的版本
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+    /// 加载时schema的sha256
+    pub fn schema_hash(&self) -> &str {
+        &self.schema_hash
+    }
+    // 没有执行中的请求，且加载后或最近一次请求后空闲超过idle_secs
+    fn is_idle(&self, now: u64, idle_secs: u64) -> bool {
+        let last_active = self.loaded_at.max(self.health.last_used());
+        self.health.in_flight() == 0 && now.saturating_sub(last_active) >= idle_secs
+    }
+}
+
+impl Deref for PluginHandler {
+    type Target = dyn GraphqlRequestHandler + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.handler.as_ref()
+    }
+}
+
+/// 已创建但尚未添加到存储器的处理器，用于替换已加载的插件前比较两者的schema。字段顺序与`PluginHandler`一致
+pub struct StagedHandler {
+    handler: Box<dyn GraphqlRequestHandler + Send + Sync>,
+    library: Option<Library>,
+    // 加载的编译产物副本的sha256
+    checksum: Option<String>,
+}
+
+impl StagedHandler {
+    pub fn new(handler: Box<dyn GraphqlRequestHandler + Send + Sync>) -> Self {
+        Self {
+            handler,
+            library: None,
+            checksum: None,
+        }
+    }
+    /// 记录加载的编译产物的sha256，金丝雀版本提升时以其确认编译产物没有被重新编译
+    pub fn with_checksum(mut self, checksum: String) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// 从动态链接包中创建处理器
+    pub fn open_lib(path: &Path) -> Result<Self, Error> {
+        let lib = Library::new(path).map_err(|e| -> Error {
+            log::error!("{}", e);
+            Error::LoadLibError
+        })?;
+        check_interface_version(&lib)?;
+        let handler = {
+            let create_service: libloading::Symbol<
+                fn() -> Box<dyn GraphqlRequestHandler + Send + Sync>,
+            > = unsafe { lib.get(b"new_service") }.map_err(|e| -> Error {
+                log::error!("{}", e);
+                Error::LoadPluginError
+            })?;
+            catch_panic_sync(*create_service).map_err(|message| -> Error {
+                log::error!("plugin panicked while creating service: {}", message);
+                Error::LoadPluginError
+            })?
+        };
+        if let Some(tracer) = telemetry::field_tracer() {
+            handler.set_field_tracer(tracer);
+        }
+        Ok(Self {
+            handler,
+            library: Some(lib),
+            checksum: None,
+        })
+    }
+
+    /// 处理器的schema
+    pub fn schema_sdl(&self) -> Result<String, Error> {
+        catch_panic_sync(|| self.handler.schema_sdl()).map_err(|message| -> Error {
+            log::error!("plugin panicked while loading: {}", message);
+            Error::LoadPluginError
+        })
+    }
+}
+
+/// 校验动态链接包导出的接口版本，与主服务的`INTERFACE_VERSION`不一致或者没有导出时返回错误。
+/// 跨越边界的类型布局不一致时调用插件是未定义行为，必须在调用插件的任何函数之前校验
+pub fn check_interface_version(lib: &Library) -> Result<(), Error> {
+    let version = unsafe { lib.get::<*const u32>(b"PLUGIN_INTERFACE_VERSION") }
+        .map(|symbol| unsafe { **symbol })
+        .map_err(|e| -> Error {
+            log::error!("plugin does not export an interface version: {}", e);
+            Error::InterfaceVersionMismatch
+        })?;
+    if version != INTERFACE_VERSION {
+        log::error!(
+            "plugin interface version {} does not match {}, rebuild the plugin",
+            version,
+            INTERFACE_VERSION
+        );
+        return Err(Error::InterfaceVersionMismatch);
+    }
+    Ok(())
+}
+
+/// 插件的动态链接包或wasm模块在私有目录中的副本。校验与加载使用同一份副本，校验后原文件被替换也不会加载到未经校验的内容；
+/// 副本的路径每次都不同，dlopen不会复用已加载的旧版本。副本释放时删除所在的目录
+pub struct PrivateArtifact {
+    dir: TempDir,
+    path: PathBuf,
+    checksum: String,
+}
+
+impl PrivateArtifact {
+    /// 复制到只有当前用户可以访问的随机目录中，并计算副本的sha256
+    pub fn copy_of(path: &Path) -> Result<Self, Error> {
+        let copy = || -> Result<Self, BuildError> {
+            let dir = private_dir("my-master-plugin-")?;
+            let copy = dir.path().join(path.file_name().unwrap_or_default());
+            fs::copy(path, &copy)?;
+            let checksum = sha256_file(&copy)?;
+            Ok(Self {
+                dir,
+                path: copy,
+                checksum,
+            })
+        };
+        copy().map_err(|e| -> Error {
+            log::error!("unable to copy {}: {}", path.display(), e);
+            Error::LoadLibError
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// 副本所在的私有目录
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+    pub fn checksum(&self) -> &str {
+        &self.checksum
+    }
+}
+
+/// 创建只有当前用户可以访问的随机目录，其他用户无法替换其中的文件
+pub fn private_dir(prefix: &str) -> io::Result<TempDir> {
+    let dir = tempfile::Builder::new().prefix(prefix).tempdir()?;
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700))?;
+    Ok(dir)
+}
+
+/// 加载插件所需的配置：动态链接包或wasm模块的路径、校验方式以及执行的资源限制
 #[derive(Clone)]
+pub struct PluginSource {
+    pub name: PluginName,
+    pub target: PluginTarget,
+    pub path: PathBuf,
+    pub require_checksum: bool,
+    pub trusted_keys: Arc<TrustedKeys>,
+    pub wasm_limits: wasm::WasmLimits,
+    /// 配置了进程外执行时为工作进程的资源限制
+    pub worker_limits: Option<worker::WorkerLimits>,
+}
+
+/// 共享的处理器，请求时从存储器中克隆出来后即可释放锁
+pub type SharedHandler = Arc<PluginHandler>;
+
+/// 处理器的存储容器
+#[derive(Clone, Default)]
 pub struct HandlerStorage {
-    storage: HashMap<String, Box<dyn GraphqlRequestHandler + Send + Sync>>,
+    storage: HashMap<String, SharedHandler>,
+    canaries: HashMap<String, Canaries>,
+    health: HashMap<String, Arc<PluginHealth>>,
+    panic_threshold: u32,
+    workers: WorkerSettings,
+    wasm: WasmSettings,
+    metrics: Arc<Metrics>,
+    // 金丝雀版本影子流量的比较结果，版本卸载时一并删除
+    shadow: Arc<ShadowStore>,
+    preload: Vec<PluginName>,
+    // 以Warn模式预加载失败的插件，使用时再加载，不影响就绪检查
+    preload_failures: HashSet<PluginName>,
+    pinned: HashSet<String>,
+    build: BuildOptions,
+    build_locks: Arc<PluginLocks>,
+    load_locks: Arc<PluginLocks>,
+    require_checksum: bool,
+    trusted_keys: Arc<TrustedKeys>,
+    eviction: EvictionConfig,
 }
 
 impl HandlerStorage {
     pub fn new() -> Self {
-        let storage = HashMap::new();
-        Self { storage }
+        Self::with_panic_threshold(health::DEFAULT_PANIC_THRESHOLD)
     }
-    pub fn get_handler(
-        &self,
-        key: String,
-    ) -> Option<&Box<dyn GraphqlRequestHandler + Send + Sync>> {
-        self.storage.get(&key)
+    /// 指定插件连续panic多少次后被隔离，0表示不隔离
+    pub fn with_panic_threshold(panic_threshold: u32) -> Self {
+        Self {
+            panic_threshold,
+            ..Default::default()
+        }
+    }
+    /// 指定在工作进程中执行的插件
+    pub fn with_workers(mut self, workers: WorkerSettings) -> Self {
+        self.workers = workers;
+        self
+    }
+    pub fn workers(&self) -> &WorkerSettings {
+        &self.workers
+    }
+    /// 指定编译为wasm的插件
+    pub fn with_wasm(mut self, wasm: WasmSettings) -> Self {
+        self.wasm = wasm;
+        self
+    }
+    pub fn wasm(&self) -> &WasmSettings {
+        &self.wasm
+    }
+    /// 指定记录插件加载、卸载的指标
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+    pub fn shadow(&self) -> &Arc<ShadowStore> {
+        &self.shadow
+    }
+    /// 指定需要预加载的插件，全部加载后服务才就绪
+    pub fn with_preload(mut self, preload: Vec<PluginName>) -> Self {
+        self.preload = preload;
+        self
+    }
+    pub fn preload(&self) -> &[PluginName] {
+        &self.preload
+    }
+    /// 指定常驻的插件，不会被定时清理卸载
+    pub fn with_pinned(mut self, pinned: impl IntoIterator<Item = String>) -> Self {
+        self.pinned = pinned.into_iter().collect();
+        self
+    }
+    pub fn is_pinned(&self, key: &str) -> bool {
+        self.pinned.contains(key)
+    }
+    /// 指定编译插件使用的目录，动态链接包与wasm模块从其中的libs目录加载
+    pub fn with_build(mut self, build: BuildOptions) -> Self {
+        self.build = build;
+        self
+    }
+    pub fn build_options(&self) -> &BuildOptions {
+        &self.build
+    }
+    /// 指定加载插件时是否要求存在sha256校验文件
+    pub fn with_require_checksum(mut self, require_checksum: bool) -> Self {
+        self.require_checksum = require_checksum;
+        self
+    }
+    pub fn require_checksum(&self) -> bool {
+        self.require_checksum
+    }
+    /// 指定受信任的公钥，配置后只加载由其签名的插件
+    pub fn with_trusted_keys(mut self, trusted_keys: TrustedKeys) -> Self {
+        self.trusted_keys = Arc::new(trusted_keys);
+        self
+    }
+    pub fn trusted_keys(&self) -> &TrustedKeys {
+        &self.trusted_keys
+    }
+    /// 插件的编译锁
+    pub fn build_lock(&self, key: &str) -> Arc<Mutex<()>> {
+        self.build_locks.lock_of(key)
+    }
+    /// 插件的加载锁，同一插件的加载按顺序执行，加载期间不持有存储器的锁
+    pub fn load_lock(&self, key: &str) -> Arc<Mutex<()>> {
+        self.load_locks.lock_of(key)
+    }
+    /// 指定处理器的卸载策略
+    pub fn with_eviction(mut self, eviction: EvictionConfig) -> Self {
+        self.eviction = eviction;
+        self
+    }
+    pub fn eviction(&self) -> &EvictionConfig {
+        &self.eviction
+    }
+    /// 按配置创建存储器
+    pub fn from_config(config: &Config) -> Self {
+        Self::with_panic_threshold(config.panic_threshold)
+            .with_workers(WorkerSettings::from_config(config))
+            .with_wasm(WasmSettings::from_config(config))
+            .with_build(config.build_options())
+            .with_require_checksum(config.require_checksum)
+            .with_eviction(config.eviction.clone())
+            .with_preload(config.startup_plugins())
+            .with_pinned(config.pinned.clone())
+    }
+    /// 热加载配置，只更新可以在运行时修改的卸载策略、预加载与常驻插件、插件的额外依赖以及是否要求校验文件
+    /// 返回新增的预加载与常驻插件，由调用方加载
+    pub fn reload(&mut self, config: &Config) -> Vec<PluginName> {
+        self.eviction = config.eviction.clone();
+        self.require_checksum = config.require_checksum;
+        let preload = config.startup_plugins();
+        let added = preload
+            .iter()
+            .filter(|name| !self.preload.contains(name))
+            .cloned()
+            .collect();
+        self.preload_failures.retain(|name| preload.contains(name));
+        self.preload = preload;
+        self.pinned = config.pinned.iter().cloned().collect();
+        added
+    }
+    /// 插件的动态链接包或wasm模块的路径
+    pub fn lib_path(&self, key: &PluginName, target: PluginTarget) -> PathBuf {
+        self.build.lib_path(key, target)
+    }
+    /// 是否存在插件的动态链接包或wasm模块
+    pub fn has_lib(&self, key: &PluginName) -> bool {
+        self.lib_path(key, self.plugin_target(key)).exists()
+    }
+    /// 记录预加载插件的加载结果，以Warn模式预加载失败的插件在使用时再加载
+    pub fn set_preload_failed(&mut self, key: &PluginName, failed: bool) {
+        if failed {
+            self.preload_failures.insert(key.clone());
+        } else {
+            self.preload_failures.remove(key);
+        }
+    }
+    /// 尚未成功加载过或已被隔离的预加载插件。加载成功后被定时清理卸载的插件会在使用时重新加载，依然视为就绪；
+    /// 以Warn模式预加载失败的插件同样在使用时加载，不计入
+    pub fn missing_preloads(&self) -> Vec<String> {
+        self.preload
+            .iter()
+            .filter(|name| {
+                !self.storage.contains_key(name.as_str())
+                    && !self.preload_failures.contains(*name)
+                    && self
+                        .health
+                        .get(name.as_str())
+                        .is_none_or(|health| health.generation() == 0 || health.is_quarantined())
+            })
+            .map(PluginName::to_string)
+            .collect()
+    }
+    /// 加载插件所需的配置，取出后即可释放存储器的锁
+    pub fn plugin_source(&self, key: &PluginName) -> PluginSource {
+        let target = self.plugin_target(key);
+        PluginSource {
+            name: key.clone(),
+            target,
+            path: self.lib_path(key, target),
+            require_checksum: self.require_checksum,
+            trusted_keys: self.trusted_keys.clone(),
+            wasm_limits: self.wasm.limits_of(key),
+            worker_limits: self.workers.limits_of(key),
+        }
+    }
+    /// 加载金丝雀版本所需的配置，编译产物为该版本的`lib_<插件名>@<版本名>.<后缀>`。版本名不合法时返回错误
+    pub fn canary_source(&self, key: &PluginName, version: &str) -> Result<PluginSource, Error> {
+        let mut source = self.plugin_source(key);
+        source.path = self
+            .canary_build_options(key, version)?
+            .lib_path(key, source.target);
+        Ok(source)
+    }
+    /// 编译金丝雀版本的选项，编译产物安装为该版本的`lib_<插件名>@<版本名>.<后缀>`，不影响稳定版本。
+    /// 版本名不合法或者为`stable`时返回错误
+    pub fn canary_build_options(&self, key: &str, version: &str) -> Result<BuildOptions, Error> {
+        if !canary::is_valid_version(version) {
+            log::warn!("invalid version of plugin {}: {:?}", key, version);
+            return Err(Error::InvalidVersion);
+        }
+        if version == STABLE {
+            log::warn!("version {} of plugin {} is reserved", version, key);
+            return Err(Error::VersionConflict);
+        }
+        Ok(self.build.for_version(version))
+    }
+    /// 插件的编译目标
+    pub fn plugin_target(&self, key: &str) -> PluginTarget {
+        if self.wasm.is_wasm(key) {
+            PluginTarget::Wasm
+        } else {
+            PluginTarget::Native
+        }
+    }
+    pub fn get_handler(&self, key: String) -> Option<SharedHandler> {
+        self.storage.get(&key).cloned()
     }
     pub fn has_handler(&self, key: String) -> bool {
         self.storage.contains_key(&key)
@@ -84,10 +652,481 @@ impl HandlerStorage {
     pub fn show_keys(&self) -> Vec<&String> {
         self.storage.keys().collect()
     }
-    pub fn add_handler(&mut self, handler: Box<dyn GraphqlRequestHandler + Send + Sync>) {
-        self.storage.insert(handler.id(), handler);
+    /// 处理器schema的sha256，用于判断插件重新加载后schema是否发生变化
+    pub fn schema_hash(&self, key: &str) -> String {
+        self.storage
+            .get(key)
+            .map(|handler| handler.schema_hash.clone())
+            .unwrap_or_default()
+    }
+    /// 插件的健康状态，插件卸载后依然保留
+    pub fn health(&self, key: &str) -> Option<Arc<PluginHealth>> {
+        self.health.get(key).cloned()
+    }
+    /// 插件的加载状态与健康状态
+    pub fn status(&self, key: &str) -> PluginStatus {
+        let handler = self.storage.get(key);
+        let state = match handler {
+            Some(_) => LoadState::Loaded,
+            None if self.is_quarantined(key) => LoadState::Quarantined,
+            None => LoadState::Unloaded,
+        };
+        let health = match self.health.get(key) {
+            Some(health) => health.snapshot(),
+            None => PluginHealth::default().snapshot(),
+        };
+        PluginStatus {
+            name: key.to_string(),
+            state,
+            version: handler.map(|h| h.version().to_string()),
+            target: self.plugin_target(key).as_str(),
+            pinned: self.is_pinned(key),
+            health,
+            canaries: self.canary_status(key),
+        }
+    }
+    fn canary_status(&self, key: &str) -> Vec<CanaryStatus> {
+        let canaries = match self.canaries.get(key) {
+            Some(canaries) => canaries,
+            None => return Vec::new(),
+        };
+        canaries
+            .iter()
+            .map(|(version, canary)| CanaryStatus {
+                version: version.clone(),
+                weight: canary.weight,
+                shadow: canary.shadow,
+                health: canary.handler.health.snapshot(),
+            })
+            .collect()
+    }
+    /// 记录插件加载失败的信息
+    pub fn record_load_error(&mut self, key: &str, message: String) {
+        self.health_of(key).record_load_error(message);
+    }
+    fn health_of(&mut self, key: &str) -> Arc<PluginHealth> {
+        let panic_threshold = self.panic_threshold;
+        self.health
+            .entry(key.to_string())
+            .or_insert_with(|| Arc::new(PluginHealth::new(panic_threshold)))
+            .clone()
+    }
+    pub fn is_quarantined(&self, key: &str) -> bool {
+        self.health
+            .get(key)
+            .map(|h| h.is_quarantined())
+            .unwrap_or(false)
+    }
+    pub fn add_handler(
+        &mut self,
+        handler: Box<dyn GraphqlRequestHandler + Send + Sync>,
+    ) -> Result<(), Error> {
+        self.insert(handler, None).map(|_| ())
     }
+    /// 添加从动态链接包中加载的处理器
+    pub fn add_plugin(
+        &mut self,
+        handler: Box<dyn GraphqlRequestHandler + Send + Sync>,
+        library: Library,
+    ) -> Result<(), Error> {
+        self.insert(handler, Some(library)).map(|_| ())
+    }
+    /// 添加已创建的处理器，返回处理器的id
+    pub fn add_staged(&mut self, staged: StagedHandler) -> Result<String, Error> {
+        self.insert(staged.handler, staged.library)
+    }
+    /// 从动态链接包中创建处理器并添加到存储器，返回处理器的id。
+    /// 动态链接包随处理器一起保存，处理器释放后才会卸载
+    pub fn load_lib(&mut self, path: &Path) -> Result<String, Error> {
+        let staged = StagedHandler::open_lib(path)?;
+        self.add_staged(staged)
+    }
+    fn insert(
+        &mut self,
+        handler: Box<dyn GraphqlRequestHandler + Send + Sync>,
+        library: Option<Library>,
+    ) -> Result<String, Error> {
+        let (id, schema_sdl, version) = describe(handler.as_ref())?;
+        let health = self.health_of(&id);
+        health.record_load();
+        // 重新编译或重新加载的处理器是新的实例，解除之前的隔离
+        health.release();
+        let handler = PluginHandler::new(handler, health, version.clone(), &schema_sdl, library);
+        let previous = self.storage.insert(id.clone(), Arc::new(handler));
+        let event = match previous {
+            Some(_) => LifecycleEvent::Reload,
+            None => LifecycleEvent::Load,
+        };
+        self.metrics.record_lifecycle(&id, Some(&version), event);
+        self.metrics.set_handlers(self.handler_count());
+        Ok(id)
+    }
+    /// 卸载插件，同时卸载插件的金丝雀版本
     pub fn remove_handler(&mut self, key: String) {
-        self.storage.remove(&key);
+        if let Some(canaries) = self.canaries.remove(&key) {
+            for (version, _) in canaries.iter() {
+                self.shadow.clear(&key, version);
+                self.metrics
+                    .record_canary(&key, version, LifecycleEvent::Unload);
+            }
+        }
+        if self.storage.remove(&key).is_some() {
+            self.metrics
+                .record_lifecycle(&key, None, LifecycleEvent::Unload);
+        }
+        self.metrics.set_handlers(self.handler_count());
+    }
+    // 所有版本的处理器数量
+    fn handler_count(&self) -> usize {
+        self.storage.len() + self.canaries.values().map(Canaries::len).sum::<usize>()
+    }
+    /// 添加插件的金丝雀版本，version为None时使用插件元数据中的版本，返回金丝雀版本的版本名。
+    /// 插件的稳定版本必须已加载，版本名不能与稳定版本或已加载的金丝雀版本重复。
+    /// weight为分配到的请求百分比，shadow为复制到该版本的稳定版本请求百分比
+    pub fn add_canary(
+        &mut self,
+        key: &str,
+        version: Option<String>,
+        weight: u32,
+        shadow: u32,
+        staged: StagedHandler,
+    ) -> Result<String, Error> {
+        let stable_version = match self.storage.get(key) {
+            Some(stable) => stable.version.clone(),
+            None => return Err(Error::HandlerNotFound),
+        };
+        let (id, schema_sdl, metadata_version) = describe(staged.handler.as_ref())?;
+        if id != key {
+            log::error!("canary of plugin {} has a different id {}", key, id);
+            return Err(Error::LoadPluginError);
+        }
+        let version = version.unwrap_or(metadata_version);
+        if !canary::is_valid_version(&version) {
+            log::warn!("invalid version of plugin {}: {:?}", key, version);
+            return Err(Error::InvalidVersion);
+        }
+        if version == STABLE || version == stable_version || self.has_canary(key, &version) {
+            log::warn!("version {} of plugin {} already loaded", version, key);
+            return Err(Error::VersionConflict);
+        }
+        self.check_weight(key, &version, weight)?;
+        check_shadow(shadow)?;
+        // 金丝雀版本的健康状态独立于稳定版本，连续panic时只回滚该版本
+        let health = Arc::new(PluginHealth::new(self.panic_threshold));
+        health.record_load();
+        let handler = PluginHandler::new(
+            staged.handler,
+            health,
+            version.clone(),
+            &schema_sdl,
+            staged.library,
+        );
+        let canary = CanaryVersion {
+            handler: Arc::new(handler),
+            weight,
+            shadow,
+            checksum: staged.checksum,
+        };
+        self.canaries
+            .entry(key.to_string())
+            .or_default()
+            .insert(version.clone(), canary);
+        self.metrics
+            .record_canary(key, &version, LifecycleEvent::Canary);
+        self.metrics.set_handlers(self.handler_count());
+        Ok(version)
+    }
+    /// 修改金丝雀版本的权重与影子流量的比例，None表示不修改
+    pub fn update_canary(
+        &mut self,
+        key: &str,
+        version: &str,
+        weight: Option<u32>,
+        shadow: Option<u32>,
+    ) -> Result<(), Error> {
+        if let Some(weight) = weight {
+            self.check_weight(key, version, weight)?;
+        }
+        if let Some(shadow) = shadow {
+            check_shadow(shadow)?;
+        }
+        let canary = self
+            .canaries
+            .get_mut(key)
+            .and_then(|canaries| canaries.get_mut(version))
+            .ok_or(Error::NoSuchVersion)?;
+        canary.weight = weight.unwrap_or(canary.weight);
+        canary.shadow = shadow.unwrap_or(canary.shadow);
+        Ok(())
+    }
+    /// 稳定版本的请求按比例采样后需要复制到的影子版本
+    pub fn shadow_targets(&self, key: &str) -> Vec<SharedHandler> {
+        self.canaries
+            .get(key)
+            .map(Canaries::shadows)
+            .unwrap_or_default()
+    }
+    // 各金丝雀版本的权重之和不能超过100
+    fn check_weight(&self, key: &str, version: &str, weight: u32) -> Result<(), Error> {
+        let others = self
+            .canaries
+            .get(key)
+            .map(|canaries| canaries.weight_except(version))
+            .unwrap_or(0);
+        if weight > TOTAL_WEIGHT - others {
+            log::warn!(
+                "weight {} of plugin {} version {} exceeds the remaining {}",
+                weight,
+                key,
+                version,
+                TOTAL_WEIGHT - others
+            );
+            return Err(Error::InvalidCanaryWeight);
+        }
+        Ok(())
+    }
+    pub fn has_canary(&self, key: &str, version: &str) -> bool {
+        self.get_canary(key, version).is_some()
+    }
+    pub fn get_canary(&self, key: &str, version: &str) -> Option<SharedHandler> {
+        self.canaries
+            .get(key)?
+            .get(version)
+            .map(|canary| canary.handler.clone())
+    }
+    /// 处理器是否为插件当前的稳定版本
+    pub fn is_stable(&self, key: &str, handler: &SharedHandler) -> bool {
+        self.storage
+            .get(key)
+            .map(|stable| Arc::ptr_eq(stable, handler))
+            .unwrap_or(false)
+    }
+    /// 处理器是否为插件的金丝雀版本
+    pub fn is_canary(&self, key: &str, handler: &SharedHandler) -> bool {
+        self.canaries
+            .get(key)
+            .map(|canaries| {
+                canaries
+                    .iter()
+                    .any(|(_, canary)| Arc::ptr_eq(&canary.handler, handler))
+            })
+            .unwrap_or(false)
+    }
+    /// 指定的金丝雀版本，未指定时为插件唯一的金丝雀版本
+    pub fn canary_version(&self, key: &str, version: Option<&str>) -> Result<String, Error> {
+        let canaries = self.canaries.get(key);
+        match (version, canaries) {
+            (Some(version), Some(canaries)) if canaries.get(version).is_some() => {
+                Ok(version.to_string())
+            }
+            (None, Some(canaries)) if canaries.len() == 1 => Ok(canaries
+                .iter()
+                .map(|(version, _)| version.clone())
+                .collect()),
+            _ => {
+                log::warn!("no such canary {:?} of plugin {}", version, key);
+                Err(Error::NoSuchVersion)
+            }
+        }
+    }
+    /// 金丝雀版本加载时编译产物的sha256
+    pub fn canary_checksum(&self, key: &str, version: &str) -> Option<String> {
+        self.canaries.get(key)?.get(version)?.checksum.clone()
+    }
+    /// 将金丝雀版本提升为稳定版本，替换原有的稳定版本，返回提升的版本名。
+    /// 未指定版本时插件必须只有一个金丝雀版本。提升后作为稳定版本重新统计健康状态，金丝雀期间的panic不计入
+    pub fn promote(&mut self, key: &str, version: Option<&str>) -> Result<String, Error> {
+        let version = self.canary_version(key, version)?;
+        let canary = self
+            .take_canary(key, &version)
+            .ok_or(Error::NoSuchVersion)?;
+        canary.handler.health.reset();
+        canary.handler.health.record_load();
+        self.health
+            .insert(key.to_string(), canary.handler.health.clone());
+        self.storage.insert(key.to_string(), canary.handler);
+        self.metrics
+            .record_lifecycle(key, Some(&version), LifecycleEvent::Promote);
+        self.metrics.set_handlers(self.handler_count());
+        Ok(version)
+    }
+    /// 回滚金丝雀版本，未指定版本时回滚插件所有的金丝雀版本，返回回滚的版本名
+    pub fn rollback(&mut self, key: &str, version: Option<&str>) -> Result<Vec<String>, Error> {
+        let versions = match version {
+            Some(version) => vec![self.canary_version(key, Some(version))?],
+            None => self
+                .canaries
+                .get(key)
+                .map(|canaries| {
+                    canaries
+                        .iter()
+                        .map(|(version, _)| version.clone())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        for version in versions.iter() {
+            if self.take_canary(key, version).is_some() {
+                self.metrics
+                    .record_canary(key, version, LifecycleEvent::Rollback);
+            }
+        }
+        self.metrics.set_handlers(self.handler_count());
+        Ok(versions)
+    }
+    // 取出金丝雀版本，同时删除该版本影子流量的比较结果
+    fn take_canary(&mut self, key: &str, version: &str) -> Option<CanaryVersion> {
+        self.shadow.clear(key, version);
+        let canaries = self.canaries.get_mut(key)?;
+        let canary = canaries.remove(version);
+        if canaries.is_empty() {
+            self.canaries.remove(key);
+        }
+        canary
+    }
+    /// 选择请求使用的版本，返回金丝雀版本的版本名，None为稳定版本。
+    /// version为请求头或租户指定的版本：`stable`或稳定版本的版本名表示稳定版本，其他版本必须是已加载的金丝雀版本；
+    /// 未指定时按金丝雀版本的权重选择
+    pub fn select_version(
+        &self,
+        key: &str,
+        version: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        let canaries = self.canaries.get(key);
+        match version {
+            None => Ok(canaries.and_then(Canaries::pick).map(str::to_string)),
+            Some(STABLE) => Ok(None),
+            Some(version) if self.has_canary(key, version) => Ok(Some(version.to_string())),
+            Some(version)
+                if self
+                    .storage
+                    .get(key)
+                    .is_some_and(|stable| stable.version == version) =>
+            {
+                Ok(None)
+            }
+            Some(version) => {
+                log::warn!("no such version {} of plugin {}", version, key);
+                Err(Error::NoSuchVersion)
+            }
+        }
+    }
+    /// 按卸载策略清理处理器，常驻的插件除外，返回被卸载的处理器
+    pub fn evict(&mut self) -> Vec<String> {
+        let now = unix_now();
+        let idle_secs = self.eviction.idle_secs;
+        let keys: Vec<String> = self
+            .storage
+            .iter()
+            .filter(|(key, _)| !self.pinned.contains(*key))
+            .filter(|(key, handler)| match self.eviction.policy {
+                EvictionPolicy::All => true,
+                // 所有版本都空闲时才卸载
+                EvictionPolicy::Idle => {
+                    handler.is_idle(now, idle_secs)
+                        && self.canaries.get(*key).is_none_or(|canaries| {
+                            canaries
+                                .iter()
+                                .all(|(_, canary)| canary.handler.is_idle(now, idle_secs))
+                        })
+                }
+                EvictionPolicy::Never => false,
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys.iter() {
+            self.remove_handler(key.clone());
+        }
+        keys
+    }
+}
+
+// 影子流量的比例不能超过100
+fn check_shadow(shadow: u32) -> Result<(), Error> {
+    if shadow > TOTAL_WEIGHT {
+        log::warn!("invalid shadow sample rate: {}", shadow);
+        return Err(Error::InvalidShadowRate);
+    }
+    Ok(())
+}
+
+// 处理器的id、schema与插件元数据中的版本
+fn describe(
+    handler: &(dyn GraphqlRequestHandler + Send + Sync),
+) -> Result<(String, String, String), Error> {
+    catch_panic_sync(|| {
+        (
+            handler.id(),
+            handler.schema_sdl(),
+            handler.metadata().version,
+        )
+    })
+    .map_err(|message| -> Error {
+        log::error!("plugin panicked while loading: {}", message);
+        Error::LoadPluginError
+    })
+}
+
+/// 按插件区分的锁，用于编译与加载，同一插件同时只有一个编译或加载，不同插件可以并行。
+/// 插件名来自请求的路径，只保存锁的弱引用，最后一个持有者释放后条目在下次获取锁时清理
+#[derive(Default)]
+pub struct PluginLocks {
+    locks: std::sync::Mutex<HashMap<String, Weak<Mutex<()>>>>,
+}
+
+impl PluginLocks {
+    pub fn lock_of(&self, key: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        if let Some(lock) = locks.get(key).and_then(Weak::upgrade) {
+            return lock;
+        }
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        let lock = Arc::new(Mutex::new(()));
+        locks.insert(key.to_string(), Arc::downgrade(&lock));
+        lock
+    }
+    /// 清理已释放的锁，返回保存的锁的数量
+    pub fn len(&self) -> usize {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        locks.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// 处理器存储器的读写锁，记录等待锁的时间
+pub struct StorageLock {
+    inner: RwLock<HandlerStorage>,
+    metrics: Arc<Metrics>,
+    shadow: Arc<ShadowStore>,
+}
+
+impl StorageLock {
+    pub fn new(storage: HandlerStorage) -> Self {
+        Self {
+            metrics: storage.metrics().clone(),
+            shadow: storage.shadow().clone(),
+            inner: RwLock::new(storage),
+        }
+    }
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+    pub fn shadow(&self) -> &Arc<ShadowStore> {
+        &self.shadow
+    }
+    pub async fn read(&self) -> RwLockReadGuard<'_, HandlerStorage> {
+        let start = Instant::now();
+        let guard = self.inner.read().await;
+        self.metrics.observe_lock_wait("read", start.elapsed());
+        guard
+    }
+    pub async fn write(&self) -> RwLockWriteGuard<'_, HandlerStorage> {
+        let start = Instant::now();
+        let guard = self.inner.write().await;
+        self.metrics.observe_lock_wait("write", start.elapsed());
+        guard
     }
 }
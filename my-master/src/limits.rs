@@ -0,0 +1,263 @@
+use graphql_parser::query::{
+    parse_query, Definition, Document, FragmentDefinition, OperationDefinition, Selection,
+    SelectionSet,
+};
+use juniper::{http::GraphQLBatchRequest, DefaultScalarValue};
+use my_interface::QueryLimits;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+use warp::http::{self, StatusCode};
+
+use crate::{config::Config, graphql_error_response};
+
+struct LimitSettings {
+    default_limits: Option<QueryLimits>,
+    plugin_limits: HashMap<String, QueryLimits>,
+}
+
+impl LimitSettings {
+    fn from_config(config: &Config) -> Self {
+        let (default_limits, plugin_limits) = config.plugin_values(|p| p.limits.clone());
+        Self {
+            default_limits,
+            plugin_limits,
+        }
+    }
+}
+
+/// 配置的查询限制，优先于插件在元数据中声明的限制
+pub struct QueryLimitSettings {
+    settings: RwLock<LimitSettings>,
+}
+
+impl QueryLimitSettings {
+    /// 读取各插件的`limits`配置，`*`为默认配置
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            settings: RwLock::new(LimitSettings::from_config(config)),
+        }
+    }
+
+    /// 配置热加载时更新查询限制，对之后执行的请求生效
+    pub fn reload(&self, config: &Config) {
+        *self.settings.write().unwrap() = LimitSettings::from_config(config);
+    }
+
+    /// 插件生效的查询限制：配置了的项覆盖插件声明的项，字段的开销按字段合并
+    pub fn limits_of(&self, plugin: &str, declared: QueryLimits) -> QueryLimits {
+        let settings = self.settings.read().unwrap();
+        let configured = match settings
+            .plugin_limits
+            .get(plugin)
+            .or(settings.default_limits.as_ref())
+        {
+            Some(configured) => configured,
+            None => return declared,
+        };
+        let mut field_costs = declared.field_costs;
+        field_costs.extend(
+            configured
+                .field_costs
+                .iter()
+                .map(|(field, cost)| (field.clone(), *cost)),
+        );
+        QueryLimits {
+            max_depth: configured.max_depth.or(declared.max_depth),
+            max_complexity: configured.max_complexity.or(declared.max_complexity),
+            max_aliases: configured.max_aliases.or(declared.max_aliases),
+            max_batch_size: configured.max_batch_size.or(declared.max_batch_size),
+            field_costs,
+        }
+    }
+}
+
+/// 查询检查失败的原因
+#[derive(Debug, PartialEq)]
+pub enum QueryCheckError {
+    /// 查询无法解析
+    Invalid(String),
+    /// 查询超出限制
+    Exceeded(String),
+}
+
+impl QueryCheckError {
+    /// 构造检查失败时的graphql错误响应
+    pub fn into_response(self) -> http::Response<Vec<u8>> {
+        match self {
+            QueryCheckError::Invalid(message) => {
+                graphql_error_response(StatusCode::BAD_REQUEST, "GRAPHQL_PARSE_FAILED", message)
+            }
+            QueryCheckError::Exceeded(message) => limit_exceeded_response(message),
+        }
+    }
+}
+
+/// 查询的统计信息
+#[derive(Debug, Default, PartialEq)]
+pub struct QueryStats {
+    pub depth: usize,
+    pub complexity: usize,
+    pub aliases: usize,
+}
+
+struct Analyzer<'a, 'q> {
+    fragments: HashMap<&'q str, &'q FragmentDefinition<'q, String>>,
+    field_costs: &'a HashMap<String, usize>,
+    // 已统计的片段，每个片段只展开一次，避免重复引用的片段使统计的耗时指数增长
+    fragment_stats: HashMap<&'q str, QueryStats>,
+}
+
+impl QueryStats {
+    // 合并同一层级的选择的统计，深度取最大值，复杂度与别名数量累加
+    fn merge(&mut self, other: &QueryStats) {
+        self.depth = self.depth.max(other.depth);
+        self.complexity = self.complexity.saturating_add(other.complexity);
+        self.aliases = self.aliases.saturating_add(other.aliases);
+    }
+}
+
+impl<'a, 'q> Analyzer<'a, 'q> {
+    // 统计选择集，深度相对于该选择集。展开片段时记录正在展开的片段防止循环引用
+    fn visit(
+        &mut self,
+        selection_set: &'q SelectionSet<'q, String>,
+        visiting: &mut HashSet<&'q str>,
+    ) -> QueryStats {
+        let mut stats = QueryStats::default();
+        for item in &selection_set.items {
+            match item {
+                Selection::Field(field) => {
+                    let children = self.visit(&field.selection_set, visiting);
+                    let cost = self
+                        .field_costs
+                        .get(field.name.as_str())
+                        .copied()
+                        .unwrap_or(1);
+                    stats.merge(&QueryStats {
+                        depth: children.depth + 1,
+                        complexity: children.complexity.saturating_add(cost),
+                        aliases: children.aliases + field.alias.is_some() as usize,
+                    });
+                }
+                Selection::InlineFragment(fragment) => {
+                    let children = self.visit(&fragment.selection_set, visiting);
+                    stats.merge(&children);
+                }
+                Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    if let Some(children) = self.fragment_stats.get(name) {
+                        stats.merge(children);
+                        continue;
+                    }
+                    if let Some(fragment) = self.fragments.get(name).copied() {
+                        if visiting.insert(name) {
+                            let children = self.visit(&fragment.selection_set, visiting);
+                            visiting.remove(name);
+                            stats.merge(&children);
+                            self.fragment_stats.insert(name, children);
+                        }
+                    }
+                }
+            }
+        }
+        stats
+    }
+}
+
+fn operation_selection_set<'q>(
+    operation: &'q OperationDefinition<'q, String>,
+) -> &'q SelectionSet<'q, String> {
+    match operation {
+        OperationDefinition::SelectionSet(set) => set,
+        OperationDefinition::Query(query) => &query.selection_set,
+        OperationDefinition::Mutation(mutation) => &mutation.selection_set,
+        OperationDefinition::Subscription(subscription) => &subscription.selection_set,
+    }
+}
+
+/// 统计查询的深度、复杂度与别名数量，多个operation时取最大值。查询无法解析时返回解析的错误信息
+pub fn analyze_query(
+    query: &str,
+    field_costs: &HashMap<String, usize>,
+) -> Result<QueryStats, String> {
+    let document: Document<String> = parse_query(query).map_err(|e| e.to_string())?;
+    let fragments = document
+        .definitions
+        .iter()
+        .filter_map(|d| match d {
+            Definition::Fragment(f) => Some((f.name.as_str(), f)),
+            _ => None,
+        })
+        .collect();
+    let mut analyzer = Analyzer {
+        fragments,
+        field_costs,
+        fragment_stats: HashMap::new(),
+    };
+    let mut result = QueryStats::default();
+    for definition in &document.definitions {
+        if let Definition::Operation(operation) = definition {
+            let stats = analyzer.visit(operation_selection_set(operation), &mut HashSet::new());
+            result.depth = result.depth.max(stats.depth);
+            result.complexity = result.complexity.max(stats.complexity);
+            result.aliases = result.aliases.max(stats.aliases);
+        }
+    }
+    Ok(result)
+}
+
+/// 检查查询能否解析以及是否超出限制
+pub fn check_query(query: &str, limits: &QueryLimits) -> Result<(), QueryCheckError> {
+    let stats = analyze_query(query, &limits.field_costs).map_err(QueryCheckError::Invalid)?;
+    let checks = [
+        ("depth", stats.depth, limits.max_depth),
+        ("complexity", stats.complexity, limits.max_complexity),
+        ("aliases", stats.aliases, limits.max_aliases),
+    ];
+    for (name, value, limit) in checks.iter() {
+        if let Some(limit) = limit {
+            if value > limit {
+                return Err(QueryCheckError::Exceeded(format!(
+                    "query {} {} exceeds the limit of {}",
+                    name, value, limit
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 检查批量请求的数量以及其中每个查询是否超出限制
+pub fn check_batch_request(
+    req: &GraphQLBatchRequest<DefaultScalarValue>,
+    limits: &QueryLimits,
+) -> Result<(), QueryCheckError> {
+    let requests = match req {
+        GraphQLBatchRequest::Single(single) => vec![single],
+        GraphQLBatchRequest::Batch(batch) => batch.iter().collect(),
+    };
+    if let Some(max_batch_size) = limits.max_batch_size {
+        if requests.len() > max_batch_size {
+            return Err(QueryCheckError::Exceeded(format!(
+                "batch size {} exceeds the limit of {}",
+                requests.len(),
+                max_batch_size
+            )));
+        }
+    }
+    // GraphQLRequest的query字段不公开，通过序列化获取
+    requests.iter().try_for_each(|r| {
+        let value = serde_json::to_value(r).map_err(|e| QueryCheckError::Invalid(e.to_string()))?;
+        match value.get("query").and_then(|q| q.as_str()) {
+            Some(query) => check_query(query, limits),
+            None => Ok(()),
+        }
+    })
+}
+
+/// 构造查询超出限制时的graphql错误响应
+pub fn limit_exceeded_response(message: String) -> http::Response<Vec<u8>> {
+    graphql_error_response(StatusCode::BAD_REQUEST, "QUERY_LIMIT_EXCEEDED", message)
+}
@@ -5,5 +5,12 @@
 /// Runs the API server.
 #[tokio::main]
 async fn main() {
-    my_master::route::run().await
+    let args: Vec<String> = std::env::args().collect();
+    match args.as_slice() {
+        // 由主服务启动的插件工作进程
+        [_, mode, lib_path, socket_path] if mode == my_master::worker::WORKER_ARG => {
+            my_master::worker::run_worker(lib_path, socket_path).await
+        }
+        _ => my_master::route::run().await,
+    }
 }
@@ -0,0 +1,283 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+    time::Duration,
+};
+use warp::http::StatusCode;
+
+// 指标名称的前缀
+const NAMESPACE: &str = "my_master";
+// 未加载过的插件没有版本信息
+const UNKNOWN_VERSION: &str = "unknown";
+// 既未加载过也未配置的插件，插件名来自请求的路径，不能直接作为标签
+const UNKNOWN_PLUGIN: &str = "unknown";
+
+/// 插件的生命周期事件
+#[derive(Debug, Clone, Copy)]
+pub enum LifecycleEvent {
+    Load,
+    Unload,
+    Reload,
+    /// 加载金丝雀版本
+    Canary,
+    /// 金丝雀版本提升为稳定版本
+    Promote,
+    /// 回滚金丝雀版本
+    Rollback,
+}
+
+impl LifecycleEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleEvent::Load => "load",
+            LifecycleEvent::Unload => "unload",
+            LifecycleEvent::Reload => "reload",
+            LifecycleEvent::Canary => "canary",
+            LifecycleEvent::Promote => "promote",
+            LifecycleEvent::Rollback => "rollback",
+        }
+    }
+}
+
+/// 主服务与各插件的Prometheus指标
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    errors: IntCounterVec,
+    lifecycle: IntCounterVec,
+    builds: IntCounterVec,
+    build_duration: HistogramVec,
+    handlers: IntGauge,
+    lock_wait: HistogramVec,
+    shadow_requests: IntCounterVec,
+    shadow_duration: HistogramVec,
+    // 插件稳定版本最近一次加载的版本，作为请求指标的默认标签
+    versions: RwLock<HashMap<String, String>>,
+    // 配置中出现的插件
+    configured: RwLock<HashSet<String>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)
+            .expect("metrics namespace is valid");
+        let requests = IntCounterVec::new(
+            Opts::new("plugin_requests_total", "插件的graphql请求数"),
+            &["plugin", "version", "tenant"],
+        )
+        .expect("metric is valid");
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("plugin_request_duration_seconds", "插件的graphql请求耗时"),
+            &["plugin", "version", "tenant"],
+        )
+        .expect("metric is valid");
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "plugin_errors_total",
+                "插件的graphql请求中状态码不小于400的请求数",
+            ),
+            &["plugin", "version", "tenant", "status"],
+        )
+        .expect("metric is valid");
+        let lifecycle = IntCounterVec::new(
+            Opts::new(
+                "plugin_lifecycle_total",
+                "插件的加载、卸载、重新加载以及金丝雀版本的加载、提升、回滚次数",
+            ),
+            &["plugin", "version", "event"],
+        )
+        .expect("metric is valid");
+        let builds = IntCounterVec::new(
+            Opts::new("plugin_builds_total", "插件的编译次数"),
+            &["plugin", "target", "outcome"],
+        )
+        .expect("metric is valid");
+        let build_duration = HistogramVec::new(
+            HistogramOpts::new("plugin_build_duration_seconds", "插件的编译耗时")
+                .buckets(vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]),
+            &["plugin", "target", "outcome"],
+        )
+        .expect("metric is valid");
+        let handlers =
+            IntGauge::new("handlers", "处理器存储器中的处理器数量").expect("metric is valid");
+        let lock_wait = HistogramVec::new(
+            HistogramOpts::new("storage_lock_wait_seconds", "等待处理器存储器读写锁的时间")
+                .buckets(vec![
+                    0.000_01, 0.000_1, 0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0,
+                ]),
+            &["mode"],
+        )
+        .expect("metric is valid");
+        let shadow_requests = IntCounterVec::new(
+            Opts::new(
+                "shadow_requests_total",
+                "发送到影子版本的请求数，按与稳定版本的响应是否一致区分",
+            ),
+            &["plugin", "version", "outcome"],
+        )
+        .expect("metric is valid");
+        let shadow_duration = HistogramVec::new(
+            HistogramOpts::new("shadow_request_duration_seconds", "影子版本的请求耗时"),
+            &["plugin", "version"],
+        )
+        .expect("metric is valid");
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(requests.clone()),
+            Box::new(request_duration.clone()),
+            Box::new(errors.clone()),
+            Box::new(lifecycle.clone()),
+            Box::new(builds.clone()),
+            Box::new(build_duration.clone()),
+            Box::new(handlers.clone()),
+            Box::new(lock_wait.clone()),
+            Box::new(shadow_requests.clone()),
+            Box::new(shadow_duration.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector).expect("metric is unique");
+        }
+        Self {
+            registry,
+            requests,
+            request_duration,
+            errors,
+            lifecycle,
+            builds,
+            build_duration,
+            handlers,
+            lock_wait,
+            shadow_requests,
+            shadow_duration,
+            versions: RwLock::new(HashMap::new()),
+            configured: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// 更新配置中出现的插件，配置热加载时调用
+    pub fn set_configured_plugins(&self, plugins: HashSet<String>) {
+        *self.configured.write().unwrap() = plugins;
+    }
+
+    // 请求指标的插件标签，只有加载过或配置了的插件使用插件名，其他插件合并为unknown，防止标签的数量无限增长
+    fn plugin_label<'a>(&self, plugin: &'a str) -> &'a str {
+        if self.versions.read().unwrap().contains_key(plugin)
+            || self.configured.read().unwrap().contains(plugin)
+        {
+            plugin
+        } else {
+            UNKNOWN_PLUGIN
+        }
+    }
+
+    fn version_of(&self, plugin: &str) -> String {
+        self.versions
+            .read()
+            .unwrap()
+            .get(plugin)
+            .cloned()
+            .unwrap_or_else(|| UNKNOWN_VERSION.to_string())
+    }
+
+    /// 记录一次graphql请求的耗时与响应状态，version为请求使用的金丝雀版本，None为稳定版本
+    pub fn observe_request(
+        &self,
+        plugin: &str,
+        version: Option<&str>,
+        tenant: &str,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let plugin = self.plugin_label(plugin);
+        let version = match version {
+            Some(version) => version.to_string(),
+            None => self.version_of(plugin),
+        };
+        let labels = [plugin, version.as_str(), tenant];
+        self.requests.with_label_values(&labels).inc();
+        self.request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+        if status.is_client_error() || status.is_server_error() {
+            self.errors
+                .with_label_values(&[plugin, version.as_str(), tenant, status.as_str()])
+                .inc();
+        }
+    }
+
+    /// 记录插件的生命周期事件，加载稳定版本时同时更新插件的版本
+    pub fn record_lifecycle(&self, plugin: &str, version: Option<&str>, event: LifecycleEvent) {
+        let version = match version {
+            Some(version) => {
+                self.versions
+                    .write()
+                    .unwrap()
+                    .insert(plugin.to_string(), version.to_string());
+                version.to_string()
+            }
+            None => self.version_of(plugin),
+        };
+        self.lifecycle
+            .with_label_values(&[plugin, version.as_str(), event.as_str()])
+            .inc();
+    }
+
+    /// 记录金丝雀版本的生命周期事件，不影响稳定版本的版本
+    pub fn record_canary(&self, plugin: &str, version: &str, event: LifecycleEvent) {
+        self.lifecycle
+            .with_label_values(&[plugin, version, event.as_str()])
+            .inc();
+    }
+
+    /// 记录一次插件编译的耗时与结果
+    pub fn observe_build(&self, plugin: &str, target: &str, success: bool, elapsed: Duration) {
+        let outcome = if success { "success" } else { "failure" };
+        let labels = [plugin, target, outcome];
+        self.builds.with_label_values(&labels).inc();
+        self.build_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn set_handlers(&self, count: usize) {
+        self.handlers.set(count as i64);
+    }
+
+    /// 记录等待处理器存储器读写锁的时间，mode为read或write
+    pub fn observe_lock_wait(&self, mode: &str, elapsed: Duration) {
+        self.lock_wait
+            .with_label_values(&[mode])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// 记录一次影子版本的请求耗时，以及与稳定版本的响应是否一致
+    pub fn observe_shadow(&self, plugin: &str, version: &str, matched: bool, elapsed: Duration) {
+        let outcome = if matched { "match" } else { "mismatch" };
+        self.shadow_requests
+            .with_label_values(&[plugin, version, outcome])
+            .inc();
+        self.shadow_duration
+            .with_label_values(&[plugin, version])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// 以Prometheus文本格式输出所有指标
+    pub fn render(&self) -> (String, Vec<u8>) {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        if let Err(e) = encoder.encode(&self.registry.gather(), &mut buffer) {
+            log::error!("encode metrics failed: {}", e);
+        }
+        (encoder.format_type().to_string(), buffer)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
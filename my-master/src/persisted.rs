@@ -0,0 +1,339 @@
+use juniper::{
+    http::{GraphQLBatchRequest, GraphQLRequest},
+    DefaultScalarValue, InputValue,
+};
+use lru::LruCache;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+use warp::http::{self, StatusCode};
+
+use crate::{config::Config, graphql_error_response};
+
+// 每个插件通过APQ自动缓存的查询数上限，超出时淘汰最久未使用的查询
+const MAX_CACHED_QUERIES: usize = 1000;
+// 每个插件保留查询的schema数，稳定版本与金丝雀版本的schema各自保留，超出时删除最久未使用的schema
+const MAX_SCHEMAS: usize = 4;
+
+// 查询按插件与schema的sha256区分
+type SchemaKey = (String, String);
+
+/// 计算sha256并转为十六进制字符串
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Apollo APQ协议中`extensions.persistedQuery`的结构
+#[derive(Deserialize, Debug, Clone)]
+pub struct PersistedQueryExtension {
+    pub version: i32,
+    #[serde(rename = "sha256Hash")]
+    pub sha256_hash: String,
+}
+
+/// 请求中的`extensions`
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Extensions {
+    #[serde(rename = "persistedQuery")]
+    pub persisted_query: Option<PersistedQueryExtension>,
+}
+
+/// 支持APQ的graphql请求，query可以为空，由`extensions.persistedQuery`指定
+#[derive(Deserialize, Debug)]
+pub struct PersistedRequest {
+    pub query: Option<String>,
+    #[serde(rename = "operationName")]
+    pub operation_name: Option<String>,
+    pub variables: Option<InputValue<DefaultScalarValue>>,
+    pub extensions: Option<Extensions>,
+}
+
+/// 支持APQ的批量graphql请求
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PersistedBatchRequest {
+    Single(PersistedRequest),
+    Batch(Vec<PersistedRequest>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PersistedQueryError {
+    #[error("PersistedQueryNotFound")]
+    NotFound,
+    #[error("PersistedQueryNotSupported")]
+    NotSupported,
+    #[error("provided sha does not match query")]
+    HashMismatch,
+    #[error("query is not in the allow-list of persisted queries")]
+    NotAllowed,
+    #[error("invalid extensions: {0}")]
+    InvalidExtensions(String),
+}
+
+impl PersistedQueryError {
+    /// 转为graphql错误响应，NotFound按APQ协议返回200使客户端重新发送完整的query
+    pub fn into_response(self) -> http::Response<Vec<u8>> {
+        let (status, code) = match &self {
+            PersistedQueryError::NotFound => (StatusCode::OK, "PERSISTED_QUERY_NOT_FOUND"),
+            PersistedQueryError::NotSupported => {
+                (StatusCode::BAD_REQUEST, "PERSISTED_QUERY_NOT_SUPPORTED")
+            }
+            PersistedQueryError::HashMismatch => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+            PersistedQueryError::NotAllowed => {
+                (StatusCode::FORBIDDEN, "PERSISTED_QUERY_NOT_ALLOWED")
+            }
+            PersistedQueryError::InvalidExtensions(_) => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+        };
+        graphql_error_response(status, code, self.to_string())
+    }
+}
+
+struct PluginQueries {
+    // 最近一次使用的序号，用于淘汰不再使用的schema
+    last_used: AtomicU64,
+    // 通过管理接口注册的查询，白名单模式下只允许执行这些查询
+    registered: HashMap<String, String>,
+    // 未开启白名单模式时客户端通过APQ自动缓存的查询，数量有上限
+    cached: LruCache<String, String>,
+}
+
+impl Default for PluginQueries {
+    fn default() -> Self {
+        Self {
+            last_used: AtomicU64::new(0),
+            registered: HashMap::new(),
+            cached: LruCache::new(NonZeroUsize::new(MAX_CACHED_QUERIES).unwrap()),
+        }
+    }
+}
+
+impl PluginQueries {
+    fn len(&self) -> usize {
+        self.registered.len() + self.cached.len()
+    }
+}
+
+/// 持久化查询的存储，与HandlerStorage分开存放，插件热加载后依然保留。
+/// 查询按插件与schema区分，稳定版本与schema不同的金丝雀版本各自保存查询，互不影响
+pub struct PersistedQueryStore {
+    plugins: RwLock<HashMap<SchemaKey, PluginQueries>>,
+    allow_list: RwLock<HashSet<String>>,
+    clock: AtomicU64,
+}
+
+impl PersistedQueryStore {
+    pub fn new(allow_list: HashSet<String>) -> Self {
+        Self {
+            plugins: RwLock::new(HashMap::new()),
+            allow_list: RwLock::new(allow_list),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// 读取开启白名单模式的插件，`*`表示所有插件
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.plugin_list(|p| p.persisted_allow_list))
+    }
+
+    /// 配置热加载时更新开启白名单模式的插件，已注册的查询保留
+    pub fn reload(&self, config: &Config) {
+        *self.allow_list.write().unwrap() = config.plugin_list(|p| p.persisted_allow_list);
+    }
+
+    /// 插件是否开启了白名单模式，开启后仅允许执行预先注册的查询
+    pub fn is_allow_list_mode(&self, plugin: &str) -> bool {
+        let allow_list = self.allow_list.read().unwrap();
+        allow_list.contains(plugin) || allow_list.contains("*")
+    }
+
+    // 记录schema的使用，新的schema使插件保留的schema超出上限时删除最久未使用的schema及其查询
+    fn sync_schema(&self, plugin: &str, schema_hash: &str) {
+        let key = (plugin.to_string(), schema_hash.to_string());
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(entry) = self.plugins.read().unwrap().get(&key) {
+            entry.last_used.store(now, Ordering::Relaxed);
+            return;
+        }
+        let mut write_guard = self.plugins.write().unwrap();
+        write_guard
+            .entry(key)
+            .or_default()
+            .last_used
+            .store(now, Ordering::Relaxed);
+        let schemas: Vec<(&SchemaKey, u64)> = write_guard
+            .iter()
+            .filter(|((p, _), _)| p == plugin)
+            .map(|(key, entry)| (key, entry.last_used.load(Ordering::Relaxed)))
+            .collect();
+        if schemas.len() <= MAX_SCHEMAS {
+            return;
+        }
+        if let Some(oldest) = schemas
+            .into_iter()
+            .min_by_key(|(_, last_used)| *last_used)
+            .map(|(key, _)| key.clone())
+        {
+            if let Some(entry) = write_guard.remove(&oldest) {
+                if entry.len() > 0 {
+                    log::warn!(
+                        "schema {} of plugin {} no longer used, {} persisted queries invalidated",
+                        oldest.1,
+                        plugin,
+                        entry.len()
+                    );
+                }
+            }
+        }
+    }
+
+    // 查找注册的查询
+    fn lookup_registered(&self, plugin: &str, schema_hash: &str, hash: &str) -> Option<String> {
+        let key = (plugin.to_string(), schema_hash.to_string());
+        let read_guard = self.plugins.read().unwrap();
+        read_guard
+            .get(&key)
+            .and_then(|p| p.registered.get(hash).cloned())
+    }
+
+    // 查找注册或缓存的查询，命中缓存时更新其使用顺序
+    fn lookup(&self, plugin: &str, schema_hash: &str, hash: &str) -> Option<String> {
+        if let Some(query) = self.lookup_registered(plugin, schema_hash, hash) {
+            return Some(query);
+        }
+        let key = (plugin.to_string(), schema_hash.to_string());
+        let mut write_guard = self.plugins.write().unwrap();
+        write_guard
+            .get_mut(&key)
+            .and_then(|p| p.cached.get(hash).cloned())
+    }
+
+    /// 为插件指定schema的版本注册查询，返回查询的sha256。注册的查询不会被淘汰
+    pub fn register(&self, plugin: &str, schema_hash: &str, query: String) -> String {
+        self.sync_schema(plugin, schema_hash);
+        let hash = sha256_hex(query.as_bytes());
+        let mut write_guard = self.plugins.write().unwrap();
+        write_guard
+            .entry((plugin.to_string(), schema_hash.to_string()))
+            .or_default()
+            .registered
+            .insert(hash.clone(), query);
+        hash
+    }
+
+    // 缓存客户端通过APQ发送的查询，超出上限时淘汰最久未使用的查询
+    fn cache(&self, plugin: &str, schema_hash: &str, hash: String, query: String) {
+        let mut write_guard = self.plugins.write().unwrap();
+        write_guard
+            .entry((plugin.to_string(), schema_hash.to_string()))
+            .or_default()
+            .cached
+            .put(hash, query);
+    }
+
+    /// 根据APQ协议解析出最终要执行的查询，query与extensions都为空时返回None，交由插件报错
+    pub fn resolve(
+        &self,
+        plugin: &str,
+        schema_hash: &str,
+        query: Option<String>,
+        extensions: Option<Extensions>,
+    ) -> Result<Option<String>, PersistedQueryError> {
+        self.sync_schema(plugin, schema_hash);
+        let allow_list_mode = self.is_allow_list_mode(plugin);
+        match (query, extensions.and_then(|e| e.persisted_query)) {
+            (query, Some(persisted)) => {
+                if persisted.version != 1 {
+                    return Err(PersistedQueryError::NotSupported);
+                }
+                match query {
+                    Some(query) => {
+                        if sha256_hex(query.as_bytes()) != persisted.sha256_hash {
+                            return Err(PersistedQueryError::HashMismatch);
+                        }
+                        if allow_list_mode {
+                            self.lookup_registered(plugin, schema_hash, &persisted.sha256_hash)
+                                .ok_or(PersistedQueryError::NotAllowed)?;
+                        } else {
+                            self.cache(plugin, schema_hash, persisted.sha256_hash, query.clone());
+                        }
+                        Ok(Some(query))
+                    }
+                    None if allow_list_mode => self
+                        .lookup_registered(plugin, schema_hash, &persisted.sha256_hash)
+                        .map(Some)
+                        .ok_or(PersistedQueryError::NotFound),
+                    None => self
+                        .lookup(plugin, schema_hash, &persisted.sha256_hash)
+                        .map(Some)
+                        .ok_or(PersistedQueryError::NotFound),
+                }
+            }
+            (Some(query), None) => {
+                if allow_list_mode
+                    && self
+                        .lookup_registered(plugin, schema_hash, &sha256_hex(query.as_bytes()))
+                        .is_none()
+                {
+                    return Err(PersistedQueryError::NotAllowed);
+                }
+                Ok(Some(query))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// 解析GET请求，`extensions`参数为json字符串，解析后的查询写回`query`参数
+    pub fn resolve_get(
+        &self,
+        plugin: &str,
+        schema_hash: &str,
+        qry: &mut HashMap<String, String>,
+    ) -> Result<(), PersistedQueryError> {
+        let extensions = qry
+            .remove("extensions")
+            .map(|e| serde_json::from_str::<Extensions>(&e))
+            .transpose()
+            .map_err(|e| PersistedQueryError::InvalidExtensions(e.to_string()))?;
+        if let Some(query) = self.resolve(plugin, schema_hash, qry.remove("query"), extensions)? {
+            qry.insert("query".to_string(), query);
+        }
+        Ok(())
+    }
+
+    /// 解析POST json请求，转换为插件所需的批量请求
+    pub fn resolve_batch(
+        &self,
+        plugin: &str,
+        schema_hash: &str,
+        req: PersistedBatchRequest,
+    ) -> Result<GraphQLBatchRequest<DefaultScalarValue>, PersistedQueryError> {
+        let resolve_one = |r: PersistedRequest| {
+            let PersistedRequest {
+                query,
+                operation_name,
+                variables,
+                extensions,
+            } = r;
+            self.resolve(plugin, schema_hash, query, extensions)
+                .map(|q| GraphQLRequest::new(q.unwrap_or_default(), operation_name, variables))
+        };
+        match req {
+            PersistedBatchRequest::Single(r) => resolve_one(r).map(GraphQLBatchRequest::Single),
+            PersistedBatchRequest::Batch(rs) => rs
+                .into_iter()
+                .map(resolve_one)
+                .collect::<Result<Vec<_>, _>>()
+                .map(GraphQLBatchRequest::Batch),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::config::Config;
+
+// 清理令牌桶的间隔，清理需要遍历所有的桶，只按间隔进行，不在每次请求时进行
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+// 超过该时间没有请求的桶视为空闲，清理时删除
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 令牌桶的限流配置
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// 桶的容量，即允许的突发请求数
+    pub capacity: f64,
+    /// 每秒补充的令牌数
+    pub refill_per_sec: f64,
+}
+
+impl RateLimit {
+    // 解析`容量:每秒补充数`格式的配置
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(2, ':');
+        let capacity = parts.next()?.trim().parse::<f64>().ok()?;
+        let refill_per_sec = parts.next()?.trim().parse::<f64>().ok()?;
+        Some(Self {
+            capacity,
+            refill_per_sec,
+        })
+        .filter(Self::is_valid)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.capacity >= 1.0 && self.refill_per_sec > 0.0
+    }
+}
+
+/// 令牌桶的key：插件、调用方（已鉴权时为subject，否则为IP）、租户
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize)]
+pub struct BucketKey {
+    pub plugin: String,
+    pub client: String,
+    pub tenant: String,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// 令牌桶的当前状态，用于管理接口展示
+#[derive(Debug, Serialize)]
+pub struct BucketState {
+    #[serde(flatten)]
+    pub key: BucketKey,
+    pub tokens: f64,
+    pub limit: RateLimit,
+}
+
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    last_prune: Instant,
+}
+
+struct RateLimits {
+    default_limit: Option<RateLimit>,
+    plugin_limits: HashMap<String, RateLimit>,
+}
+
+/// 按插件、调用方、租户进行令牌桶限流
+pub struct RateLimiter {
+    limits: RwLock<RateLimits>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        default_limit: Option<RateLimit>,
+        plugin_limits: HashMap<String, RateLimit>,
+    ) -> Self {
+        Self {
+            limits: RwLock::new(RateLimits {
+                default_limit,
+                plugin_limits,
+            }),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    /// 读取各插件的`rate_limit`配置，`*`为默认配置。未配置时不限流
+    pub fn from_config(config: &Config) -> Self {
+        let (default_limit, plugin_limits) = config.plugin_values(|p| p.rate_limit);
+        Self::new(default_limit, plugin_limits)
+    }
+
+    /// 配置热加载时更新限流配置，已有的令牌桶保留，令牌数不超过新的容量
+    pub fn reload(&self, config: &Config) {
+        let (default_limit, plugin_limits) = config.plugin_values(|p| p.rate_limit);
+        *self.limits.write().unwrap() = RateLimits {
+            default_limit,
+            plugin_limits,
+        };
+    }
+
+    fn limit_of(&self, plugin: &str) -> Option<RateLimit> {
+        let limits = self.limits.read().unwrap();
+        limits
+            .plugin_limits
+            .get(plugin)
+            .copied()
+            .or(limits.default_limit)
+    }
+
+    /// 消耗一个令牌，超出限制时返回需要等待的时间
+    pub fn check(&self, key: BucketKey) -> Result<(), Duration> {
+        let limit = match self.limit_of(&key.plugin) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.last_prune) >= PRUNE_INTERVAL {
+            self.prune(&mut buckets, now);
+        }
+        let bucket = buckets.buckets.entry(key).or_insert(Bucket {
+            tokens: limit.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_sec).min(limit.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / limit.refill_per_sec;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+
+    // 清理空闲或已经回满的桶，回满的桶与新建的桶状态一致
+    fn prune(&self, buckets: &mut Buckets, now: Instant) {
+        buckets.last_prune = now;
+        let limits = self.limits.read().unwrap();
+        buckets.buckets.retain(|key, bucket| {
+            let idle = now.duration_since(bucket.updated);
+            let limit = limits
+                .plugin_limits
+                .get(&key.plugin)
+                .or(limits.default_limit.as_ref());
+            match limit {
+                Some(limit) if idle < IDLE_TIMEOUT => {
+                    bucket.tokens + idle.as_secs_f64() * limit.refill_per_sec < limit.capacity
+                }
+                _ => false,
+            }
+        });
+    }
+
+    /// 所有令牌桶的当前状态
+    pub fn snapshot(&self) -> Vec<BucketState> {
+        let now = Instant::now();
+        let buckets = self.buckets.lock().unwrap();
+        buckets
+            .buckets
+            .iter()
+            .filter_map(|(key, bucket)| {
+                let limit = self.limit_of(&key.plugin)?;
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                Some(BucketState {
+                    key: key.clone(),
+                    tokens: (bucket.tokens + elapsed * limit.refill_per_sec).min(limit.capacity),
+                    limit,
+                })
+            })
+            .collect()
+    }
+}
@@ -1,16 +1,94 @@
 use bytes::Bytes;
 use dotenv::dotenv;
-use juniper::{http::GraphQLBatchRequest, DefaultScalarValue};
-use libloading::Library;
-use my_interface::{data_context_extractor, get_lib_suffix, DataContext, GraphqlRequestHandler};
-use my_plugin_builder::{build_plugin, demo};
-use std::{collections::HashMap, convert::Infallible, net::SocketAddr, path::Path, sync::Arc};
-use tokio::sync::{RwLock, RwLockWriteGuard};
-use warp::{body, http, query, reject, Filter, Rejection, Reply};
+use my_interface::{
+    data_context_extractor, plugin_panic_response, DataContext, PluginName, QueryLimits,
+    PLUGIN_PANIC_HEADER,
+};
+use my_plugin_builder::{
+    build_plugin_for, demo,
+    errors::BuildError,
+    install::{promote_artifact, verify_checksum},
+    signing::TrustedKeys,
+    BuildOptions, PluginTarget,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::Instrument;
+use warp::{
+    body,
+    http::{self, StatusCode},
+    query, reject, Filter, Rejection, Reply,
+};
 
-use crate::{handle_rejection, Error, HandlerStorage};
+use crate::{
+    auth::{with_admin, with_client, Authenticator, Client},
+    check_shadow,
+    config::{Config, PreloadFailure},
+    handle_rejection,
+    health::{catch_panic, catch_panic_sync},
+    limits::{check_batch_request, check_query, QueryLimitSettings},
+    metrics::Metrics,
+    persisted::{PersistedBatchRequest, PersistedQueryStore},
+    ratelimit::{BucketKey, RateLimiter},
+    rejection_status,
+    schema_diff::{ChangeLevel, SchemaChange, SchemaDiff},
+    shadow::{ShadowRequest, ShadowResponse, ShadowStore},
+    telemetry,
+    tenant::{Tenant, Tenants},
+    timeout::ExecutionTimeouts,
+    wasm::WasmHandler,
+    worker::RemoteHandler,
+    Error, HandlerStorage, PluginSource, PrivateArtifact, SharedHandler, StagedHandler,
+    StorageLock,
+};
 
-type StateContext = Arc<RwLock<HandlerStorage>>;
+/// 共享的处理器存储器
+pub type StateContext = Arc<StorageLock>;
+type PersistedContext = Arc<PersistedQueryStore>;
+type RateLimitContext = Arc<RateLimiter>;
+type ShadowContext = Arc<ShadowStore>;
+
+/// graphql接口所需的服务状态
+#[derive(Clone)]
+struct ApiContext {
+    storage: StateContext,
+    persisted: PersistedContext,
+    limiter: RateLimitContext,
+    timeouts: Arc<ExecutionTimeouts>,
+    limits: Arc<QueryLimitSettings>,
+    tenants: Arc<Tenants>,
+    shadow: ShadowContext,
+    metrics: Arc<Metrics>,
+    auth: Arc<Authenticator>,
+}
+
+impl ApiContext {
+    /// 按配置创建鉴权、持久化查询的存储、限流器、执行超时与租户，指标与影子流量的比较结果与处理器存储器共用，影子流量的结果记录在同一指标中
+    fn from_config(config: &Config, storage: StateContext) -> Result<Self, Error> {
+        let auth = Arc::new(Authenticator::from_config(config)?);
+        let metrics = storage.metrics().clone();
+        let shadow = storage.shadow().clone();
+        metrics.set_configured_plugins(config.configured_plugins());
+        Ok(Self {
+            storage,
+            persisted: Arc::new(PersistedQueryStore::from_config(config)),
+            limiter: Arc::new(RateLimiter::from_config(config)),
+            timeouts: Arc::new(ExecutionTimeouts::from_config(config)),
+            limits: Arc::new(QueryLimitSettings::from_config(config)),
+            tenants: Arc::new(Tenants::from_config(config)),
+            shadow,
+            metrics,
+            auth,
+        })
+    }
+}
 
 /// 注入状态上下文context
 fn with_context(
@@ -19,175 +97,1173 @@ fn with_context(
     warp::any().map(move || ctx.clone())
 }
 
-// 检查否存在相应的动态链接包
-fn has_plugin_lib(name: &String) -> bool {
-    let path = format!("./libs/lib_{}.{}", name, get_lib_suffix());
-    Path::new(&path).exists()
+/// 注入持久化查询的存储
+fn with_persisted(
+    persisted: PersistedContext,
+) -> impl Filter<Extract = (PersistedContext,), Error = Infallible> + Clone {
+    warp::any().map(move || persisted.clone())
 }
 
-// 加载插件
-fn load_plugin_to_context(
-    path: &String,
-    guard: &mut RwLockWriteGuard<HandlerStorage>,
-) -> Result<(), Error> {
-    let lib = Library::new(path).map_err(|e| -> Error {
-        log::error!("{}", e);
-        Error::LoadLibError
-    })?;
-    let create_service: libloading::Symbol<fn() -> Box<dyn GraphqlRequestHandler + Send + Sync>> =
-        unsafe { lib.get(b"new_service") }.map_err(|e| -> Error {
+/// 注入限流器
+fn with_rate_limiter(
+    limiter: RateLimitContext,
+) -> impl Filter<Extract = (RateLimitContext,), Error = Infallible> + Clone {
+    warp::any().map(move || limiter.clone())
+}
+
+/// 注入配置的查询限制
+fn with_limits(
+    limits: Arc<QueryLimitSettings>,
+) -> impl Filter<Extract = (Arc<QueryLimitSettings>,), Error = Infallible> + Clone {
+    warp::any().map(move || limits.clone())
+}
+
+/// 注入指标
+fn with_metrics(
+    metrics: Arc<Metrics>,
+) -> impl Filter<Extract = (Arc<Metrics>,), Error = Infallible> + Clone {
+    warp::any().map(move || metrics.clone())
+}
+
+/// 注入影子流量的比较结果
+fn with_shadow(
+    shadow: ShadowContext,
+) -> impl Filter<Extract = (ShadowContext,), Error = Infallible> + Clone {
+    warp::any().map(move || shadow.clone())
+}
+
+/// 注入graphql接口所需的服务状态
+fn with_api_context(
+    api: ApiContext,
+) -> impl Filter<Extract = (ApiContext,), Error = Infallible> + Clone {
+    warp::any().map(move || api.clone())
+}
+
+/// 指定租户的请求头，路径中没有租户时使用
+pub const TENANT_HEADER: &str = "x-tenant";
+
+// 请求的租户：路径的最后一段，路径中没有时取请求头，都没有时为None
+fn tenant_segment() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::path::param::<String>()
+        .and(warp::path::end())
+        .map(Some)
+        .or(warp::path::end().and(warp::header::optional::<String>(TENANT_HEADER)))
+        .unify()
+}
+
+/// 指定插件版本的请求头，优先于租户固定使用的版本
+pub const VERSION_HEADER: &str = "x-plugin-version";
+
+// 解析请求的租户，租户不存在或者没有开启插件时返回404
+fn resolve_tenant(
+    api: &ApiContext,
+    tenant: Option<String>,
+    key: &PluginName,
+) -> Result<Tenant, Rejection> {
+    api.tenants
+        .resolve(tenant.as_deref(), key)
+        .map_err(warp::reject::custom)
+}
+
+// 选择请求使用的插件版本，返回金丝雀版本的版本名，None为稳定版本。
+// 请求头指定的版本优先，其次是租户固定使用的版本，都没有时按金丝雀版本的权重选择
+async fn select_version(
+    api: &ApiContext,
+    key: &PluginName,
+    tenant: &Tenant,
+    version: Option<String>,
+) -> Result<Option<String>, Rejection> {
+    let version = version.or_else(|| tenant.version.clone());
+    api.storage
+        .read()
+        .await
+        .select_version(key, version.as_deref())
+        .map_err(warp::reject::custom)
+}
+
+// 校验路径中的插件名称，不合法时返回400，名称在拼接路径之前校验
+fn plugin_name(name: String) -> Result<PluginName, Rejection> {
+    PluginName::new(name).map_err(|e| {
+        log::warn!("{}", e);
+        warp::reject::custom(Error::InvalidPluginName)
+    })
+}
+
+// 加载插件并添加到存储器，返回已加载的处理器，失败时记录到插件的健康状态。
+// 同一插件的加载按顺序执行，等待加载锁后插件已加载时直接返回；打开插件期间不持有存储器的锁，只在添加时短暂持有写锁
+#[tracing::instrument(skip(context))]
+async fn load_plugin_to_context(
+    name: &PluginName,
+    context: &StorageLock,
+) -> Result<SharedHandler, Error> {
+    let load_lock = context.read().await.load_lock(name);
+    let _loading = load_lock.lock().await;
+    let source = {
+        let read_guard = context.read().await;
+        if let Some(handler) = read_guard.get_handler(name.to_string()) {
+            return Ok(handler);
+        }
+        read_guard.plugin_source(name)
+    };
+    log::info!("to load handler {}", name);
+    let staged = open_plugin(&source).await;
+    let mut write_guard = context.write().await;
+    let result = staged.and_then(|staged| write_guard.add_staged(staged));
+    if let Err(e) = &result {
+        write_guard.record_load_error(name, e.to_string());
+    }
+    result?;
+    write_guard
+        .get_handler(name.to_string())
+        .ok_or(Error::HandlerNotFound)
+}
+
+// 加载前以副本的sha256校验插件，没有校验文件时按配置拒绝加载或记录警告
+fn verify_plugin_lib(path: &Path, checksum: &str, require_checksum: bool) -> Result<(), Error> {
+    match verify_checksum(path, checksum) {
+        Ok(true) => Ok(()),
+        Ok(false) if require_checksum => {
+            log::error!("missing checksum of {}", path.display());
+            Err(Error::ChecksumError)
+        }
+        Ok(false) => {
+            log::warn!(
+                "missing checksum of {}, loaded without verification",
+                path.display()
+            );
+            Ok(())
+        }
+        Err(e) => {
             log::error!("{}", e);
-            Error::LoadPluginError
-        })?;
-    guard.add_handler(create_service());
-    Ok(())
+            Err(Error::ChecksumError)
+        }
+    }
 }
 
-// 编译插件
-fn create_and_build_plugin(name: &String) -> Result<(), Error> {
-    if has_plugin_lib(name) {
-        Ok(())
-    } else if name == "foo" {
-        build_plugin(name.to_owned(), demo::foo::genernate_tokens())?;
-        Ok(())
-    } else if name == "bar" {
-        build_plugin(name.to_owned(), demo::bar::genernate_tokens())?;
-        Ok(())
-    } else {
-        Err(Error::DemoNotSupport)
+// 配置了受信任的公钥时，加载前以副本的sha256校验插件的签名，拒绝加载未签名或签名无效的插件，并记录审计日志
+fn verify_plugin_signature(
+    name: &str,
+    path: &Path,
+    checksum: &str,
+    keys: &TrustedKeys,
+) -> Result<(), Error> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    match keys.verify(path, checksum) {
+        Ok(key) => {
+            log::info!(
+                target: "audit",
+                "plugin {} from {} signed by {}",
+                name,
+                path.display(),
+                key
+            );
+            Ok(())
+        }
+        Err(e) => {
+            // 审计日志保持单行
+            let reason = match e {
+                BuildError::SignatureError(reason) => reason,
+                e => e.to_string(),
+            };
+            log::warn!(
+                target: "audit",
+                "refused to load plugin {} from {}: {}",
+                name,
+                path.display(),
+                reason
+            );
+            Err(Error::SignatureError)
+        }
+    }
+}
+
+// 校验并创建插件的处理器，不需要持有存储器的锁。插件先复制到私有目录，校验与加载的都是这份副本。
+// wasm插件在内嵌的wasm运行时中执行，配置了进程外执行的插件在工作进程中加载
+async fn open_plugin(source: &PluginSource) -> Result<StagedHandler, Error> {
+    let PluginSource { name, path, .. } = source;
+    // 复制与计算sha256比较耗时，在阻塞线程中执行
+    let artifact = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || PrivateArtifact::copy_of(&path))
+            .await
+            .map_err(|e| -> Error {
+                log::error!("copy plugin {} task failed: {}", name, e);
+                Error::LoadLibError
+            })??
+    };
+    verify_plugin_lib(path, artifact.checksum(), source.require_checksum)?;
+    verify_plugin_signature(name, path, artifact.checksum(), &source.trusted_keys)?;
+    let checksum = artifact.checksum().to_string();
+    if source.target == PluginTarget::Wasm {
+        // 编译wasm模块与生成schema都比较耗时，在阻塞线程中执行
+        let limits = source.wasm_limits;
+        let handler =
+            tokio::task::spawn_blocking(move || WasmHandler::load(artifact.path(), limits))
+                .await
+                .map_err(|e| -> Error {
+                    log::error!("load wasm plugin {} task failed: {}", name, e);
+                    Error::LoadPluginError
+                })??;
+        return Ok(StagedHandler::new(Box::new(handler)).with_checksum(checksum));
+    }
+    if let Some(limits) = source.worker_limits {
+        let handler = RemoteHandler::spawn(name, artifact, limits).await?;
+        return Ok(StagedHandler::new(Box::new(handler)).with_checksum(checksum));
+    }
+    // 动态链接包映射到内存后即可删除副本
+    StagedHandler::open_lib(artifact.path()).map(|staged| staged.with_checksum(checksum))
+}
+
+/// 重新加载插件的结果，changes为新旧schema之间的变更
+#[derive(Serialize)]
+struct ReloadReport {
+    plugin: String,
+    /// 是否已替换为新加载的插件
+    reloaded: bool,
+    forced: bool,
+    breaking: usize,
+    dangerous: usize,
+    safe: usize,
+    changes: Vec<SchemaChange>,
+}
+
+// 重新加载插件。已加载的插件先与新版本比较schema，有破坏性变更且未强制时保留已加载的插件。
+// 与加载共用加载锁，打开新版本与比较schema期间不持有存储器的锁，只在替换时短暂持有写锁
+async fn reload_plugin_in_context(
+    name: &PluginName,
+    context: &StateContext,
+    force: bool,
+) -> Result<ReloadReport, Error> {
+    let load_lock = context.read().await.load_lock(name);
+    let _loading = load_lock.lock().await;
+    let (previous, source) = {
+        let read_guard = context.read().await;
+        if !read_guard.has_lib(name) {
+            return Err(Error::NoSuchPluginError);
+        }
+        (
+            read_guard.get_handler(name.to_string()),
+            read_guard.plugin_source(name),
+        )
+    };
+    let staged = match open_plugin(&source).await {
+        Ok(staged) => staged,
+        Err(e) => {
+            context.write().await.record_load_error(name, e.to_string());
+            return Err(e);
+        }
+    };
+    let diff = match previous {
+        Some(previous) => {
+            let old_sdl = catch_panic_sync(|| previous.schema_sdl()).map_err(|message| {
+                log::error!("plugin {} panicked: {}", name, message);
+                Error::LoadPluginError
+            })?;
+            SchemaDiff::between(&old_sdl, &staged.schema_sdl()?)?
+        }
+        None => SchemaDiff::default(),
+    };
+    let blocked = diff.is_breaking() && !force;
+    let report = ReloadReport {
+        plugin: name.to_string(),
+        reloaded: !blocked,
+        forced: force,
+        breaking: diff.count(ChangeLevel::Breaking),
+        dangerous: diff.count(ChangeLevel::Dangerous),
+        safe: diff.count(ChangeLevel::Safe),
+        changes: diff.changes,
+    };
+    if blocked {
+        log::warn!(
+            "reload of plugin {} blocked by {} breaking schema changes",
+            name,
+            report.breaking
+        );
+        return Ok(report);
     }
+    context.write().await.add_staged(staged)?;
+    log::info!(
+        "plugin {} reloaded, {} breaking, {} dangerous, {} safe schema changes",
+        name,
+        report.breaking,
+        report.dangerous,
+        report.safe
+    );
+    Ok(report)
+}
+
+// 按插件的定义编译插件，并记录编译的耗时与结果
+fn create_and_build_plugin(
+    name: &PluginName,
+    target: PluginTarget,
+    options: &BuildOptions,
+    metrics: &Metrics,
+) -> Result<(), Error> {
+    let definition = demo::definition(name, target).ok_or(Error::DemoNotSupport)?;
+    let start = Instant::now();
+    let result = build_plugin_for(
+        name.clone(),
+        definition.tokens,
+        &definition.dependencies,
+        target,
+        options,
+    );
+    metrics.observe_build(name, target.as_str(), result.is_ok(), start.elapsed());
+    result?;
+    Ok(())
 }
 
-// 在使用时判断载入context
-async fn load_plugin_on_use(name: &String, lock: &RwLock<HandlerStorage>) -> Result<(), Error> {
-    let has_handler = {
+// 在使用时判断载入context，返回已加载的处理器
+#[tracing::instrument(skip(lock))]
+async fn load_plugin_on_use(name: &PluginName, lock: &StorageLock) -> Result<SharedHandler, Error> {
+    {
         //这里需要注意！读写锁不能同时存在，这里读锁仅为了判断是否存在handler
         //所以读完就要清理读锁
         //放入block中，离开block就自动清理读锁
         let read_guard = lock.read().await;
-        read_guard.has_handler(name.to_owned())
-    };
-    if has_handler {
-        Ok(())
-    } else {
-        if has_plugin_lib(name) {
-            log::info!("to load handler {}", &name);
-            let mut write_guard = lock.write().await;
-            let path = format!("./libs/lib_{}.{}", name, get_lib_suffix());
-            load_plugin_to_context(&path, &mut write_guard)?;
-            Ok(())
+        if read_guard.is_quarantined(name) {
+            return Err(Error::PluginQuarantined);
+        }
+        if let Some(handler) = read_guard.get_handler(name.to_string()) {
+            return Ok(handler);
+        }
+        if !read_guard.has_lib(name) {
+            return Err(Error::NoSuchPluginError);
+        }
+    }
+    load_plugin_to_context(name, lock).await
+}
+
+// 从存储器中取出处理器，取出后即释放读锁，请求执行期间不持有锁。
+// version为金丝雀版本的版本名，None时取稳定版本，未加载时按需加载
+#[tracing::instrument(skip(context))]
+async fn checkout_handler(
+    key: &PluginName,
+    version: Option<&str>,
+    context: &StateContext,
+) -> Result<(SharedHandler, String), Rejection> {
+    let handler = match version {
+        Some(version) => context
+            .read()
+            .await
+            .get_canary(key, version)
+            .ok_or(Error::NoSuchVersion),
+        None => load_plugin_on_use(key, context).await,
+    }
+    .map_err(warp::reject::custom)?;
+    let schema_hash = handler.schema_hash().to_string();
+    Ok((handler, schema_hash))
+}
+
+// 插件panic时记录健康状态，连续panic达到阈值时隔离并卸载插件
+async fn plugin_panicked(
+    key: &str,
+    handler: &SharedHandler,
+    context: &StateContext,
+    message: String,
+) -> http::Response<Vec<u8>> {
+    record_plugin_panic(key, handler, context, message.clone()).await;
+    plugin_panic_response(message)
+}
+
+async fn record_plugin_panic(
+    key: &str,
+    handler: &SharedHandler,
+    context: &StateContext,
+    message: String,
+) {
+    log::error!(
+        "plugin {} version {} panicked: {}",
+        key,
+        handler.version(),
+        &message
+    );
+    if handler.health().record_panic(message) {
+        let mut write_guard = context.write().await;
+        // 连续panic的金丝雀版本只回滚该版本，不影响稳定版本
+        if write_guard.is_canary(key, handler) {
+            log::error!(
+                "canary {} of plugin {} keeps panicking, rolled back",
+                handler.version(),
+                key
+            );
+            let _ = write_guard.rollback(key, Some(handler.version()));
+        } else if write_guard.is_stable(key, handler) {
+            log::error!("plugin {} keeps panicking, quarantined and unloaded", key);
+            write_guard.remove_handler(key.to_string());
+        } else if !write_guard.has_handler(key.to_string()) {
+            log::error!("plugin {} keeps panicking, quarantined", key);
         } else {
-            Err(Error::NoSuchPluginError)
+            // 请求执行期间插件已被重新加载，新的处理器不受旧处理器的panic影响
+            log::warn!(
+                "replaced handler of plugin {} keeps panicking, current handler kept",
+                key
+            );
+            handler.health().release();
+        }
+    }
+}
+
+// 执行插件的请求：超时控制、捕获panic，并记录插件的健康状态
+async fn execute_plugin<F>(
+    api: &ApiContext,
+    key: &str,
+    handler: &SharedHandler,
+    fut: F,
+) -> Result<http::Response<Vec<u8>>, Rejection>
+where
+    F: Future<Output = Result<http::Response<Vec<u8>>, Rejection>>,
+{
+    let _in_flight = handler.health().start_request();
+    let fut = fut.instrument(tracing::info_span!("execute_plugin", plugin = %key));
+    match api.timeouts.execute(key, catch_panic(fut)).await {
+        Ok(Ok(Ok(response))) => {
+            let mut response = detach_response(response);
+            // 插件内部捕获的panic通过响应头传递
+            match response.headers_mut().remove(PLUGIN_PANIC_HEADER) {
+                Some(message) => {
+                    let message = message.to_str().unwrap_or_default().to_string();
+                    record_plugin_panic(key, handler, &api.storage, message).await;
+                }
+                None => handler.health().record_success(),
+            }
+            Ok(response)
+        }
+        Ok(Ok(Err(rejection))) => {
+            handler.health().record_success();
+            Err(rejection)
         }
+        Ok(Err(message)) => Ok(plugin_panicked(key, handler, &api.storage, message).await),
+        Err(timeout_response) => Ok(timeout_response),
     }
 }
 
-async fn build_plugin_handler(name: String) -> Result<impl Reply, Rejection> {
-    create_and_build_plugin(&name).map_err(|e| warp::reject::custom(e))?;
+// 执行请求。稳定版本的查询按采样将请求的副本异步发送到影子版本，比较两者的响应，
+// 影子版本的响应不会返回给客户端。变更可能有副作用，不会被复制
+async fn execute_with_shadow(
+    api: &ApiContext,
+    key: &PluginName,
+    tenant: &Tenant,
+    version: Option<&str>,
+    handler: &SharedHandler,
+    context: DataContext,
+    request: ShadowRequest,
+) -> Result<http::Response<Vec<u8>>, Rejection> {
+    let shadows = match version {
+        None => api.storage.read().await.shadow_targets(key),
+        Some(_) => Vec::new(),
+    };
+    if shadows.is_empty() || !request.is_read_only() {
+        return execute_plugin(api, key, handler, request.execute(handler, context)).await;
+    }
+    let mirror = (context.clone(), request.clone());
+    let start = Instant::now();
+    let result = execute_plugin(api, key, handler, request.execute(handler, context)).await;
+    let primary = Arc::new(ShadowResponse::from_result(&result, start.elapsed()));
+    for shadow in shadows {
+        // 同时执行的影子请求过多时丢弃采样，不再创建任务
+        let permit = match api.shadow.try_acquire(key, shadow.version()) {
+            Some(permit) => permit,
+            None => continue,
+        };
+        let (context, request) = mirror.clone();
+        let mirror = mirror_request(
+            api.clone(),
+            key.clone(),
+            tenant.name.clone(),
+            shadow,
+            context,
+            request,
+            primary.clone(),
+        );
+        tokio::spawn(async move {
+            mirror.await;
+            drop(permit);
+        });
+    }
+    result
+}
+
+// 在影子版本中执行请求的副本，与稳定版本的响应比较后记录结果。影子版本的panic同样计入其健康状态
+async fn mirror_request(
+    api: ApiContext,
+    key: PluginName,
+    tenant: String,
+    handler: SharedHandler,
+    context: DataContext,
+    request: ShadowRequest,
+    primary: Arc<ShadowResponse>,
+) {
+    let query = request.query();
+    let start = Instant::now();
+    let result = execute_plugin(&api, &key, &handler, request.execute(&handler, context)).await;
+    let shadow = ShadowResponse::from_result(&result, start.elapsed());
+    let matched = api
+        .shadow
+        .record(&key, handler.version(), &tenant, query, &primary, &shadow);
+    api.metrics
+        .observe_shadow(&key, handler.version(), matched, shadow.elapsed);
+}
+
+// 将插件构造的响应复制为主服务的数据。响应头的值引用了插件代码中的虚表，插件卸载后再释放会导致段错误
+fn detach_response(response: http::Response<Vec<u8>>) -> http::Response<Vec<u8>> {
+    let (parts, body) = response.into_parts();
+    let mut builder = http::Response::builder()
+        .status(parts.status)
+        .version(parts.version);
+    for (name, value) in parts.headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder.body(body).expect("response is valid")
+}
+
+// 获取插件生效的查询限制，配置的限制优先于插件声明的限制
+async fn plugin_limits(
+    api: &ApiContext,
+    key: &str,
+    handler: &SharedHandler,
+) -> Result<QueryLimits, http::Response<Vec<u8>>> {
+    match catch_panic_sync(|| handler.metadata()) {
+        Ok(metadata) => Ok(api.limits.limits_of(key, metadata.limits)),
+        Err(message) => Err(plugin_panicked(key, handler, &api.storage, message).await),
+    }
+}
+
+// 按存储器的编译目标与目录编译插件，已存在接口版本一致的动态链接包时跳过，force为true时强制重新编译。
+// 指定version时编译为该金丝雀版本的编译产物，稳定版本的编译产物不变。
+// 同一插件的编译按顺序执行，等待编译锁后再判断是否需要编译，编译在阻塞线程中执行
+async fn build_plugin_in_context(
+    name: &PluginName,
+    context: &StateContext,
+    force: bool,
+    version: Option<&str>,
+) -> Result<(), Error> {
+    let (target, options, metrics, build_lock) = {
+        let read_guard = context.read().await;
+        let options = match version {
+            Some(version) => read_guard.canary_build_options(name, version)?,
+            None => read_guard.build_options().clone(),
+        };
+        (
+            read_guard.plugin_target(name),
+            options,
+            read_guard.metrics().clone(),
+            read_guard.build_lock(name),
+        )
+    };
+    let _build_guard = build_lock.lock().await;
+    if !force && options.is_up_to_date(name, target) {
+        return Ok(());
+    }
+    let name = name.clone();
+    tokio::task::spawn_blocking(move || create_and_build_plugin(&name, target, &options, &metrics))
+        .await
+        .map_err(|e| -> Error {
+            log::error!("build task failed: {}", e);
+            Error::BuildError(BuildError::BuildProjectError(e.to_string()))
+        })?
+}
+
+/// 编译接口的参数
+#[derive(Deserialize)]
+struct BuildQuery {
+    /// 已存在动态链接包时依然重新编译
+    #[serde(default)]
+    force: bool,
+    /// 编译为金丝雀版本，编译产物为`lib_<插件名>@<版本名>.<后缀>`
+    version: Option<String>,
+}
+
+async fn build_plugin_handler(
+    name: String,
+    query: BuildQuery,
+    context: StateContext,
+) -> Result<impl Reply, Rejection> {
+    let name = plugin_name(name)?;
+    build_plugin_in_context(&name, &context, query.force, query.version.as_deref())
+        .await
+        .map_err(warp::reject::custom)?;
     Ok(warp::reply::json(&"ok"))
 }
 
+/// 加载预加载与常驻的插件，动态链接包不存在时先编译，避免第一个请求承担加载的耗时与错误。
+/// 启动时按`preload_failure`处理失败，配置热加载新增的插件失败时只记录日志
+pub async fn preload_plugins(
+    context: &StateContext,
+    plugins: &[PluginName],
+    failure: PreloadFailure,
+) -> Result<(), Error> {
+    for name in plugins.iter() {
+        let result = match build_plugin_in_context(name, context, false, None).await {
+            Ok(()) => load_plugin_on_use(name, context).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        context
+            .write()
+            .await
+            .set_preload_failed(name, result.is_err() && failure == PreloadFailure::Warn);
+        match (result, failure) {
+            (Ok(()), _) => log::info!("plugin {} preloaded", name),
+            (Err(e), PreloadFailure::Fail) => {
+                log::error!("preload plugin {} failed: {}", name, e);
+                return Err(e);
+            }
+            (Err(e), PreloadFailure::Warn) => {
+                log::warn!("preload plugin {} failed, load on use: {}", name, e)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 控制接口的参数
+#[derive(Deserialize)]
+struct ControlQuery {
+    /// 重新加载时忽略schema的破坏性变更
+    #[serde(default)]
+    force: bool,
+    /// 金丝雀版本的版本名
+    version: Option<String>,
+    /// 金丝雀版本的权重，即分配到的请求百分比
+    weight: Option<u32>,
+    /// 稳定版本的请求中复制到金丝雀版本作为影子流量的百分比
+    shadow: Option<u32>,
+}
+
+// 加载该版本的编译产物`lib_<插件名>@<版本名>.<后缀>`作为插件的金丝雀版本，稳定版本不受影响。版本已加载时只修改权重与影子流量的比例。
+// 金丝雀版本的操作与加载共用加载锁，打开编译产物期间不持有存储器的锁，只在添加时短暂持有写锁
+async fn load_canary_in_context(
+    name: &PluginName,
+    context: &StateContext,
+    control: ControlQuery,
+) -> Result<(), Error> {
+    let ControlQuery {
+        version,
+        weight,
+        shadow,
+        ..
+    } = control;
+    let load_lock = context.read().await.load_lock(name);
+    let _loading = load_lock.lock().await;
+    let (version, source) = {
+        let read_guard = context.read().await;
+        if let Some(version) = version.as_deref() {
+            if read_guard.has_canary(name, version) {
+                drop(read_guard);
+                if weight.is_none() && shadow.is_none() {
+                    return Err(Error::VersionConflict);
+                }
+                return context
+                    .write()
+                    .await
+                    .update_canary(name, version, weight, shadow);
+            }
+        }
+        if !read_guard.has_handler(name.to_string()) {
+            return Err(Error::HandlerNotFound);
+        }
+        // 金丝雀版本的编译产物与版本名对应，必须指定版本名
+        let version = version.ok_or(Error::InvalidVersion)?;
+        let source = read_guard.canary_source(name, &version)?;
+        // 打开编译产物之前先校验权重与影子流量的比例
+        read_guard.check_weight(name, &version, weight.unwrap_or(0))?;
+        check_shadow(shadow.unwrap_or(0))?;
+        if !source.path.exists() {
+            log::warn!("no artifact {} of plugin {}", source.path.display(), name);
+            return Err(Error::NoSuchPluginError);
+        }
+        (version, source)
+    };
+    let staged = open_plugin(&source).await?;
+    let version = context.write().await.add_canary(
+        name,
+        Some(version),
+        weight.unwrap_or(0),
+        shadow.unwrap_or(0),
+        staged,
+    )?;
+    log::info!("canary {} of plugin {} loaded", version, name);
+    Ok(())
+}
+
+// 将金丝雀版本提升为稳定版本：先将该版本的编译产物安装为稳定版本的编译产物，再替换已加载的稳定版本，
+// 之后卸载、重新加载时加载的都是提升后的版本。安装在阻塞线程中执行，不持有存储器的锁
+async fn promote_canary_in_context(
+    name: &PluginName,
+    context: &StateContext,
+    version: Option<&str>,
+) -> Result<String, Error> {
+    let load_lock = context.read().await.load_lock(name);
+    let _loading = load_lock.lock().await;
+    let (version, checksum, src, dest) = {
+        let read_guard = context.read().await;
+        let version = read_guard.canary_version(name, version)?;
+        let checksum = read_guard.canary_checksum(name, &version);
+        let source = read_guard.canary_source(name, &version)?;
+        let dest = read_guard.lib_path(name, source.target);
+        (version, checksum, source.path, dest)
+    };
+    let checksum = checksum.ok_or_else(|| -> Error {
+        log::error!(
+            "canary {} of plugin {} has no artifact checksum",
+            version,
+            name
+        );
+        Error::ChecksumError
+    })?;
+    tokio::task::spawn_blocking(move || promote_artifact(&src, &dest, &checksum))
+        .await
+        .map_err(|e| -> Error {
+            log::error!("promote artifact task failed: {}", e);
+            Error::LoadLibError
+        })?
+        .map_err(|e| -> Error {
+            log::error!(
+                "promote canary {} of plugin {} failed: {}",
+                version,
+                name,
+                e
+            );
+            match e {
+                BuildError::ChecksumMismatch(_) => Error::ChecksumError,
+                e => Error::BuildError(e),
+            }
+        })?;
+    context.write().await.promote(name, Some(&version))
+}
+
 async fn contro_context_handle(
     add_or_remove: String,
     handler_key: String,
+    control: ControlQuery,
     context: StateContext,
-) -> Result<impl Reply, Rejection> {
+    limits: Arc<QueryLimitSettings>,
+) -> Result<warp::reply::Response, Rejection> {
+    let handler_key = plugin_name(handler_key)?;
     if add_or_remove == "add" {
-        let has_handler = {
+        let (has_handler, has_lib) = {
             let read_guard = context.read().await;
-            read_guard.has_handler(handler_key.clone())
+            // 手动加载时解除隔离
+            if let Some(health) = read_guard.health(&handler_key) {
+                health.release();
+            }
+            (
+                read_guard.has_handler(handler_key.to_string()),
+                read_guard.has_lib(&handler_key),
+            )
         };
         if has_handler {
-            Ok(warp::reply::json(&"already has handler"))
-        } else if has_plugin_lib(&handler_key) {
-            let mut write_guard = context.write().await;
-            let path = format!("./libs/lib_{}.{}", &handler_key, get_lib_suffix());
-            load_plugin_to_context(&path, &mut write_guard).map_err(|e| warp::reject::custom(e))?;
-            Ok(warp::reply::json(&"ok"))
+            Ok(warp::reply::json(&"already has handler").into_response())
+        } else if has_lib {
+            load_plugin_to_context(&handler_key, &context)
+                .await
+                .map_err(warp::reject::custom)?;
+            Ok(warp::reply::json(&"ok").into_response())
         } else {
             Err(warp::reject::custom(Error::NoSuchPluginError))
         }
     } else if add_or_remove == "remove" {
         let mut write_guard = context.write().await;
         write_guard.remove_handler(handler_key.to_string());
-        Ok(warp::reply::json(&"ok"))
+        Ok(warp::reply::json(&"ok").into_response())
+    } else if add_or_remove == "reload" {
+        let report = reload_plugin_in_context(&handler_key, &context, control.force)
+            .await
+            .map_err(warp::reject::custom)?;
+        // 被阻止的重新加载返回409，响应体依然是变更的报告
+        let status = if report.reloaded {
+            StatusCode::OK
+        } else {
+            StatusCode::CONFLICT
+        };
+        Ok(warp::reply::with_status(warp::reply::json(&report), status).into_response())
+    } else if add_or_remove == "canary" {
+        load_canary_in_context(&handler_key, &context, control)
+            .await
+            .map_err(warp::reject::custom)?;
+        Ok(warp::reply::json(&context.read().await.status(&handler_key)).into_response())
+    } else if add_or_remove == "promote" {
+        let version = promote_canary_in_context(&handler_key, &context, control.version.as_deref())
+            .await
+            .map_err(warp::reject::custom)?;
+        log::info!("canary {} of plugin {} promoted", version, handler_key);
+        Ok(warp::reply::json(&context.read().await.status(&handler_key)).into_response())
+    } else if add_or_remove == "rollback" {
+        // 回滚只卸载金丝雀版本，稳定版本的编译产物不变
+        let load_lock = context.read().await.load_lock(&handler_key);
+        let _loading = load_lock.lock().await;
+        let mut write_guard = context.write().await;
+        let versions = write_guard
+            .rollback(&handler_key, control.version.as_deref())
+            .map_err(warp::reject::custom)?;
+        log::info!(
+            "canaries {:?} of plugin {} rolled back",
+            versions,
+            handler_key
+        );
+        Ok(warp::reply::json(&write_guard.status(&handler_key)).into_response())
+    } else if add_or_remove == "metadata" {
+        let (handler, _) = checkout_handler(&handler_key, None, &context).await?;
+        let mut metadata = catch_panic_sync(|| handler.metadata())
+            .map_err(|_| warp::reject::custom(Error::LoadPluginError))?;
+        metadata.limits = limits.limits_of(&handler_key, metadata.limits);
+        Ok(warp::reply::json(&metadata).into_response())
     } else {
         Err(reject())
     }
 }
 
+// 设置数据上下文的租户与调用方身份，并校验调用方是否允许调用该插件、访问该租户以及是否超出限流
+fn prepare_data_context(
+    mut data_context: DataContext,
+    tenant: &Tenant,
+    key: &str,
+    client: Client,
+    limiter: &RateLimiter,
+) -> Result<DataContext, Rejection> {
+    if let Some(caller) = &client.caller {
+        if !caller.can_call(key) {
+            return Err(warp::reject::custom(Error::Forbidden));
+        }
+        if !caller.can_use_tenant(&tenant.name) {
+            log::warn!(
+                "{} is not allowed to use tenant {}",
+                caller.identity.subject,
+                tenant.name
+            );
+            return Err(warp::reject::custom(Error::Forbidden));
+        }
+    }
+    let bucket = BucketKey {
+        plugin: key.to_string(),
+        client: client.rate_limit_id(),
+        tenant: tenant.name.clone(),
+    };
+    limiter.check(bucket).map_err(|retry_after| {
+        warp::reject::custom(Error::RateLimited(retry_after.as_secs_f64().ceil() as u64))
+    })?;
+    data_context.tenant(&tenant.name, &tenant.dataset);
+    data_context.identity(client.caller.map(|c| c.identity));
+    Ok(data_context)
+}
+
+// 记录graphql请求的数量、耗时与错误，被拒绝的请求按最终响应的状态码记录
+async fn observe_graphql<F, R>(
+    metrics: &Metrics,
+    key: &str,
+    version: Option<&str>,
+    tenant: &str,
+    fut: F,
+) -> Result<warp::reply::Response, Rejection>
+where
+    F: Future<Output = Result<R, Rejection>>,
+    R: Reply,
+{
+    let start = Instant::now();
+    let result = fut.await.map(Reply::into_response);
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(rejection) => rejection_status(rejection).0,
+    };
+    metrics.observe_request(key, version, tenant, status, start.elapsed());
+    result
+}
+
 async fn graphql_get_handler(
     key: String,
-    flag: bool,
-    context: StateContext,
+    tenant: Option<String>,
+    version: Option<String>,
+    api: ApiContext,
+    client: Client,
     data_context: DataContext,
     qry: HashMap<String, String>,
 ) -> Result<impl Reply, Rejection> {
-    let mut dc = data_context;
-    dc.flag(flag);
+    let key = plugin_name(key)?;
+    let tenant = resolve_tenant(&api, tenant, &key)?;
+    let version = select_version(&api, &key, &tenant, version).await?;
+    let metrics = api.metrics.clone();
+    let name = tenant.name.clone();
+    let fut = execute_graphql_get(
+        key.clone(),
+        tenant,
+        version.clone(),
+        api,
+        client,
+        data_context,
+        qry,
+    );
+    observe_graphql(&metrics, &key, version.as_deref(), &name, fut).await
+}
 
-    load_plugin_on_use(&key, &context)
-        .await
-        .map_err(|e| warp::reject::custom(e))?;
-    let read_guard = context.read().await;
-    read_guard
-        .get_handler(key)
-        .unwrap()
-        .get_request_handle(dc, qry)
-        .await
+async fn execute_graphql_get(
+    key: PluginName,
+    tenant: Tenant,
+    version: Option<String>,
+    api: ApiContext,
+    client: Client,
+    data_context: DataContext,
+    mut qry: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    let dc = prepare_data_context(data_context, &tenant, &key, client, &api.limiter)?;
+
+    let (handler, schema_hash) = checkout_handler(&key, version.as_deref(), &api.storage).await?;
+    if let Err(e) = api.persisted.resolve_get(&key, &schema_hash, &mut qry) {
+        return Ok(e.into_response());
+    }
+    let limits = match plugin_limits(&api, &key, &handler).await {
+        Ok(limits) => limits,
+        Err(response) => return Ok(response),
+    };
+    let query = qry.get("query").map(String::as_str).unwrap_or_default();
+    if let Err(e) = check_query(query, &limits) {
+        return Ok(e.into_response());
+    }
+    let request = ShadowRequest::Get(qry);
+    execute_with_shadow(
+        &api,
+        &key,
+        &tenant,
+        version.as_deref(),
+        &handler,
+        dc,
+        request,
+    )
+    .await
 }
 
-async fn graphql_post_json_handler(
+/// POST请求的body，按content-type区分
+enum PostBody {
+    Json(PersistedBatchRequest),
+    Graphql(Bytes),
+}
+
+// content-type为application/graphql时body为查询原文，否则按json解析。只解析body，鉴权与限流在处理请求时执行一次
+fn post_body() -> impl Filter<Extract = (PostBody,), Error = Rejection> + Clone {
+    let graphql = warp::header::<String>("content-type")
+        .and_then(|content_type: String| async move {
+            if content_type
+                .to_ascii_lowercase()
+                .starts_with("application/graphql")
+            {
+                Ok(())
+            } else {
+                Err(reject())
+            }
+        })
+        .untuple_one()
+        .and(body::bytes())
+        .map(PostBody::Graphql);
+    let json = body::json().map(PostBody::Json);
+    graphql.or(json).unify()
+}
+
+async fn graphql_post_handler(
     key: String,
-    flag: bool,
-    context: StateContext,
+    tenant: Option<String>,
+    version: Option<String>,
+    api: ApiContext,
+    client: Client,
+    data_context: DataContext,
+    body: PostBody,
+) -> Result<warp::reply::Response, Rejection> {
+    let key = plugin_name(key)?;
+    let tenant = resolve_tenant(&api, tenant, &key)?;
+    let version = select_version(&api, &key, &tenant, version).await?;
+    let metrics = api.metrics.clone();
+    let name = tenant.name.clone();
+    let (fut_key, fut_version) = (key.clone(), version.clone());
+    let fut = async move {
+        match body {
+            PostBody::Json(req) => execute_graphql_post_json(
+                fut_key,
+                tenant,
+                fut_version,
+                api,
+                client,
+                data_context,
+                req,
+            )
+            .await
+            .map(Reply::into_response),
+            PostBody::Graphql(body) => execute_graphql_post_graphql(
+                fut_key,
+                tenant,
+                fut_version,
+                api,
+                client,
+                data_context,
+                body,
+            )
+            .await
+            .map(Reply::into_response),
+        }
+    };
+    observe_graphql(&metrics, &key, version.as_deref(), &name, fut).await
+}
+
+async fn execute_graphql_post_json(
+    key: PluginName,
+    tenant: Tenant,
+    version: Option<String>,
+    api: ApiContext,
+    client: Client,
     data_context: DataContext,
-    req: GraphQLBatchRequest<DefaultScalarValue>,
+    req: PersistedBatchRequest,
 ) -> Result<impl Reply, Rejection> {
-    let mut dc = data_context;
-    dc.flag(flag);
+    let dc = prepare_data_context(data_context, &tenant, &key, client, &api.limiter)?;
 
-    load_plugin_on_use(&key, &context)
-        .await
-        .map_err(|e| warp::reject::custom(e))?;
-    let read_guard = context.read().await;
-    read_guard
-        .get_handler(key)
-        .unwrap()
-        .post_json_request_handle(dc, req)
-        .await
+    let (handler, schema_hash) = checkout_handler(&key, version.as_deref(), &api.storage).await?;
+    let req = match api.persisted.resolve_batch(&key, &schema_hash, req) {
+        Ok(req) => req,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let limits = match plugin_limits(&api, &key, &handler).await {
+        Ok(limits) => limits,
+        Err(response) => return Ok(response),
+    };
+    if let Err(e) = check_batch_request(&req, &limits) {
+        return Ok(e.into_response());
+    }
+    let request = ShadowRequest::PostJson(req);
+    execute_with_shadow(
+        &api,
+        &key,
+        &tenant,
+        version.as_deref(),
+        &handler,
+        dc,
+        request,
+    )
+    .await
 }
 
-async fn graphql_post_graphql_handler(
-    key: String,
-    flag: bool,
-    context: StateContext,
+async fn execute_graphql_post_graphql(
+    key: PluginName,
+    tenant: Tenant,
+    version: Option<String>,
+    api: ApiContext,
+    client: Client,
     data_context: DataContext,
     body: Bytes,
 ) -> Result<impl Reply, Rejection> {
-    let mut dc = data_context;
-    dc.flag(flag);
+    let dc = prepare_data_context(data_context, &tenant, &key, client, &api.limiter)?;
 
-    load_plugin_on_use(&key, &context)
-        .await
-        .map_err(|e| warp::reject::custom(e))?;
+    let (handler, schema_hash) = checkout_handler(&key, version.as_deref(), &api.storage).await?;
+    let query = std::str::from_utf8(body.as_ref()).unwrap_or_default();
+    if let Err(e) = api
+        .persisted
+        .resolve(&key, &schema_hash, Some(query.to_string()), None)
+    {
+        return Ok(e.into_response());
+    }
+    let limits = match plugin_limits(&api, &key, &handler).await {
+        Ok(limits) => limits,
+        Err(response) => return Ok(response),
+    };
+    if let Err(e) = check_query(query, &limits) {
+        return Ok(e.into_response());
+    }
+    let request = ShadowRequest::PostGraphql(body);
+    execute_with_shadow(
+        &api,
+        &key,
+        &tenant,
+        version.as_deref(),
+        &handler,
+        dc,
+        request,
+    )
+    .await
+}
+
+/// 注册持久化查询的参数
+#[derive(Deserialize)]
+struct PersistedQuery {
+    /// 为金丝雀版本的schema注册查询，不指定时为稳定版本
+    version: Option<String>,
+}
+
+async fn register_persisted_handler(
+    key: String,
+    query: PersistedQuery,
+    context: StateContext,
+    persisted: PersistedContext,
+    queries: Vec<String>,
+) -> Result<impl Reply, Rejection> {
+    let key = plugin_name(key)?;
+    let (_, schema_hash) = checkout_handler(&key, query.version.as_deref(), &context).await?;
+    let hashes: Vec<String> = queries
+        .into_iter()
+        .map(|query| persisted.register(&key, &schema_hash, query))
+        .collect();
+    Ok(warp::reply::json(&hashes))
+}
+
+// 已配置的预加载插件全部加载后才就绪
+async fn readiness_handler(context: StateContext) -> Result<impl Reply, Rejection> {
+    let missing = context.read().await.missing_preloads();
+    let status = if missing.is_empty() {
+        http::StatusCode::OK
+    } else {
+        http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({ "ready": missing.is_empty(), "missing": missing });
+    Ok(warp::reply::with_status(warp::reply::json(&body), status))
+}
+
+async fn plugin_health_handler(
+    name: String,
+    context: StateContext,
+) -> Result<impl Reply, Rejection> {
+    let name = plugin_name(name)?;
     let read_guard = context.read().await;
-    read_guard
-        .get_handler(key)
-        .unwrap()
-        .post_grqphql_request_handle(dc, body)
-        .await
+    if read_guard.health(&name).is_none() && !read_guard.has_lib(&name) {
+        return Err(warp::reject::not_found());
+    }
+    Ok(warp::reply::json(&read_guard.status(&name)))
+}
+
+async fn rate_limits_handler(limiter: RateLimitContext) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&limiter.snapshot()))
+}
+
+async fn shadow_handler(shadow: ShadowContext) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&shadow.snapshot()))
+}
+
+async fn metrics_handler(metrics: Arc<Metrics>) -> Result<impl Reply, Rejection> {
+    let (content_type, body) = metrics.render();
+    let response = http::Response::builder()
+        .header("content-type", content_type)
+        .body(body)
+        .expect("response is valid");
+    Ok(response)
 }
 
 async fn graphiql_handler(
     key: String,
-    flag: bool,
-    context: StateContext,
+    tenant: Option<String>,
+    api: ApiContext,
+    client: Client,
 ) -> Result<impl Reply, Rejection> {
-    load_plugin_on_use(&key, &context)
+    let key = plugin_name(key)?;
+    if let Some(caller) = &client.caller {
+        if !caller.can_call(&key) {
+            return Err(warp::reject::custom(Error::Forbidden));
+        }
+    }
+    let tenant = resolve_tenant(&api, tenant, &key)?;
+    if let Some(caller) = &client.caller {
+        if !caller.can_use_tenant(&tenant.name) {
+            return Err(warp::reject::custom(Error::Forbidden));
+        }
+    }
+    load_plugin_on_use(&key, &api.storage)
         .await
-        .map_err(|e| warp::reject::custom(e))?;
-    let graphql_url = format!("/api/{}/graphql/{}", key, flag);
+        .map_err(warp::reject::custom)?;
+    let graphql_url = format!("/api/{}/graphql/{}", key, tenant.name);
     let html_body =
         juniper::http::graphiql::graphiql_source(graphql_url.as_str(), None).into_bytes();
     let html = http::Response::builder()
@@ -197,84 +1273,263 @@ async fn graphiql_handler(
     Ok(html)
 }
 
-// 定时清理context的函数，没10秒清理一次
+// 定时按卸载策略清理context，每次清理前重新读取策略，热加载后立即生效
 async fn clean_context(context: StateContext) {
     loop {
+        let interval = context.read().await.eviction().interval();
+        tokio::time::sleep(interval).await;
         log::info!("start handler storage cleans...");
-        {
-            let mut write_guard = context.write().await;
-            write_guard.remove_handler("foo".to_string());
-            write_guard.remove_handler("bar".to_string());
-        }
-        log::info!("end handler storage cleans");
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await
+        let evicted = context.write().await.evict();
+        log::info!("end handler storage cleans, evicted: {:?}", evicted);
     }
 }
 
-pub async fn run() {
-    dotenv().ok();
-    pretty_env_logger::init();
-    let server_addr = std::env::var("SERVER_ADDR").expect("missing env variable");
-    let addr: SocketAddr = server_addr.parse().expect("unable to parse socket address");
+// 配置文件的修改时间，文件不存在时为None
+fn config_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
 
-    let ctx = Arc::new(RwLock::new(HandlerStorage::new()));
+// 鉴权配置文件的修改时间，未配置时为None
+fn auth_modified(auth: &Authenticator) -> Option<SystemTime> {
+    auth.path().as_deref().and_then(config_modified)
+}
 
-    // 定时清理context
-    let con_ctx = ctx.clone();
-    tokio::spawn(async move { clean_context(con_ctx.clone()).await });
+// 定时检查配置文件以及鉴权配置文件是否修改，修改后热加载可以在运行时修改的配置，其余配置需要重启才能生效，
+// 需要重启的配置只与上一次加载的配置比较，每次修改只提示一次
+async fn watch_config(api: ApiContext, mut config: Config) {
+    let path = match Config::file_path() {
+        Some(path) if config.reload_interval_secs > 0 => path,
+        _ => return,
+    };
+    let interval = Duration::from_secs(config.reload_interval_secs);
+    let mut modified = config_modified(&path);
+    let mut auth_modified_at = auth_modified(&api.auth);
+    loop {
+        tokio::time::sleep(interval).await;
+        let current = config_modified(&path);
+        if current == modified {
+            // 配置文件未修改时，单独检查鉴权配置文件
+            let auth_current = auth_modified(&api.auth);
+            if auth_current != auth_modified_at {
+                auth_modified_at = auth_current;
+                if api.auth.reload(&config).is_ok() {
+                    log::info!("auth config reloaded");
+                }
+            }
+            continue;
+        }
+        modified = current;
+        let new_config = match Config::load() {
+            Ok(new_config) => new_config,
+            Err(_) => {
+                log::error!("config {} not reloaded", path.display());
+                continue;
+            }
+        };
+        for change in config.restart_required_changes(&new_config) {
+            log::warn!("config {} changed, restart required to take effect", change);
+        }
+        if api.auth.reload(&new_config).is_err() {
+            log::error!("auth config not reloaded, keep the previous one");
+        }
+        auth_modified_at = auth_modified(&api.auth);
+        api.timeouts.reload(&new_config);
+        api.limits.reload(&new_config);
+        api.metrics
+            .set_configured_plugins(new_config.configured_plugins());
+        api.limiter.reload(&new_config);
+        api.tenants.reload(&new_config);
+        api.persisted.reload(&new_config);
+        let added = api.storage.write().await.reload(&new_config);
+        if !added.is_empty() {
+            let storage = api.storage.clone();
+            tokio::spawn(async move {
+                let _ = preload_plugins(&storage, &added, PreloadFailure::Warn).await;
+            });
+        }
+        log::info!("config {} reloaded", path.display());
+        config = new_config;
+    }
+}
+
+/// 按配置与处理器存储器创建主服务的全部路由
+pub fn routes(
+    config: &Config,
+    storage: StateContext,
+) -> Result<impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone, Error> {
+    Ok(filters(ApiContext::from_config(config, storage)?))
+}
+
+// 组合所有路由，被拒绝的请求映射为对应的状态码
+fn filters(api: ApiContext) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    let ctx = api.storage.clone();
+    let persisted = api.persisted.clone();
+    let limiter = api.limiter.clone();
+    let metrics = api.metrics.clone();
+    let auth = api.auth.clone();
 
     let home = warp::path::end().map(|| "it works");
 
-    // 编译插件 GET /build/:name
+    // 存活检查 GET /healthz
+    let liveness_route = warp::path!("healthz")
+        .and(warp::get())
+        .map(|| warp::reply::json(&"ok"));
+
+    // 就绪检查 GET /readyz
+    let readiness_route = warp::path!("readyz")
+        .and(warp::get())
+        .and(with_context(ctx.clone()))
+        .and_then(readiness_handler);
+
+    // 插件的状态 GET /plugins/:name/health，包含panic与错误信息，只对管理员开放
+    let plugin_health_route = warp::path!("plugins" / String / "health")
+        .and(warp::get())
+        .and(with_admin(auth.clone()))
+        .and(with_context(ctx.clone()))
+        .and_then(plugin_health_handler);
+
+    // 编译插件 GET /build/:name?force=true
     let build_plugin_route = warp::path!("build" / String)
         .and(warp::get())
+        .and(with_admin(auth.clone()))
+        .and(query::query())
+        .and(with_context(ctx.clone()))
         .and_then(build_plugin_handler);
 
-    // 操作处理器存储器 GET /control/:add_or_remove/:name
+    // 操作处理器存储器 GET /control/:action/:name，action: add、remove、reload、metadata、canary、promote、rollback，
+    // 重新加载时返回schema的变更，GET /control/reload/:name?force=true忽略破坏性变更；
+    // GET /control/canary/:name?version=v2&weight=10&shadow=20加载金丝雀版本或修改其权重与影子流量的比例，
+    // promote、rollback可以通过version指定版本
     let control_context_storage = warp::path!("control" / String / String)
         .and(warp::get())
+        .and(with_admin(auth.clone()))
+        .and(query::query())
         .and(with_context(ctx.clone()))
+        .and(with_limits(api.limits.clone()))
         .and_then(contro_context_handle);
 
-    // Graphql Get请求 GET /api/:name/graphql/:flag
-    let graphql_get_route = warp::path!("api" / String / "graphql" / bool)
+    // Graphql Get请求 GET /api/:name/graphql/:tenant，不带租户时取请求头x-tenant或默认租户，
+    // 请求头x-plugin-version指定插件的版本
+    let graphql_get_route = warp::path!("api" / String / "graphql" / ..)
+        .and(tenant_segment())
+        .and(warp::header::optional::<String>(VERSION_HEADER))
         .and(warp::get())
-        .and(with_context(ctx.clone()))
+        .and(with_api_context(api.clone()))
+        .and(with_client(auth.clone()))
         .and(data_context_extractor())
         .and(query::query())
         .and_then(graphql_get_handler);
 
-    // Graphql Post json请求 POST /api/:name/graphql/:flag
-    let graphql_post_json_route = warp::path!("api" / String / "graphql" / bool)
+    // Graphql Post请求 POST /api/:name/graphql/:tenant，content-type为application/graphql时body为查询原文，否则为json
+    let graphql_post_route = warp::path!("api" / String / "graphql" / ..)
+        .and(tenant_segment())
+        .and(warp::header::optional::<String>(VERSION_HEADER))
         .and(warp::post())
-        .and(with_context(ctx.clone()))
+        .and(with_api_context(api.clone()))
+        .and(with_client(auth.clone()))
         .and(data_context_extractor())
-        .and(body::json())
-        .and_then(graphql_post_json_handler);
+        .and(post_body())
+        .and_then(graphql_post_handler);
 
-    // Graphql POST graphql请求 POST /api/:name/graphql/:flag
-    let graphql_post_graphql_route = warp::path!("api" / String / "graphql" / bool)
+    // 注册持久化查询 POST /persisted/:name?version=v2，body为查询的json数组，返回查询的sha256，
+    // 查询按schema保存，指定version时为该金丝雀版本的schema注册
+    let register_persisted_route = warp::path!("persisted" / String)
         .and(warp::post())
+        .and(with_admin(auth.clone()))
+        .and(query::query())
         .and(with_context(ctx.clone()))
-        .and(data_context_extractor())
-        .and(body::bytes())
-        .and_then(graphql_post_graphql_handler);
+        .and(with_persisted(persisted.clone()))
+        .and(body::json())
+        .and_then(register_persisted_handler);
 
-    // Graphiql页面 GET /api/:name/graphiql/:flag
-    let graphiql_route = warp::path!("api" / String / "graphiql" / bool)
+    // 查看限流令牌桶的状态 GET /admin/rate-limits
+    let rate_limits_route = warp::path!("admin" / "rate-limits")
         .and(warp::get())
-        .and(with_context(ctx.clone()))
+        .and(with_admin(auth.clone()))
+        .and(with_rate_limiter(limiter.clone()))
+        .and_then(rate_limits_handler);
+
+    // 查看影子流量的比较结果 GET /admin/shadow
+    let shadow_route = warp::path!("admin" / "shadow")
+        .and(warp::get())
+        .and(with_admin(auth.clone()))
+        .and(with_shadow(api.shadow.clone()))
+        .and_then(shadow_handler);
+
+    // Prometheus指标 GET /metrics
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(with_metrics(metrics.clone()))
+        .and_then(metrics_handler);
+
+    // Graphiql页面 GET /api/:name/graphiql/:tenant
+    let graphiql_route = warp::path!("api" / String / "graphiql" / ..)
+        .and(tenant_segment())
+        .and(warp::get())
+        .and(with_api_context(api.clone()))
+        .and(with_client(auth.clone()))
         .and_then(graphiql_handler);
 
-    let routes = home
+    home.or(liveness_route)
+        .or(readiness_route)
+        .or(plugin_health_route)
         .or(build_plugin_route)
         .or(control_context_storage)
         .or(graphql_get_route)
-        .or(graphql_post_json_route)
-        .or(graphql_post_graphql_route)
+        .or(graphql_post_route)
+        .or(register_persisted_route)
+        .or(rate_limits_route)
+        .or(shadow_route)
+        .or(metrics_route)
         .or(graphiql_route)
         .recover(handle_rejection)
-        .with(warp::log("server:demo"));
+        .with(warp::log("server:demo"))
+        .with(warp::trace(telemetry::request_span))
+}
 
-    warp::serve(routes).run(addr).await
+pub async fn run() {
+    dotenv().ok();
+    pretty_env_logger::init();
+    let _telemetry = telemetry::init("my-master");
+    let config = Config::load().expect("unable to load config");
+
+    let metrics = Arc::new(Metrics::new());
+    let ctx = Arc::new(StorageLock::new(
+        HandlerStorage::from_config(&config)
+            .with_metrics(metrics)
+            .with_trusted_keys(
+                TrustedKeys::load(&config.trusted_keys).expect("unable to load trusted keys"),
+            ),
+    ));
+    let api = ApiContext::from_config(&config, ctx.clone()).expect("unable to load auth config");
+    if !api.auth.current().has_credentials() {
+        log::warn!("no api key or jwt key configured, admin api is not protected");
+    }
+
+    // 启动前加载预加载与常驻的插件
+    let plugins = ctx.read().await.preload().to_vec();
+    preload_plugins(&ctx, &plugins, config.preload_failure)
+        .await
+        .expect("unable to preload plugins");
+
+    // 定时清理context
+    let con_ctx = ctx.clone();
+    tokio::spawn(async move { clean_context(con_ctx.clone()).await });
+
+    // 热加载配置文件
+    tokio::spawn(watch_config(api.clone(), config.clone()));
+
+    let routes = filters(api);
+    let server = warp::serve(routes);
+    match &config.server.tls {
+        Some(tls) => {
+            server
+                .tls()
+                .cert_path(&tls.cert)
+                .key_path(&tls.key)
+                .run(config.server.addr)
+                .await
+        }
+        None => server.run(config.server.addr).await,
+    }
 }
@@ -0,0 +1,415 @@
+use graphql_parser::schema::{
+    parse_schema, Definition, Directive, Document, EnumValue, Field, InputValue, SchemaDefinition,
+    Type, TypeDefinition,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Error;
+
+/// schema变更的级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeLevel {
+    /// 已有的查询不受影响
+    Safe,
+    /// 已有的查询依然合法，但结果可能变化，如枚举新增了值、参数默认值变化
+    Dangerous,
+    /// 已有的查询可能不再合法，如删除了字段、修改了字段类型
+    Breaking,
+}
+
+/// schema的一项变更，path为变更的位置，如`Foo.bars(id)`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaChange {
+    pub level: ChangeLevel,
+    pub path: String,
+    pub message: String,
+}
+
+/// 两个schema之间的变更，按位置排序
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchemaDiff {
+    pub changes: Vec<SchemaChange>,
+}
+
+type Types<'a> = BTreeMap<&'a str, &'a TypeDefinition<'a, String>>;
+
+impl SchemaDiff {
+    /// 比较重新加载前后schema的SDL
+    pub fn between(old_sdl: &str, new_sdl: &str) -> Result<Self, Error> {
+        let old = parse_sdl(old_sdl)?;
+        let new = parse_sdl(new_sdl)?;
+        let mut diff = SchemaDiff::default();
+        diff.diff_schema(schema_definition(&old), schema_definition(&new));
+        diff.diff_types(&types(&old), &types(&new));
+        diff.changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(diff)
+    }
+
+    /// 是否有破坏性的变更
+    pub fn is_breaking(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|change| change.level == ChangeLevel::Breaking)
+    }
+
+    /// 指定级别的变更数
+    pub fn count(&self, level: ChangeLevel) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| change.level == level)
+            .count()
+    }
+
+    fn push(&mut self, level: ChangeLevel, path: impl Into<String>, message: impl Into<String>) {
+        self.changes.push(SchemaChange {
+            level,
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    // 根操作类型，未声明schema时为默认的Query、Mutation、Subscription
+    fn diff_schema(&mut self, old: RootTypes<'_>, new: RootTypes<'_>) {
+        for (operation, old_type, new_type) in [
+            ("query", old.query, new.query),
+            ("mutation", old.mutation, new.mutation),
+            ("subscription", old.subscription, new.subscription),
+        ] {
+            let path = format!("schema.{}", operation);
+            match (old_type, new_type) {
+                (Some(old_type), Some(new_type)) if old_type != new_type => self.push(
+                    ChangeLevel::Breaking,
+                    path,
+                    format!("root type changed from {} to {}", old_type, new_type),
+                ),
+                (Some(old_type), None) => self.push(
+                    ChangeLevel::Breaking,
+                    path,
+                    format!("root type {} removed", old_type),
+                ),
+                (None, Some(new_type)) => self.push(
+                    ChangeLevel::Safe,
+                    path,
+                    format!("root type {} added", new_type),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    fn diff_types<'a>(&mut self, old: &Types<'a>, new: &Types<'a>) {
+        for (name, old_type) in old.iter() {
+            let new_type = match new.get(name) {
+                Some(new_type) => new_type,
+                None => {
+                    self.push(ChangeLevel::Breaking, *name, "type removed");
+                    continue;
+                }
+            };
+            match (old_type, new_type) {
+                (TypeDefinition::Object(o), TypeDefinition::Object(n)) => {
+                    self.diff_members(
+                        name,
+                        "interface",
+                        &o.implements_interfaces,
+                        &n.implements_interfaces,
+                    );
+                    self.diff_fields(name, &o.fields, &n.fields);
+                }
+                (TypeDefinition::Interface(o), TypeDefinition::Interface(n)) => {
+                    self.diff_fields(name, &o.fields, &n.fields);
+                }
+                (TypeDefinition::Union(o), TypeDefinition::Union(n)) => {
+                    self.diff_members(name, "member", &o.types, &n.types);
+                }
+                (TypeDefinition::Enum(o), TypeDefinition::Enum(n)) => {
+                    self.diff_enum_values(name, &o.values, &n.values);
+                }
+                (TypeDefinition::InputObject(o), TypeDefinition::InputObject(n)) => {
+                    self.diff_input_values(name, &o.fields, &n.fields);
+                }
+                (TypeDefinition::Scalar(_), TypeDefinition::Scalar(_)) => {}
+                _ => self.push(
+                    ChangeLevel::Breaking,
+                    *name,
+                    format!(
+                        "type changed from {} to {}",
+                        kind_of(old_type),
+                        kind_of(new_type)
+                    ),
+                ),
+            }
+        }
+        for name in new.keys().filter(|name| !old.contains_key(*name)) {
+            self.push(ChangeLevel::Safe, *name, "type added");
+        }
+    }
+
+    // 对象实现的接口、联合类型的成员：删除是破坏性的，新增会让已有的类型判断遇到未处理的类型
+    fn diff_members(&mut self, path: &str, kind: &str, old: &[String], new: &[String]) {
+        let old: BTreeSet<&String> = old.iter().collect();
+        let new: BTreeSet<&String> = new.iter().collect();
+        for removed in old.difference(&new) {
+            self.push(
+                ChangeLevel::Breaking,
+                path,
+                format!("{} {} removed", kind, removed),
+            );
+        }
+        for added in new.difference(&old) {
+            self.push(
+                ChangeLevel::Dangerous,
+                path,
+                format!("{} {} added", kind, added),
+            );
+        }
+    }
+
+    fn diff_fields<'a>(
+        &mut self,
+        path: &str,
+        old: &[Field<'a, String>],
+        new: &[Field<'a, String>],
+    ) {
+        for old_field in old.iter() {
+            let field_path = format!("{}.{}", path, old_field.name);
+            let new_field = match new.iter().find(|f| f.name == old_field.name) {
+                Some(new_field) => new_field,
+                None => {
+                    self.push(ChangeLevel::Breaking, field_path, "field removed");
+                    continue;
+                }
+            };
+            if old_field.field_type != new_field.field_type {
+                let level = if is_safe_output_change(&old_field.field_type, &new_field.field_type) {
+                    ChangeLevel::Safe
+                } else {
+                    ChangeLevel::Breaking
+                };
+                self.push(
+                    level,
+                    field_path.clone(),
+                    format!(
+                        "field type changed from {} to {}",
+                        old_field.field_type, new_field.field_type
+                    ),
+                );
+            }
+            if !is_deprecated(&old_field.directives) && is_deprecated(&new_field.directives) {
+                self.push(ChangeLevel::Safe, field_path.clone(), "field deprecated");
+            }
+            self.diff_input_values(&field_path, &old_field.arguments, &new_field.arguments);
+        }
+        for new_field in new.iter() {
+            if !old.iter().any(|f| f.name == new_field.name) {
+                self.push(
+                    ChangeLevel::Safe,
+                    format!("{}.{}", path, new_field.name),
+                    "field added",
+                );
+            }
+        }
+    }
+
+    // 字段的参数与输入类型的字段：由调用方传入，类型只能放宽
+    fn diff_input_values<'a>(
+        &mut self,
+        path: &str,
+        old: &[InputValue<'a, String>],
+        new: &[InputValue<'a, String>],
+    ) {
+        for old_value in old.iter() {
+            let value_path = format!("{}({})", path, old_value.name);
+            let new_value = match new.iter().find(|v| v.name == old_value.name) {
+                Some(new_value) => new_value,
+                None => {
+                    self.push(ChangeLevel::Breaking, value_path, "input removed");
+                    continue;
+                }
+            };
+            if old_value.value_type != new_value.value_type {
+                let level = if is_safe_input_change(&old_value.value_type, &new_value.value_type) {
+                    ChangeLevel::Safe
+                } else {
+                    ChangeLevel::Breaking
+                };
+                self.push(
+                    level,
+                    value_path.clone(),
+                    format!(
+                        "input type changed from {} to {}",
+                        old_value.value_type, new_value.value_type
+                    ),
+                );
+            }
+            if old_value.default_value != new_value.default_value {
+                let show = |value: &Option<_>| match value {
+                    Some(value) => format!("{}", value),
+                    None => "none".to_string(),
+                };
+                self.push(
+                    ChangeLevel::Dangerous,
+                    value_path,
+                    format!(
+                        "default value changed from {} to {}",
+                        show(&old_value.default_value),
+                        show(&new_value.default_value)
+                    ),
+                );
+            }
+        }
+        for new_value in new.iter() {
+            if old.iter().any(|v| v.name == new_value.name) {
+                continue;
+            }
+            let value_path = format!("{}({})", path, new_value.name);
+            if is_required(new_value) {
+                self.push(ChangeLevel::Breaking, value_path, "required input added");
+            } else {
+                self.push(ChangeLevel::Dangerous, value_path, "optional input added");
+            }
+        }
+    }
+
+    fn diff_enum_values<'a>(
+        &mut self,
+        path: &str,
+        old: &[EnumValue<'a, String>],
+        new: &[EnumValue<'a, String>],
+    ) {
+        for old_value in old.iter() {
+            let value_path = format!("{}.{}", path, old_value.name);
+            match new.iter().find(|v| v.name == old_value.name) {
+                None => self.push(ChangeLevel::Breaking, value_path, "enum value removed"),
+                Some(new_value)
+                    if !is_deprecated(&old_value.directives)
+                        && is_deprecated(&new_value.directives) =>
+                {
+                    self.push(ChangeLevel::Safe, value_path, "enum value deprecated")
+                }
+                Some(_) => {}
+            }
+        }
+        for new_value in new.iter() {
+            if !old.iter().any(|v| v.name == new_value.name) {
+                self.push(
+                    ChangeLevel::Dangerous,
+                    format!("{}.{}", path, new_value.name),
+                    "enum value added",
+                );
+            }
+        }
+    }
+}
+
+fn parse_sdl(sdl: &str) -> Result<Document<'_, String>, Error> {
+    parse_schema::<String>(sdl).map_err(|e| {
+        log::error!("unable to parse schema: {}", e);
+        Error::SchemaError
+    })
+}
+
+fn types<'a>(document: &'a Document<'a, String>) -> Types<'a> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            Definition::TypeDefinition(type_definition) => {
+                Some((type_name(type_definition), type_definition))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+struct RootTypes<'a> {
+    query: Option<&'a str>,
+    mutation: Option<&'a str>,
+    subscription: Option<&'a str>,
+}
+
+fn schema_definition<'a>(document: &'a Document<'a, String>) -> RootTypes<'a> {
+    let schema = document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            Definition::SchemaDefinition(schema) => Some(schema),
+            _ => None,
+        });
+    match schema {
+        Some(SchemaDefinition {
+            query,
+            mutation,
+            subscription,
+            ..
+        }) => RootTypes {
+            query: query.as_deref(),
+            mutation: mutation.as_deref(),
+            subscription: subscription.as_deref(),
+        },
+        None => {
+            let types = types(document);
+            let root = |name: &'static str| types.contains_key(name).then_some(name);
+            RootTypes {
+                query: root("Query"),
+                mutation: root("Mutation"),
+                subscription: root("Subscription"),
+            }
+        }
+    }
+}
+
+fn type_name<'a>(type_definition: &'a TypeDefinition<'a, String>) -> &'a str {
+    match type_definition {
+        TypeDefinition::Scalar(t) => &t.name,
+        TypeDefinition::Object(t) => &t.name,
+        TypeDefinition::Interface(t) => &t.name,
+        TypeDefinition::Union(t) => &t.name,
+        TypeDefinition::Enum(t) => &t.name,
+        TypeDefinition::InputObject(t) => &t.name,
+    }
+}
+
+fn kind_of(type_definition: &TypeDefinition<'_, String>) -> &'static str {
+    match type_definition {
+        TypeDefinition::Scalar(_) => "scalar",
+        TypeDefinition::Object(_) => "object",
+        TypeDefinition::Interface(_) => "interface",
+        TypeDefinition::Union(_) => "union",
+        TypeDefinition::Enum(_) => "enum",
+        TypeDefinition::InputObject(_) => "input",
+    }
+}
+
+fn is_deprecated(directives: &[Directive<'_, String>]) -> bool {
+    directives.iter().any(|d| d.name == "deprecated")
+}
+
+// 非空且没有默认值的参数调用方必须传入
+fn is_required(value: &InputValue<'_, String>) -> bool {
+    matches!(value.value_type, Type::NonNullType(_)) && value.default_value.is_none()
+}
+
+// 返回值的类型只能收紧：可空变为非空不影响已有的查询
+fn is_safe_output_change(old: &Type<'_, String>, new: &Type<'_, String>) -> bool {
+    match (old, new) {
+        (Type::NonNullType(old), Type::NonNullType(new)) => is_safe_output_change(old, new),
+        (old, Type::NonNullType(new)) => is_safe_output_change(old, new),
+        (Type::ListType(old), Type::ListType(new)) => is_safe_output_change(old, new),
+        (Type::NamedType(old), Type::NamedType(new)) => old == new,
+        _ => false,
+    }
+}
+
+// 输入的类型只能放宽：非空变为可空不影响已有的查询
+fn is_safe_input_change(old: &Type<'_, String>, new: &Type<'_, String>) -> bool {
+    match (old, new) {
+        (Type::NonNullType(old), Type::NonNullType(new)) => is_safe_input_change(old, new),
+        (Type::NonNullType(old), new) => is_safe_input_change(old, new),
+        (Type::ListType(old), Type::ListType(new)) => is_safe_input_change(old, new),
+        (Type::NamedType(old), Type::NamedType(new)) => old == new,
+        _ => false,
+    }
+}
@@ -0,0 +1,330 @@
+//! 影子流量：按采样将请求的副本异步发送到插件的金丝雀版本，比较两者的响应，影子版本的响应不会返回给客户端
+use bytes::Bytes;
+use graphql_parser::query::{parse_query, Definition, Document, OperationDefinition};
+use juniper::{http::GraphQLBatchRequest, DefaultScalarValue};
+use my_interface::DataContext;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use warp::{
+    http::{self, StatusCode},
+    Rejection,
+};
+
+use crate::{health::unix_now, rejection_status, PluginHandler};
+
+// 每个版本保留的最近不一致的请求数
+const RECENT_MISMATCHES: usize = 20;
+// 每次不一致最多记录的差异位置数
+const MAX_DIFF_PATHS: usize = 20;
+// 同时在影子版本中执行的请求数上限，超出时丢弃采样
+const MAX_IN_FLIGHT: usize = 64;
+
+/// 请求的副本，发送到影子版本执行
+pub enum ShadowRequest {
+    Get(HashMap<String, String>),
+    PostJson(GraphQLBatchRequest<DefaultScalarValue>),
+    PostGraphql(Bytes),
+}
+
+impl Clone for ShadowRequest {
+    // GraphQLBatchRequest没有实现Clone
+    fn clone(&self) -> Self {
+        match self {
+            ShadowRequest::Get(qry) => ShadowRequest::Get(qry.clone()),
+            ShadowRequest::PostJson(GraphQLBatchRequest::Single(req)) => {
+                ShadowRequest::PostJson(GraphQLBatchRequest::Single(req.clone()))
+            }
+            ShadowRequest::PostJson(GraphQLBatchRequest::Batch(reqs)) => {
+                ShadowRequest::PostJson(GraphQLBatchRequest::Batch(reqs.clone()))
+            }
+            ShadowRequest::PostGraphql(body) => ShadowRequest::PostGraphql(body.clone()),
+        }
+    }
+}
+
+impl ShadowRequest {
+    /// 以处理器执行请求
+    pub async fn execute(
+        self,
+        handler: &PluginHandler,
+        context: DataContext,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        match self {
+            ShadowRequest::Get(qry) => handler.get_request_handle(context, qry).await,
+            ShadowRequest::PostJson(req) => handler.post_json_request_handle(context, req).await,
+            ShadowRequest::PostGraphql(body) => {
+                handler.post_grqphql_request_handle(context, body).await
+            }
+        }
+    }
+
+    // 请求中的查询，批量请求中每个请求一个
+    fn queries(&self) -> Vec<String> {
+        match self {
+            ShadowRequest::Get(qry) => qry.get("query").cloned().into_iter().collect(),
+            ShadowRequest::PostJson(req) => {
+                let requests = match req {
+                    GraphQLBatchRequest::Single(single) => vec![single],
+                    GraphQLBatchRequest::Batch(batch) => batch.iter().collect(),
+                };
+                // GraphQLRequest的query字段不公开，通过序列化获取
+                requests
+                    .iter()
+                    .filter_map(|r| {
+                        let value = serde_json::to_value(r).ok()?;
+                        value.get("query")?.as_str().map(str::to_string)
+                    })
+                    .collect()
+            }
+            ShadowRequest::PostGraphql(body) => vec![String::from_utf8_lossy(body).to_string()],
+        }
+    }
+
+    /// 请求中的查询，批量请求的查询按行分隔
+    pub fn query(&self) -> String {
+        self.queries().join("\n")
+    }
+
+    /// 是否只包含查询操作。变更可能有副作用，只有查询会发送到影子版本，无法解析的请求同样不发送
+    pub fn is_read_only(&self) -> bool {
+        let queries = self.queries();
+        !queries.is_empty()
+            && queries
+                .iter()
+                .all(|query| match parse_query::<String>(query) {
+                    Ok(document) => is_read_only(&document),
+                    Err(_) => false,
+                })
+    }
+}
+
+fn is_read_only(document: &Document<String>) -> bool {
+    !document.definitions.iter().any(|definition| {
+        matches!(
+            definition,
+            Definition::Operation(OperationDefinition::Mutation(_))
+                | Definition::Operation(OperationDefinition::Subscription(_))
+        )
+    })
+}
+
+/// 响应的状态码、响应体与执行耗时，被拒绝的请求响应体为空
+pub struct ShadowResponse {
+    pub status: StatusCode,
+    pub body: Vec<u8>,
+    pub elapsed: Duration,
+}
+
+impl ShadowResponse {
+    pub fn from_result(
+        result: &Result<http::Response<Vec<u8>>, Rejection>,
+        elapsed: Duration,
+    ) -> Self {
+        let (status, body) = match result {
+            Ok(response) => (response.status(), response.body().clone()),
+            Err(rejection) => (rejection_status(rejection).0, Vec::new()),
+        };
+        Self {
+            status,
+            body,
+            elapsed,
+        }
+    }
+
+    // 响应体按json解析，不是json时为字符串
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&self.body).to_string()))
+    }
+}
+
+/// 一次稳定版本与影子版本的响应不一致的请求，只记录不一致的位置，不保留响应体
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowMismatch {
+    /// unix时间戳（秒）
+    pub at: u64,
+    pub tenant: String,
+    pub query: String,
+    pub primary_status: u16,
+    pub shadow_status: u16,
+    /// 响应体中不一致的位置，以JSON Pointer表示
+    pub paths: Vec<String>,
+}
+
+#[derive(Default)]
+struct ShadowStats {
+    requests: u64,
+    mismatches: u64,
+    dropped: u64,
+    primary_secs: f64,
+    shadow_secs: f64,
+    recent: VecDeque<ShadowMismatch>,
+}
+
+/// 影子版本的比较结果，用于`/admin/shadow`接口展示
+#[derive(Debug, Serialize)]
+pub struct ShadowReport {
+    pub plugin: String,
+    pub version: String,
+    pub requests: u64,
+    pub mismatches: u64,
+    /// 同时执行的影子请求过多而丢弃的采样数
+    pub dropped: u64,
+    /// 稳定版本的平均耗时（毫秒）
+    pub primary_avg_ms: f64,
+    /// 影子版本的平均耗时（毫秒）
+    pub shadow_avg_ms: f64,
+    /// 影子版本比稳定版本平均多出的耗时（毫秒），为负时影子版本更快
+    pub latency_diff_ms: f64,
+    /// 最近不一致的请求，按时间先后排列
+    pub recent_mismatches: Vec<ShadowMismatch>,
+}
+
+/// 按插件与版本记录影子流量的比较结果
+pub struct ShadowStore {
+    stats: Mutex<BTreeMap<(String, String), ShadowStats>>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Default for ShadowStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShadowStore {
+    pub fn new() -> Self {
+        Self {
+            stats: Mutex::new(BTreeMap::new()),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        }
+    }
+
+    /// 申请在影子版本中执行一个请求，同时执行的请求已达上限时丢弃该采样并返回None
+    pub fn try_acquire(&self, plugin: &str, version: &str) -> Option<OwnedSemaphorePermit> {
+        let permit = self.in_flight.clone().try_acquire_owned().ok();
+        if permit.is_none() {
+            log::debug!(
+                "too many shadow requests in flight, sample for {} of plugin {} dropped",
+                version,
+                plugin
+            );
+            let mut stats = self.stats.lock().unwrap();
+            stats
+                .entry((plugin.to_string(), version.to_string()))
+                .or_default()
+                .dropped += 1;
+        }
+        permit
+    }
+
+    /// 删除插件版本的比较结果，金丝雀版本回滚、转正或卸载时调用
+    pub fn clear(&self, plugin: &str, version: &str) {
+        self.stats
+            .lock()
+            .unwrap()
+            .remove(&(plugin.to_string(), version.to_string()));
+    }
+
+    /// 比较稳定版本与影子版本的响应并记录结果，状态码与响应体的json都一致时视为一致，返回是否一致
+    pub fn record(
+        &self,
+        plugin: &str,
+        version: &str,
+        tenant: &str,
+        query: String,
+        primary: &ShadowResponse,
+        shadow: &ShadowResponse,
+    ) -> bool {
+        let (primary_json, shadow_json) = (primary.json(), shadow.json());
+        let matched = primary.status == shadow.status && primary_json == shadow_json;
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats
+            .entry((plugin.to_string(), version.to_string()))
+            .or_default();
+        stats.requests += 1;
+        stats.primary_secs += primary.elapsed.as_secs_f64();
+        stats.shadow_secs += shadow.elapsed.as_secs_f64();
+        if matched {
+            return true;
+        }
+        log::warn!(
+            "shadow {} of plugin {} mismatched, status {} vs {}",
+            version,
+            plugin,
+            primary.status,
+            shadow.status
+        );
+        stats.mismatches += 1;
+        if stats.recent.len() >= RECENT_MISMATCHES {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back(ShadowMismatch {
+            at: unix_now(),
+            tenant: tenant.to_string(),
+            query,
+            primary_status: primary.status.as_u16(),
+            shadow_status: shadow.status.as_u16(),
+            paths: json_diff(&primary_json, &shadow_json),
+        });
+        false
+    }
+
+    pub fn snapshot(&self) -> Vec<ShadowReport> {
+        let stats = self.stats.lock().unwrap();
+        stats
+            .iter()
+            .map(|((plugin, version), stats)| {
+                let average = |secs: f64| secs * 1000.0 / stats.requests.max(1) as f64;
+                ShadowReport {
+                    plugin: plugin.clone(),
+                    version: version.clone(),
+                    requests: stats.requests,
+                    mismatches: stats.mismatches,
+                    dropped: stats.dropped,
+                    primary_avg_ms: average(stats.primary_secs),
+                    shadow_avg_ms: average(stats.shadow_secs),
+                    latency_diff_ms: average(stats.shadow_secs - stats.primary_secs),
+                    recent_mismatches: stats.recent.iter().cloned().collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// 两个json值不一致的位置，以JSON Pointer表示，最多20个
+pub fn json_diff(primary: &Value, shadow: &Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    collect_diff(Some(primary), Some(shadow), String::new(), &mut paths);
+    paths
+}
+
+// 对象按key、数组按下标逐层比较，一方缺失的位置也视为不一致
+fn collect_diff(a: Option<&Value>, b: Option<&Value>, path: String, paths: &mut Vec<String>) {
+    if paths.len() >= MAX_DIFF_PATHS || a == b {
+        return;
+    }
+    match (a, b) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let pointer = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+                collect_diff(a.get(key), b.get(key), pointer, paths);
+            }
+        }
+        (Some(Value::Array(a)), Some(Value::Array(b))) => {
+            for i in 0..a.len().max(b.len()) {
+                collect_diff(a.get(i), b.get(i), format!("{}/{}", path, i), paths);
+            }
+        }
+        _ => paths.push(path),
+    }
+}
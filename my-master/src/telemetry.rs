@@ -0,0 +1,129 @@
+use my_interface::FieldTracer;
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider as _,
+};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use std::{any::Any, collections::HashMap, sync::Arc};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, Layer, Registry};
+use warp::http::HeaderMap;
+
+// 配置该环境变量后才导出span，例如本地collector的`http://localhost:4318`
+const OTLP_ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// 初始化链路追踪，配置了`OTEL_EXPORTER_OTLP_ENDPOINT`时通过OTLP（http/protobuf）导出span。
+/// 返回的provider释放时会导出剩余的span，需要在服务运行期间持有
+pub fn init(service_name: &str) -> Option<SdkTracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    std::env::var(OTLP_ENDPOINT_VAR).ok()?;
+    let exporter = match SpanExporter::builder().with_http().build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            log::error!("unable to create otlp exporter: {}", e);
+            return None;
+        }
+    };
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    // warp、hyper内部有大量TRACE级别的span与事件，只导出INFO及以上级别
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(LevelFilter::INFO);
+    let subscriber = Registry::default().with(layer);
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        log::error!("unable to set tracing subscriber: {}", e);
+        return None;
+    }
+    global::set_tracer_provider(provider.clone());
+    log::info!(
+        "tracing enabled, exporting to {}",
+        std::env::var(OTLP_ENDPOINT_VAR).unwrap_or_default()
+    );
+    Some(provider)
+}
+
+/// 插件resolver的字段追踪器，追踪未开启时为None
+pub fn field_tracer() -> Option<Arc<dyn FieldTracer>> {
+    if tracing::dispatcher::has_been_set() {
+        Some(Arc::new(SpanFieldTracer))
+    } else {
+        None
+    }
+}
+
+// span在主服务中创建，名称与属性都复制为主服务的数据，插件卸载后导出span不会访问插件的内存
+struct SpanFieldTracer;
+
+impl FieldTracer for SpanFieldTracer {
+    fn start_field(&self, object: &str, field: &str) -> Box<dyn Any + Send> {
+        Box::new(tracing::info_span!(
+            "resolve_field",
+            otel.name = %format_args!("{}.{}", object, field),
+            graphql.object = %object,
+            graphql.field = %field,
+        ))
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct MapInjector<'a>(&'a mut HashMap<String, String>);
+
+impl Injector for MapInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+/// 每个请求的span，按W3C`traceparent`请求头关联调用方的链路
+pub fn request_span(info: warp::trace::Info) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format_args!("{} {}", info.method(), info.path()),
+        http.method = %info.method(),
+        http.path = %info.path(),
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(info.request_headers()))
+    });
+    let _ = span.set_parent(parent);
+    span
+}
+
+/// 当前span的链路上下文，用于传递给工作进程
+pub fn current_context() -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MapInjector(&mut carrier))
+    });
+    carrier
+}
+
+/// 以传入的链路上下文为父级创建span
+pub fn span_with_parent(span: Span, carrier: &HashMap<String, String>) -> Span {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(carrier));
+    let _ = span.set_parent(parent);
+    span
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use crate::{config::Config, Error};
+
+/// 请求所属的租户
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    pub name: String,
+    /// 租户使用的数据集
+    pub dataset: String,
+    /// 租户固定使用的插件版本，None时按金丝雀版本的权重分配
+    pub version: Option<String>,
+}
+
+struct TenantSettings {
+    dataset: String,
+    // 开启的插件，None表示开启所有插件
+    plugins: Option<HashSet<String>>,
+    // 固定使用的插件版本
+    versions: HashMap<String, String>,
+}
+
+struct Settings {
+    default_tenant: String,
+    tenants: HashMap<String, TenantSettings>,
+}
+
+impl Settings {
+    fn from_config(config: &Config) -> Self {
+        let tenants = config
+            .tenants
+            .iter()
+            .map(|(name, tenant)| {
+                let settings = TenantSettings {
+                    dataset: tenant.dataset.clone().unwrap_or_else(|| name.clone()),
+                    plugins: tenant
+                        .plugins
+                        .as_ref()
+                        .map(|plugins| plugins.iter().cloned().collect()),
+                    versions: tenant
+                        .versions
+                        .iter()
+                        .map(|(plugin, version)| (plugin.clone(), version.clone()))
+                        .collect(),
+                };
+                (name.clone(), settings)
+            })
+            .collect();
+        Self {
+            default_tenant: config.default_tenant.clone(),
+            tenants,
+        }
+    }
+}
+
+/// 租户的配置：租户使用的数据集、开启的插件以及固定使用的插件版本
+pub struct Tenants {
+    settings: RwLock<Settings>,
+}
+
+impl Tenants {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            settings: RwLock::new(Settings::from_config(config)),
+        }
+    }
+
+    /// 配置热加载时更新租户，对之后的请求生效
+    pub fn reload(&self, config: &Config) {
+        *self.settings.write().unwrap() = Settings::from_config(config);
+    }
+
+    /// 解析请求的租户以及租户固定使用的插件版本，未指定时为默认租户。租户不存在或者没有开启该插件时返回错误
+    pub fn resolve(&self, tenant: Option<&str>, plugin: &str) -> Result<Tenant, Error> {
+        let settings = self.settings.read().unwrap();
+        let name = tenant.unwrap_or(&settings.default_tenant);
+        let tenant = settings.tenants.get(name).ok_or_else(|| {
+            log::warn!("no such tenant: {:?}", name);
+            Error::NoSuchTenant
+        })?;
+        if let Some(plugins) = &tenant.plugins {
+            if !plugins.contains(plugin) {
+                log::warn!("plugin {} is not enabled for tenant {}", plugin, name);
+                return Err(Error::PluginNotEnabled);
+            }
+        }
+        Ok(Tenant {
+            name: name.to_string(),
+            dataset: tenant.dataset.clone(),
+            version: tenant.versions.get(plugin).cloned(),
+        })
+    }
+}
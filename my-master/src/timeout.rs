@@ -0,0 +1,77 @@
+use std::{collections::HashMap, future::Future, sync::RwLock, time::Duration};
+use warp::http::{self, StatusCode};
+
+use crate::{config::Config, graphql_error_response};
+
+// 未配置时插件执行的默认超时时间
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct TimeoutSettings {
+    default_timeout: Duration,
+    plugin_timeouts: HashMap<String, Duration>,
+}
+
+impl TimeoutSettings {
+    fn from_config(config: &Config) -> Self {
+        let (default_timeout, plugin_timeouts) =
+            config.plugin_values(|p| p.timeout_secs.map(Duration::from_secs_f64));
+        Self {
+            default_timeout: default_timeout.unwrap_or(DEFAULT_TIMEOUT),
+            plugin_timeouts,
+        }
+    }
+}
+
+/// 插件执行的超时配置
+pub struct ExecutionTimeouts {
+    settings: RwLock<TimeoutSettings>,
+}
+
+impl ExecutionTimeouts {
+    pub fn new(default_timeout: Duration, plugin_timeouts: HashMap<String, Duration>) -> Self {
+        Self {
+            settings: RwLock::new(TimeoutSettings {
+                default_timeout,
+                plugin_timeouts,
+            }),
+        }
+    }
+
+    /// 读取各插件的`timeout_secs`配置，`*`为默认配置
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            settings: RwLock::new(TimeoutSettings::from_config(config)),
+        }
+    }
+
+    /// 配置热加载时更新超时配置，对之后执行的请求生效
+    pub fn reload(&self, config: &Config) {
+        *self.settings.write().unwrap() = TimeoutSettings::from_config(config);
+    }
+
+    pub fn timeout_of(&self, plugin: &str) -> Duration {
+        let settings = self.settings.read().unwrap();
+        settings
+            .plugin_timeouts
+            .get(plugin)
+            .copied()
+            .unwrap_or(settings.default_timeout)
+    }
+
+    /// 在超时时间内执行插件的请求，超时后取消执行并返回graphql的超时错误响应
+    pub async fn execute<F: Future>(
+        &self,
+        plugin: &str,
+        fut: F,
+    ) -> Result<F::Output, http::Response<Vec<u8>>> {
+        let timeout = self.timeout_of(plugin);
+        tokio::time::timeout(timeout, fut).await.map_err(|_| {
+            log::warn!("plugin {} execution timed out after {:?}", plugin, timeout);
+            graphql_error_response(
+                StatusCode::GATEWAY_TIMEOUT,
+                "TIMEOUT",
+                format!("plugin execution timed out after {:?}", timeout),
+            )
+        })
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bytes::Bytes;
+use juniper::http::GraphQLBatchRequest;
+use my_interface::{
+    build_response, plugin_panic_response,
+    wasm::{
+        DataRequest, WasmRequest, WasmResponse, EXPORT_ALLOC, EXPORT_HANDLE, HOST_DATA_REQUEST,
+        HOST_DATA_RESPONSE, HOST_MODULE,
+    },
+    DataContext, GraphqlRequestHandler, PluginMetadata,
+};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+use warp::{
+    http::{self, StatusCode},
+    Rejection,
+};
+use wasmi::{
+    Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TrapCode,
+};
+use wasmi_wasi::{WasiCtx, WasiCtxBuilder};
+
+use crate::{config, graphql_error_response, Error};
+
+/// 加载时获取插件描述信息默认可消耗的fuel，示例插件生成schema约消耗几百万fuel
+pub const DEFAULT_DESCRIBE_FUEL: u64 = 1_000_000_000;
+
+/// 每个请求默认可消耗的fuel。请求在阻塞线程中执行，超时只会丢弃等待结果的future，
+/// 必须限制fuel才能让死循环的插件停下并释放线程
+pub const DEFAULT_REQUEST_FUEL: u64 = 1_000_000_000;
+
+/// wasm插件的资源限制，未配置的fuel使用默认值，未配置的内存不做限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmLimits {
+    /// 每个请求可消耗的fuel，大致对应执行的指令数，未配置时为`DEFAULT_REQUEST_FUEL`
+    pub fuel: Option<u64>,
+    /// 线性内存上限（MB）
+    pub memory_mb: Option<usize>,
+    /// 加载时获取插件描述信息（生成schema）可消耗的fuel，未配置时为`DEFAULT_DESCRIBE_FUEL`
+    pub describe_fuel: Option<u64>,
+}
+
+impl WasmLimits {
+    // 解析`fuel:内存MB`格式的配置，fuel为0时使用默认值，内存为0表示不限制
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (fuel, memory) = value.split_once(':')?;
+        Some(Self {
+            fuel: fuel
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .map(|v| Some(v).filter(|v| *v > 0))?,
+            memory_mb: memory
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .map(|v| Some(v).filter(|v| *v > 0))?,
+            describe_fuel: None,
+        })
+    }
+}
+
+/// wasm插件的配置
+#[derive(Debug, Clone, Default)]
+pub struct WasmSettings {
+    plugins: HashSet<String>,
+    default_limits: Option<WasmLimits>,
+    plugin_limits: HashMap<String, WasmLimits>,
+}
+
+impl WasmSettings {
+    pub fn new(
+        plugins: HashSet<String>,
+        default_limits: Option<WasmLimits>,
+        plugin_limits: HashMap<String, WasmLimits>,
+    ) -> Self {
+        Self {
+            plugins,
+            default_limits,
+            plugin_limits,
+        }
+    }
+
+    /// 读取各插件的`wasm`与`wasm_limits`配置，`*`为默认配置
+    pub fn from_config(config: &config::Config) -> Self {
+        let (default_limits, plugin_limits) = config.plugin_values(|p| p.wasm_limits);
+        Self::new(
+            config.plugin_list(|p| p.wasm),
+            default_limits,
+            plugin_limits,
+        )
+    }
+
+    pub fn is_wasm(&self, plugin: &str) -> bool {
+        self.plugins.contains(plugin) || self.plugins.contains("*")
+    }
+
+    pub fn limits_of(&self, plugin: &str) -> WasmLimits {
+        self.plugin_limits
+            .get(plugin)
+            .copied()
+            .or(self.default_limits)
+            .unwrap_or_default()
+    }
+}
+
+/// 每个请求的宿主状态
+struct HostState {
+    wasi: WasiCtx,
+    limits: StoreLimits,
+    data: DataContext,
+    /// 上一次数据请求的响应，等待插件取回
+    pending: Vec<u8>,
+}
+
+fn memory_of(caller: &Caller<'_, HostState>) -> Result<wasmi::Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("missing memory export"))
+}
+
+// 在主服务的DataContext上执行插件的数据请求
+fn serve_data(data: &DataContext, request: DataRequest) -> serde_json::Result<Vec<u8>> {
+    match request {
+        DataRequest::GetFoos => serde_json::to_vec(&data.get_foos()),
+        DataRequest::GetFoo(id) => serde_json::to_vec(&data.get_foo(id)),
+        DataRequest::GetBars => serde_json::to_vec(&data.get_bars()),
+        DataRequest::GetBar(id) => serde_json::to_vec(&data.get_bar(id)),
+        DataRequest::GetBarsByIds(ids) => serde_json::to_vec(&data.get_bars_by_ids(ids)),
+    }
+}
+
+fn host_linker(engine: &Engine) -> Result<Linker<HostState>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    wasmi_wasi::add_to_linker(&mut linker, |state: &mut HostState| &mut state.wasi)
+        .map_err(|e| wasmi::Error::new(e.to_string()))?;
+    linker.func_wrap(
+        HOST_MODULE,
+        HOST_DATA_REQUEST,
+        |mut caller: Caller<'_, HostState>, ptr: u32, len: u32| -> Result<u32, wasmi::Error> {
+            let memory = memory_of(&caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let request = data
+                .get(ptr as usize..ptr as usize + len as usize)
+                .ok_or_else(|| wasmi::Error::new("data request out of bounds"))?;
+            let request = serde_json::from_slice(request)
+                .map_err(|e| wasmi::Error::new(format!("invalid data request: {}", e)))?;
+            state.pending =
+                serve_data(&state.data, request).map_err(|e| wasmi::Error::new(e.to_string()))?;
+            Ok(state.pending.len() as u32)
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        HOST_DATA_RESPONSE,
+        |mut caller: Caller<'_, HostState>, ptr: u32| -> Result<(), wasmi::Error> {
+            let memory = memory_of(&caller)?;
+            let pending = std::mem::take(&mut caller.data_mut().pending);
+            memory.write(&mut caller, ptr as usize, &pending)?;
+            Ok(())
+        },
+    )?;
+    Ok(linker)
+}
+
+/// 编译后的wasm插件
+struct WasmPlugin {
+    engine: Engine,
+    module: Module,
+    linker: Linker<HostState>,
+    limits: WasmLimits,
+}
+
+impl WasmPlugin {
+    // 每个请求使用新的实例，插件trap或者耗尽fuel后不会影响后续请求
+    fn call(&self, request: &WasmRequest, data: DataContext) -> Result<WasmResponse, wasmi::Error> {
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(memory_mb) = self.limits.memory_mb {
+            limits = limits.memory_size(memory_mb * 1024 * 1024);
+        }
+        let state = HostState {
+            wasi: WasiCtxBuilder::new().inherit_stderr().build(),
+            limits: limits.build(),
+            data,
+            pending: Vec::new(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        // 加载时生成schema的开销较大，单独限制
+        let fuel = match request {
+            WasmRequest::Describe => self.limits.describe_fuel.unwrap_or(DEFAULT_DESCRIBE_FUEL),
+            WasmRequest::Execute { .. } => self.limits.fuel.unwrap_or(DEFAULT_REQUEST_FUEL),
+        };
+        store.set_fuel(fuel)?;
+        let instance = self
+            .linker
+            .instantiate_and_start(&mut store, &self.module)?;
+        if let Ok(initialize) = instance.get_typed_func::<(), ()>(&store, "_initialize") {
+            initialize.call(&mut store, ())?;
+        }
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("missing memory export"))?;
+        let alloc = instance.get_typed_func::<u32, u32>(&store, EXPORT_ALLOC)?;
+        let handle = instance.get_typed_func::<(u32, u32), u64>(&store, EXPORT_HANDLE)?;
+
+        let request = serde_json::to_vec(request).map_err(|e| wasmi::Error::new(e.to_string()))?;
+        let ptr = alloc.call(&mut store, request.len() as u32)?;
+        memory.write(&mut store, ptr as usize, &request)?;
+        let packed = handle.call(&mut store, (ptr, request.len() as u32))?;
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let response = memory
+            .data(&store)
+            .get(ptr..ptr + len)
+            .ok_or_else(|| wasmi::Error::new("response out of bounds"))?;
+        serde_json::from_slice(response)
+            .map_err(|e| wasmi::Error::new(format!("invalid response: {}", e)))
+    }
+}
+
+/// 在内嵌wasm运行时中执行请求的处理器，对调用方而言与原生动态链接包的处理器一致
+#[derive(Clone)]
+pub struct WasmHandler {
+    plugin: Arc<WasmPlugin>,
+    id: String,
+    metadata: PluginMetadata,
+    schema_sdl: String,
+}
+
+impl WasmHandler {
+    /// 编译wasm插件并获取插件的描述信息，编译与执行都是同步的，在异步上下文中需要放到阻塞线程中调用
+    pub fn load(path: &Path, limits: WasmLimits) -> Result<Self, Error> {
+        let wasm = std::fs::read(path).map_err(|e| -> Error {
+            log::error!("read wasm plugin {} failed: {}", path.display(), e);
+            Error::LoadLibError
+        })?;
+        let mut config = Config::default();
+        // 加载时总是限制fuel，防止插件在生成描述信息时死循环
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let plugin = Module::new(&engine, wasm)
+            .and_then(|module| {
+                Ok(WasmPlugin {
+                    linker: host_linker(&engine)?,
+                    engine,
+                    module,
+                    limits,
+                })
+            })
+            .map_err(|e| -> Error {
+                log::error!("compile wasm plugin {} failed: {}", path.display(), e);
+                Error::LoadLibError
+            })?;
+        match plugin.call(&WasmRequest::Describe, DataContext::new()) {
+            Ok(WasmResponse::Describe {
+                id,
+                metadata,
+                schema_sdl,
+            }) => Ok(Self {
+                plugin: Arc::new(plugin),
+                id,
+                metadata,
+                schema_sdl,
+            }),
+            other => {
+                log::error!(
+                    "describe wasm plugin {} failed: {:?}",
+                    path.display(),
+                    other
+                );
+                Err(Error::LoadPluginError)
+            }
+        }
+    }
+
+    async fn execute(
+        &self,
+        context: DataContext,
+        request: serde_json::Value,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        let plugin = self.plugin.clone();
+        // 在阻塞线程中执行时延续当前的span
+        let span = tracing::info_span!("wasm_execute", plugin = %self.id);
+        let result = tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let request = WasmRequest::Execute {
+                context: context.params(),
+                request,
+            };
+            plugin.call(&request, context)
+        })
+        .await
+        .map_err(|e| {
+            log::error!("wasm plugin {} task failed: {}", self.id, e);
+            warp::reject::custom(Error::WasmError)
+        })?;
+        match result {
+            Ok(WasmResponse::Executed { body, is_ok }) => {
+                Ok(build_response(Ok((body.into_bytes(), is_ok))))
+            }
+            Ok(WasmResponse::Error(message)) => Ok(graphql_error_response(
+                StatusCode::BAD_REQUEST,
+                "BAD_REQUEST",
+                message,
+            )),
+            Ok(WasmResponse::Describe { .. }) => Err(warp::reject::custom(Error::WasmError)),
+            Err(e) if e.as_trap_code() == Some(TrapCode::OutOfFuel) => Ok(graphql_error_response(
+                StatusCode::GATEWAY_TIMEOUT,
+                "FUEL_EXHAUSTED",
+                format!("plugin {} ran out of fuel", self.id),
+            )),
+            // 插件trap（包括panic）计入插件的健康状态
+            Err(e) => Ok(plugin_panic_response(format!("wasm plugin trapped: {}", e))),
+        }
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<serde_json::Value, Rejection> {
+    serde_json::to_value(value).map_err(|e| {
+        log::error!("serialize request failed: {}", e);
+        warp::reject::custom(Error::WasmError)
+    })
+}
+
+#[async_trait]
+impl GraphqlRequestHandler for WasmHandler {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn metadata(&self) -> PluginMetadata {
+        self.metadata.clone()
+    }
+
+    fn schema_sdl(&self) -> String {
+        self.schema_sdl.clone()
+    }
+
+    async fn get_request_handle(
+        &self,
+        context: DataContext,
+        mut qry: HashMap<String, String>,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        let query = match qry.remove("query") {
+            Some(query) => query,
+            None => {
+                return Ok(build_response(Err(anyhow!(
+                    "Missing GraphQL query string in query parameters"
+                ))))
+            }
+        };
+        let variables = match qry.remove("variables").map(|vs| serde_json::from_str(&vs)) {
+            Some(Ok(variables)) => Some(variables),
+            Some(Err(e)) => return Ok(build_response(Err(e.into()))),
+            None => None,
+        };
+        let request = serde_json::json!({
+            "query": query,
+            "operationName": qry.remove("operation_name"),
+            "variables": variables.unwrap_or(serde_json::Value::Null),
+        });
+        self.execute(context, request).await
+    }
+
+    async fn post_json_request_handle(
+        &self,
+        context: DataContext,
+        req: GraphQLBatchRequest,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        // GraphQLBatchRequest没有实现Serialize，分别序列化其中的请求
+        let request = match &req {
+            GraphQLBatchRequest::Single(single) => to_json(single)?,
+            GraphQLBatchRequest::Batch(batch) => to_json(batch)?,
+        };
+        self.execute(context, request).await
+    }
+
+    async fn post_grqphql_request_handle(
+        &self,
+        context: DataContext,
+        body: Bytes,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        let query = match std::str::from_utf8(body.as_ref()) {
+            Ok(query) => query,
+            Err(e) => {
+                return Ok(build_response(Err(anyhow!(
+                    "Request body query is not a valid UTF-8 string: {}",
+                    e
+                ))))
+            }
+        };
+        self.execute(context, serde_json::json!({ "query": query }))
+            .await
+    }
+}
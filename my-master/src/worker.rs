@@ -0,0 +1,559 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use juniper::http::GraphQLBatchRequest;
+use libloading::Library;
+use my_interface::{
+    plugin_panic_response, DataContext, DataContextParams, GraphqlRequestHandler, PluginMetadata,
+    PluginName,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    os::unix::process::CommandExt as _,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    process::{Child, Command},
+    sync::oneshot,
+};
+use tracing::Instrument;
+use warp::{
+    http::{self, StatusCode},
+    Rejection,
+};
+
+use crate::{
+    check_interface_version, config::Config, graphql_error_response, telemetry, Error,
+    PrivateArtifact,
+};
+
+/// 以工作进程方式启动主服务程序时的参数：`my-master worker <动态链接包路径> <socket路径>`
+pub const WORKER_ARG: &str = "worker";
+
+// 单个消息的最大长度
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+// 等待工作进程就绪的最长时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_RESTART_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+
+/// 工作进程的资源限制，未配置的项不做限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerLimits {
+    /// 虚拟内存上限（MB）
+    pub memory_mb: Option<u64>,
+    /// CPU时间上限（秒）
+    pub cpu_secs: Option<u64>,
+}
+
+impl WorkerLimits {
+    // 解析`内存MB:CPU秒数`格式的配置，0表示不限制
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (memory, cpu) = value.split_once(':')?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u64>()
+                .ok()
+                .map(|v| Some(v).filter(|v| *v > 0))
+        };
+        Some(Self {
+            memory_mb: parse(memory)?,
+            cpu_secs: parse(cpu)?,
+        })
+    }
+}
+
+/// 插件进程外执行的配置
+#[derive(Debug, Clone, Default)]
+pub struct WorkerSettings {
+    plugins: HashSet<String>,
+    default_limits: Option<WorkerLimits>,
+    plugin_limits: HashMap<String, WorkerLimits>,
+}
+
+impl WorkerSettings {
+    pub fn new(
+        plugins: HashSet<String>,
+        default_limits: Option<WorkerLimits>,
+        plugin_limits: HashMap<String, WorkerLimits>,
+    ) -> Self {
+        Self {
+            plugins,
+            default_limits,
+            plugin_limits,
+        }
+    }
+
+    /// 读取各插件的`worker`与`worker_limits`配置，`*`为默认配置
+    pub fn from_config(config: &Config) -> Self {
+        let (default_limits, plugin_limits) = config.plugin_values(|p| p.worker_limits);
+        Self::new(
+            config.plugin_list(|p| p.worker),
+            default_limits,
+            plugin_limits,
+        )
+    }
+
+    /// 插件在工作进程中执行时返回其资源限制，否则返回None
+    pub fn limits_of(&self, plugin: &str) -> Option<WorkerLimits> {
+        if self.plugins.contains(plugin) || self.plugins.contains("*") {
+            Some(
+                self.plugin_limits
+                    .get(plugin)
+                    .copied()
+                    .or(self.default_limits)
+                    .unwrap_or_default(),
+            )
+        } else {
+            None
+        }
+    }
+}
+
+/// 主服务发送给工作进程的消息，附带调用方的链路上下文
+#[derive(Serialize, Deserialize, Debug)]
+struct WorkerCall {
+    trace: HashMap<String, String>,
+    request: WorkerRequest,
+}
+
+/// 主服务发送给工作进程的请求
+#[derive(Serialize, Deserialize, Debug)]
+enum WorkerRequest {
+    Describe,
+    Get {
+        context: DataContextParams,
+        qry: HashMap<String, String>,
+    },
+    PostJson {
+        context: DataContextParams,
+        req: serde_json::Value,
+    },
+    PostGraphql {
+        context: DataContextParams,
+        body: Vec<u8>,
+    },
+}
+
+/// 工作进程返回的响应
+#[derive(Serialize, Deserialize, Debug)]
+enum WorkerResponse {
+    Describe {
+        id: String,
+        metadata: PluginMetadata,
+        schema_sdl: String,
+    },
+    Response {
+        status: u16,
+        headers: Vec<(String, Vec<u8>)>,
+        body: Vec<u8>,
+    },
+    Rejected(String),
+}
+
+impl WorkerResponse {
+    fn from_response(response: http::Response<Vec<u8>>) -> Self {
+        let (parts, body) = response.into_parts();
+        WorkerResponse::Response {
+            status: parts.status.as_u16(),
+            headers: parts
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            body,
+        }
+    }
+}
+
+// 消息格式：4字节大端长度 + json
+async fn write_frame<W, T>(writer: &mut W, value: &T) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let data = serde_json::to_vec(value)?;
+    writer.write_u32(data.len() as u32).await?;
+    writer.write_all(&data).await?;
+    writer.flush().await
+}
+
+async fn read_frame<R, T>(reader: &mut R) -> io::Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", len),
+        ));
+    }
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+type BoxedHandler = Box<dyn GraphqlRequestHandler + Send + Sync>;
+
+/// 工作进程的入口：加载动态链接包，在Unix socket上处理主服务转发的请求
+pub async fn run_worker(lib_path: &str, socket_path: &str) {
+    pretty_env_logger::init();
+    let _telemetry = telemetry::init("my-master-worker");
+    let lib = Library::new(lib_path).expect("unable to load plugin library");
+    check_interface_version(&lib).expect("plugin interface version mismatch");
+    let handler: Arc<BoxedHandler> = {
+        let create_service: libloading::Symbol<fn() -> BoxedHandler> =
+            unsafe { lib.get(b"new_service") }.expect("unable to find new_service");
+        Arc::new(create_service())
+    };
+    if let Some(tracer) = telemetry::field_tracer() {
+        handler.set_field_tracer(tracer);
+    }
+    let _ = std::fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path).expect("unable to bind worker socket");
+    log::info!(
+        "worker of plugin {} listening on {}",
+        handler.id(),
+        socket_path
+    );
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_connection(stream, handler).await {
+                        log::error!("worker connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => log::error!("worker accept failed: {}", e),
+        }
+    }
+}
+
+async fn serve_connection(mut stream: UnixStream, handler: Arc<BoxedHandler>) -> io::Result<()> {
+    loop {
+        let call: WorkerCall = match read_frame(&mut stream).await {
+            Ok(call) => call,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let span = telemetry::span_with_parent(
+            tracing::info_span!("worker_execute", plugin = %handler.id()),
+            &call.trace,
+        );
+        let response = handle_request(handler.as_ref(), call.request)
+            .instrument(span)
+            .await;
+        write_frame(&mut stream, &response).await?;
+    }
+}
+
+async fn handle_request(handler: &BoxedHandler, request: WorkerRequest) -> WorkerResponse {
+    let result = match request {
+        WorkerRequest::Describe => {
+            return WorkerResponse::Describe {
+                id: handler.id(),
+                metadata: handler.metadata(),
+                schema_sdl: handler.schema_sdl(),
+            }
+        }
+        WorkerRequest::Get { context, qry } => {
+            handler
+                .get_request_handle(DataContext::from_params(context), qry)
+                .await
+        }
+        WorkerRequest::PostJson { context, req } => match serde_json::from_value(req) {
+            Ok(req) => {
+                handler
+                    .post_json_request_handle(DataContext::from_params(context), req)
+                    .await
+            }
+            Err(e) => return WorkerResponse::Rejected(e.to_string()),
+        },
+        WorkerRequest::PostGraphql { context, body } => {
+            handler
+                .post_grqphql_request_handle(DataContext::from_params(context), Bytes::from(body))
+                .await
+        }
+    };
+    match result {
+        Ok(response) => WorkerResponse::from_response(response),
+        Err(rejection) => WorkerResponse::Rejected(format!("{:?}", rejection)),
+    }
+}
+
+// 在子进程中设置资源限制，并在主服务退出时结束工作进程
+fn apply_limits(limits: WorkerLimits) -> io::Result<()> {
+    let check = |ret: libc::c_int| {
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    };
+    let rlimit = |value: u64| libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    if let Some(memory_mb) = limits.memory_mb {
+        check(unsafe { libc::setrlimit(libc::RLIMIT_AS, &rlimit(memory_mb * 1024 * 1024)) })?;
+    }
+    if let Some(cpu_secs) = limits.cpu_secs {
+        check(unsafe { libc::setrlimit(libc::RLIMIT_CPU, &rlimit(cpu_secs)) })?;
+    }
+    #[cfg(target_os = "linux")]
+    check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) })?;
+    Ok(())
+}
+
+fn spawn_worker(lib_path: &Path, socket_path: &Path, limits: WorkerLimits) -> io::Result<Child> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .arg(WORKER_ARG)
+        .arg(lib_path)
+        .arg(socket_path)
+        .stdin(Stdio::null());
+    unsafe {
+        command.pre_exec(move || apply_limits(limits));
+    }
+    Command::from(command).kill_on_drop(true).spawn()
+}
+
+// 监控工作进程，退出后按指数退避重启，收到停止信号（发送端释放）后结束工作进程
+async fn supervise(
+    plugin: String,
+    lib_path: PathBuf,
+    socket_path: PathBuf,
+    limits: WorkerLimits,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let mut backoff = MIN_RESTART_BACKOFF;
+    loop {
+        let started = Instant::now();
+        match spawn_worker(&lib_path, &socket_path, limits) {
+            Ok(mut child) => {
+                tokio::select! {
+                    status = child.wait() => {
+                        log::error!("worker of plugin {} exited: {:?}, restarting", plugin, status);
+                    }
+                    _ = &mut shutdown => {
+                        let _ = child.kill().await;
+                        break;
+                    }
+                }
+            }
+            Err(e) => log::error!("failed to spawn worker of plugin {}: {}", plugin, e),
+        }
+        // 稳定运行一段时间后重置退避时间
+        if started.elapsed() > MAX_RESTART_BACKOFF {
+            backoff = MIN_RESTART_BACKOFF;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = &mut shutdown => break,
+        }
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+    }
+    log::info!("worker of plugin {} stopped", plugin);
+    let _ = std::fs::remove_file(&socket_path);
+}
+
+#[derive(Debug)]
+enum CallError {
+    /// 工作进程未就绪
+    Unavailable(io::Error),
+    /// 请求过程中连接断开，通常是工作进程崩溃
+    Crashed(io::Error),
+}
+
+/// 工作进程，释放时停止监控任务并结束进程，删除动态链接包的副本与socket所在的私有目录
+struct WorkerProcess {
+    socket_path: PathBuf,
+    _shutdown: oneshot::Sender<()>,
+    _artifact: PrivateArtifact,
+}
+
+impl WorkerProcess {
+    // 工作进程启动或重启期间会重试连接
+    async fn connect(&self) -> io::Result<UnixStream> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            match UnixStream::connect(&self.socket_path).await {
+                Ok(stream) => return Ok(stream),
+                Err(_) if Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(50)).await
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn call(&self, request: WorkerRequest) -> Result<WorkerResponse, CallError> {
+        let call = WorkerCall {
+            trace: telemetry::current_context(),
+            request,
+        };
+        let mut stream = self.connect().await.map_err(CallError::Unavailable)?;
+        write_frame(&mut stream, &call)
+            .await
+            .map_err(CallError::Crashed)?;
+        read_frame(&mut stream).await.map_err(CallError::Crashed)
+    }
+}
+
+/// 在工作进程中执行请求的处理器，对调用方而言与进程内加载的处理器一致
+#[derive(Clone)]
+pub struct RemoteHandler {
+    process: Arc<WorkerProcess>,
+    id: String,
+    metadata: PluginMetadata,
+    schema_sdl: String,
+}
+
+impl RemoteHandler {
+    /// 以校验过的动态链接包副本启动插件的工作进程，就绪后获取插件的描述信息，工作进程重启时依然加载该副本
+    pub async fn spawn(
+        plugin: &PluginName,
+        artifact: PrivateArtifact,
+        limits: WorkerLimits,
+    ) -> Result<Self, Error> {
+        // socket与动态链接包的副本放在同一个只有当前用户可以访问的随机目录中，其他用户无法抢先绑定或连接
+        let socket_path = artifact.dir().join(format!("{}.sock", plugin));
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        tokio::spawn(supervise(
+            plugin.to_string(),
+            artifact.path().to_path_buf(),
+            socket_path.clone(),
+            limits,
+            shutdown_rx,
+        ));
+        let process = Arc::new(WorkerProcess {
+            socket_path,
+            _shutdown: shutdown,
+            _artifact: artifact,
+        });
+        match process.call(WorkerRequest::Describe).await {
+            Ok(WorkerResponse::Describe {
+                id,
+                metadata,
+                schema_sdl,
+            }) => Ok(Self {
+                process,
+                id,
+                metadata,
+                schema_sdl,
+            }),
+            other => {
+                log::error!("worker of plugin {} failed to start: {:?}", plugin, other);
+                Err(Error::LoadPluginError)
+            }
+        }
+    }
+
+    async fn forward(&self, request: WorkerRequest) -> Result<http::Response<Vec<u8>>, Rejection> {
+        match self.process.call(request).await {
+            Ok(WorkerResponse::Response {
+                status,
+                headers,
+                body,
+            }) => {
+                let mut builder = http::Response::builder().status(status);
+                for (name, value) in headers {
+                    builder = builder.header(name.as_str(), value);
+                }
+                builder.body(body).map_err(|e| {
+                    log::error!("invalid response from worker of plugin {}: {}", self.id, e);
+                    warp::reject::custom(Error::WorkerError)
+                })
+            }
+            Ok(WorkerResponse::Rejected(message)) => {
+                log::error!("worker of plugin {} rejected: {}", self.id, message);
+                Err(warp::reject::custom(Error::WorkerError))
+            }
+            Ok(WorkerResponse::Describe { .. }) => Err(warp::reject::custom(Error::WorkerError)),
+            Err(CallError::Unavailable(e)) => {
+                log::error!("worker of plugin {} unavailable: {}", self.id, e);
+                Ok(graphql_error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "WORKER_UNAVAILABLE",
+                    "plugin worker unavailable".to_string(),
+                ))
+            }
+            // 工作进程崩溃按panic处理，计入插件的健康状态
+            Err(CallError::Crashed(e)) => Ok(plugin_panic_response(format!(
+                "plugin worker crashed: {}",
+                e
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl GraphqlRequestHandler for RemoteHandler {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn metadata(&self) -> PluginMetadata {
+        self.metadata.clone()
+    }
+
+    fn schema_sdl(&self) -> String {
+        self.schema_sdl.clone()
+    }
+
+    async fn get_request_handle(
+        &self,
+        context: DataContext,
+        qry: HashMap<String, String>,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        self.forward(WorkerRequest::Get {
+            context: context.params(),
+            qry,
+        })
+        .await
+    }
+
+    async fn post_json_request_handle(
+        &self,
+        context: DataContext,
+        req: GraphQLBatchRequest,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        // GraphQLBatchRequest没有实现Serialize，分别序列化其中的请求
+        let req = match &req {
+            GraphQLBatchRequest::Single(single) => serde_json::to_value(single),
+            GraphQLBatchRequest::Batch(batch) => serde_json::to_value(batch),
+        }
+        .map_err(|e| {
+            log::error!("serialize request failed: {}", e);
+            warp::reject::custom(Error::WorkerError)
+        })?;
+        self.forward(WorkerRequest::PostJson {
+            context: context.params(),
+            req,
+        })
+        .await
+    }
+
+    async fn post_grqphql_request_handle(
+        &self,
+        context: DataContext,
+        body: Bytes,
+    ) -> Result<http::Response<Vec<u8>>, Rejection> {
+        self.forward(WorkerRequest::PostGraphql {
+            context: context.params(),
+            body: body.to_vec(),
+        })
+        .await
+    }
+}
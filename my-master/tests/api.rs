@@ -0,0 +1,1288 @@
+//! 主服务HTTP接口的端到端测试，以`warp::test`直接调用路由，不绑定端口。
+//! 插件使用预先编译的示例插件，编译结果缓存在testkit的目录中，编译时不访问网络
+use std::{
+    convert::Infallible,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use futures::future::join_all;
+use my_interface::PluginName;
+use my_master::{
+    auth::Authenticator,
+    config::{Config, PreloadFailure},
+    route::{preload_plugins, routes, StateContext},
+    HandlerStorage, StorageLock,
+};
+use my_plugin_builder::demo;
+use my_plugin_testkit::TestKit;
+use serde_json::{json, Value};
+use warp::{http::StatusCode, hyper::body::Bytes, test::request, Filter, Reply};
+
+static FIXTURE: OnceLock<PathBuf> = OnceLock::new();
+static BREAKING_FIXTURE: OnceLock<PathBuf> = OnceLock::new();
+static TEST_DIR_SEQ: AtomicU64 = AtomicU64::new(0);
+
+fn build_foo(tokens: proc_macro2::TokenStream) -> PathBuf {
+    TestKit::new()
+        .offline()
+        .build_tokens(&PluginName::new("foo").unwrap(), tokens, &[])
+        .expect("unable to build plugin fixture")
+}
+
+// 预先编译的foo插件，整个测试进程只编译一次
+fn fixture() -> &'static Path {
+    FIXTURE.get_or_init(|| build_foo(demo::foo::genernate_tokens()))
+}
+
+// 删除了Boo.light字段的foo插件，用于测试schema的破坏性变更
+fn breaking_fixture() -> &'static Path {
+    BREAKING_FIXTURE.get_or_init(|| {
+        let tokens = demo::foo::genernate_tokens().to_string();
+        let light = "fn light (& self) -> Light { self . po . light . clone () }";
+        assert!(tokens.contains(light));
+        build_foo(tokens.replacen(light, "", 1).parse().unwrap())
+    })
+}
+
+/// 每个测试独立的libs目录，释放时删除
+struct LibsDir(PathBuf);
+
+impl LibsDir {
+    fn with_fixture() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "my-master-api-{}-{}",
+            std::process::id(),
+            TEST_DIR_SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let libs = Self(dir);
+        libs.install(fixture());
+        libs
+    }
+
+    // 以硬链接放入预先编译的插件、校验文件与接口版本文件，替换已有的文件
+    fn install(&self, lib: &Path) {
+        self.install_as(lib, "lib_foo.so");
+    }
+
+    // 放入预先编译的插件作为金丝雀版本的编译产物
+    fn install_canary(&self, lib: &Path, version: &str) {
+        self.install_as(lib, &format!("lib_foo@{}.so", version));
+    }
+
+    fn install_as(&self, lib: &Path, name: &str) {
+        for suffix in ["", ".sha256", ".interface"] {
+            let src = lib.with_file_name(format!("lib_foo.so{}", suffix));
+            let dst = self.0.join(format!("{}{}", name, suffix));
+            let _ = fs::remove_file(&dst);
+            if fs::hard_link(&src, &dst).is_err() {
+                fs::copy(&src, &dst).unwrap();
+            }
+        }
+    }
+}
+
+impl Drop for LibsDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+struct TestServer {
+    storage: StateContext,
+    _libs: LibsDir,
+}
+
+fn server(
+    extra_config: &str,
+) -> (
+    TestServer,
+    impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone,
+) {
+    server_in(LibsDir::with_fixture(), extra_config)
+}
+
+// 开启鉴权的服务，鉴权配置写入libs目录下的auth.json
+fn auth_server(
+    auth: Value,
+    extra_config: &str,
+) -> (
+    TestServer,
+    impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone,
+) {
+    let libs = LibsDir::with_fixture();
+    let path = libs.0.join("auth.json");
+    fs::write(&path, auth.to_string()).unwrap();
+    let extra_config = format!(
+        "auth_config = {:?}\n{}",
+        path.display().to_string(),
+        extra_config
+    );
+    server_in(libs, &extra_config)
+}
+
+fn server_in(
+    libs: LibsDir,
+    extra_config: &str,
+) -> (
+    TestServer,
+    impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone,
+) {
+    let mut config: Config = toml::from_str(&format!(
+        "libs_dir = {:?}\n{}",
+        libs.0.display().to_string(),
+        extra_config
+    ))
+    .expect("config is valid");
+    config.reload_interval_secs = 0;
+    let storage = Arc::new(StorageLock::new(HandlerStorage::from_config(&config)));
+    let filter = routes(&config, storage.clone()).expect("routes are valid");
+    (
+        TestServer {
+            storage,
+            _libs: libs,
+        },
+        filter,
+    )
+}
+
+fn json_body(body: &Bytes) -> Value {
+    serde_json::from_slice(body).unwrap_or_else(|_| panic!("not json: {:?}", body))
+}
+
+const FOO_QUERY: &str = "/api/foo/graphql/dev?query=%7Bfoo(id%3A3)%7Bname%7D%7D";
+
+#[tokio::test]
+async fn home_and_health() {
+    let (_server, api) = server("");
+    let response = request().path("/").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body(), "it works");
+
+    let response = request().path("/healthz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "ready": true, "missing": [] })
+    );
+}
+
+// 预加载的插件加载成功后即就绪，之后被定时清理卸载也不影响就绪
+#[tokio::test]
+async fn readiness_survives_eviction() {
+    let (server, api) = server("preload = [\"foo\"]\n[eviction]\npolicy = \"all\"\n");
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "ready": false, "missing": ["foo"] })
+    );
+
+    let response = request().path("/control/add/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(server.storage.write().await.evict(), ["foo"]);
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+// 以Warn模式预加载失败的插件在使用时再加载，不影响就绪；热加载配置新增的预加载插件同样按Warn模式加载
+#[tokio::test]
+async fn readiness_ignores_warned_preload_failures() {
+    let (server, api) = server("preload = [\"foo\", \"baz\"]\n");
+    let plugins = server.storage.read().await.preload().to_vec();
+    preload_plugins(&server.storage, &plugins, PreloadFailure::Fail)
+        .await
+        .unwrap_err();
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "ready": false, "missing": ["baz"] })
+    );
+    preload_plugins(&server.storage, &plugins, PreloadFailure::Warn)
+        .await
+        .unwrap();
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let config: Config = toml::from_str("preload = [\"foo\", \"baz\", \"qux\"]\n").unwrap();
+    let added = server.storage.write().await.reload(&config);
+    assert_eq!(added, [PluginName::new("qux").unwrap()]);
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "ready": false, "missing": ["qux"] })
+    );
+    preload_plugins(&server.storage, &added, PreloadFailure::Warn)
+        .await
+        .unwrap();
+    let response = request().path("/readyz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn build_skips_existing_lib() {
+    let (_server, api) = server("");
+    let response = request().path("/build/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response.body()), json!("ok"));
+
+    let response = request().path("/build/baz").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.body(), "demo not support");
+}
+
+#[tokio::test]
+async fn query_loads_plugin_on_use() {
+    let (_server, api) = server("");
+    let response = request().path("/plugins/foo/health").reply(&api).await;
+    assert_eq!(json_body(response.body())["state"], "unloaded");
+
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "data": { "foo": { "name": "foo3" } } })
+    );
+
+    let response = request().path("/plugins/foo/health").reply(&api).await;
+    let status = json_body(response.body());
+    assert_eq!(status["state"], "loaded");
+    assert_eq!(status["version"], "0.1.0");
+}
+
+#[tokio::test]
+async fn post_json_and_graphql() {
+    let (_server, api) = server("");
+    let response = request()
+        .method("POST")
+        .path("/api/foo/graphql/true")
+        .json(&json!({ "query": "{ foos { name } }" }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut names: Vec<String> = json_body(response.body())["data"]["foos"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|foo| foo["name"].as_str().unwrap().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["foo3", "foo4"]);
+
+    let response = request()
+        .method("POST")
+        .path("/api/foo/graphql/false")
+        .header("content-type", "application/graphql")
+        .body("{ foo(id: 1) { id name } }")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "data": { "foo": { "id": 1, "name": "foo1" } } })
+    );
+}
+
+#[tokio::test]
+async fn control_add_metadata_remove() {
+    let (server, api) = server("[plugins.foo.limits]\nmax_depth = 5\n");
+    let response = request().path("/control/add/foo").reply(&api).await;
+    assert_eq!(json_body(response.body()), json!("ok"));
+    let response = request().path("/control/add/foo").reply(&api).await;
+    assert_eq!(json_body(response.body()), json!("already has handler"));
+
+    let response = request().path("/control/metadata/foo").reply(&api).await;
+    let metadata = json_body(response.body());
+    assert_eq!(metadata["name"], "foo");
+    // 返回生效的查询限制：配置的限制与插件声明的字段开销
+    assert_eq!(metadata["limits"]["max_depth"], 5);
+    assert_eq!(metadata["limits"]["field_costs"]["bars"], 5);
+
+    let response = request().path("/control/remove/foo").reply(&api).await;
+    assert_eq!(json_body(response.body()), json!("ok"));
+    assert!(!server.storage.read().await.has_handler("foo".to_string()));
+
+    // 卸载后再次请求时重新加载
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(server.storage.read().await.has_handler("foo".to_string()));
+}
+
+#[tokio::test]
+async fn graphiql_page() {
+    let (_server, api) = server("");
+    let response = request().path("/api/foo/graphiql/true").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/html;charset=utf-8"
+    );
+    let html = String::from_utf8_lossy(response.body());
+    assert!(html.contains("/api/foo/graphql/true"));
+}
+
+const JWT_SECRET: &str = "test-secret";
+
+fn auth_config(required: bool) -> Value {
+    json!({
+        "required": required,
+        "api_keys": [
+            {"key": "foo-key", "subject": "foo-client", "plugins": ["foo"]},
+            {"key": "bar-key", "subject": "bar-client", "plugins": ["bar"]},
+            {"key": "admin-key", "subject": "admin", "scopes": ["admin"]}
+        ],
+        "jwt_keys": [{"kid": "test", "algorithm": "HS256", "key": JWT_SECRET}],
+        "jwt_issuer": "test-issuer"
+    })
+}
+
+fn jwt(secret: &str, claims: Value) -> String {
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256);
+    header.kid = Some("test".to_string());
+    jsonwebtoken::encode(
+        &header,
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap()
+}
+
+fn jwt_claims(sub: &str) -> Value {
+    json!({"sub": sub, "iss": "test-issuer", "exp": 4_000_000_000u64})
+}
+
+#[tokio::test]
+async fn api_key_authentication() {
+    let (_server, api) = auth_server(auth_config(true), "");
+    let status = |key: Option<&'static str>, path: &'static str| {
+        let api = api.clone();
+        async move {
+            let mut req = request().path(path);
+            if let Some(key) = key {
+                req = req.header("x-api-key", key);
+            }
+            req.reply(&api).await.status()
+        }
+    };
+    assert_eq!(status(Some("foo-key"), FOO_QUERY).await, StatusCode::OK);
+    assert_eq!(status(None, FOO_QUERY).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        status(Some("wrong"), FOO_QUERY).await,
+        StatusCode::UNAUTHORIZED
+    );
+    // 该API Key只允许调用bar插件
+    assert_eq!(
+        status(Some("bar-key"), FOO_QUERY).await,
+        StatusCode::FORBIDDEN
+    );
+    // graphiql页面同样需要鉴权
+    let graphiql = "/api/foo/graphiql/dev";
+    assert_eq!(status(None, graphiql).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        status(Some("bar-key"), graphiql).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(status(Some("foo-key"), graphiql).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn jwt_authentication() {
+    let (_server, api) = auth_server(auth_config(true), "");
+    let status = |authorization: String, api_key: Option<&'static str>| {
+        let api = api.clone();
+        async move {
+            let mut req = request()
+                .path(FOO_QUERY)
+                .header("authorization", authorization);
+            if let Some(key) = api_key {
+                req = req.header("x-api-key", key);
+            }
+            req.reply(&api).await.status()
+        }
+    };
+    let token = jwt(JWT_SECRET, jwt_claims("jwt-client"));
+    assert_eq!(
+        status(format!("Bearer {}", token), None).await,
+        StatusCode::OK
+    );
+    let forged = jwt("other-secret", jwt_claims("jwt-client"));
+    assert_eq!(
+        status(format!("Bearer {}", forged), None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let mut claims = jwt_claims("jwt-client");
+    claims["iss"] = json!("other-issuer");
+    let wrong_issuer = jwt(JWT_SECRET, claims);
+    assert_eq!(
+        status(format!("Bearer {}", wrong_issuer), None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let mut claims = jwt_claims("jwt-client");
+    claims["plugins"] = json!(["bar"]);
+    let bar_only = jwt(JWT_SECRET, claims);
+    assert_eq!(
+        status(format!("Bearer {}", bar_only), None).await,
+        StatusCode::FORBIDDEN
+    );
+    // 同时带有token与API Key时无法确定调用方
+    assert_eq!(
+        status(format!("Bearer {}", token), Some("foo-key")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    // 强制鉴权时非Bearer的Authorization不能通过鉴权
+    assert_eq!(
+        status("Basic Zm9vOmJhcg==".to_string(), None).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn admin_api_requires_admin_scope() {
+    let (_server, api) = auth_server(auth_config(false), "");
+    let register = |key: Option<&'static str>| {
+        let api = api.clone();
+        async move {
+            let mut req = request()
+                .method("POST")
+                .path("/persisted/foo")
+                .json(&json!(["{ foo(id: 1) { id } }"]));
+            if let Some(key) = key {
+                req = req.header("x-api-key", key);
+            }
+            req.reply(&api).await.status()
+        }
+    };
+    assert_eq!(register(None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(register(Some("foo-key")).await, StatusCode::FORBIDDEN);
+    assert_eq!(register(Some("admin-key")).await, StatusCode::OK);
+
+    for path in [
+        "/control/add/foo",
+        "/build/foo",
+        "/admin/rate-limits",
+        "/admin/shadow",
+        "/plugins/foo/health",
+    ] {
+        let response = request().path(path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", path);
+        let response = request()
+            .path(path)
+            .header("x-api-key", "foo-key")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+        let response = request()
+            .path(path)
+            .header("x-api-key", "admin-key")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn optional_authentication() {
+    let (_server, api) = auth_server(auth_config(false), "");
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // 未强制鉴权时非Bearer的Authorization按匿名调用处理
+    let response = request()
+        .path(FOO_QUERY)
+        .header("authorization", "Basic Zm9vOmJhcg==")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // 带了凭证就必须是有效的
+    let response = request()
+        .path(FOO_QUERY)
+        .header("x-api-key", "wrong")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn auth_config_reloads() {
+    let libs = LibsDir::with_fixture();
+    let path = libs.0.join("auth.json");
+    fs::write(&path, auth_config(true).to_string()).unwrap();
+    let config = Config {
+        auth_config: Some(path.clone()),
+        ..Config::default()
+    };
+    let auth = Authenticator::from_config(&config).unwrap();
+    let authenticate = |key: &str| {
+        auth.current()
+            .authenticate(None, Some(key.to_string()))
+            .map(|caller| caller.unwrap().identity.subject)
+    };
+    assert_eq!(authenticate("foo-key").unwrap(), "foo-client");
+
+    // 修改鉴权配置文件后重新加载，新的key生效，删除的key失效
+    fs::write(
+        &path,
+        json!({"required": true, "api_keys": [{"key": "new-key", "subject": "new-client"}]})
+            .to_string(),
+    )
+    .unwrap();
+    auth.reload(&config).unwrap();
+    assert_eq!(authenticate("new-key").unwrap(), "new-client");
+    assert!(authenticate("foo-key").is_err());
+
+    // 无效的鉴权配置不会替换原来的配置
+    fs::write(&path, "not json").unwrap();
+    assert!(auth.reload(&config).is_err());
+    assert_eq!(authenticate("new-key").unwrap(), "new-client");
+}
+
+#[tokio::test]
+async fn rejection_mapping() {
+    let (_server, api) = server("");
+    let cases = [
+        ("/no/such/route", StatusCode::NOT_FOUND, "Not Found"),
+        ("/build/Foo", StatusCode::BAD_REQUEST, "invalid plugin name"),
+        (
+            "/api/..%2Flibs/graphql/false",
+            StatusCode::BAD_REQUEST,
+            "invalid plugin name",
+        ),
+        (
+            "/api/bar/graphql/false?query=%7Bbars%7Bid%7D%7D",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "no such plugin error",
+        ),
+        (
+            "/control/add/bar",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "no such plugin error",
+        ),
+        ("/control/restart/foo", StatusCode::NOT_FOUND, "Not Found"),
+        ("/plugins/bar/health", StatusCode::NOT_FOUND, "Not Found"),
+    ];
+    for (path, status, message) in cases {
+        let response = request().path(path).reply(&api).await;
+        assert_eq!(response.status(), status, "{}", path);
+        assert_eq!(response.body(), message, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn query_limits_are_enforced() {
+    let (_server, api) = server(
+        "[plugins.\"*\".limits]\nmax_aliases = 20\n[plugins.foo.limits]\nmax_aliases = 10\n",
+    );
+    // foo配置的别名数为10，覆盖了`*`的默认配置
+    let aliases: String = (0..11)
+        .map(|i| format!("a{}: foo(id: 1) {{ id }} ", i))
+        .collect();
+    let query = format!("{{ {}}}", aliases);
+    let response = request()
+        .method("POST")
+        .path("/api/foo/graphql/false")
+        .json(&json!({ "query": query }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        json_body(response.body())["errors"][0]["extensions"]["code"],
+        "QUERY_LIMIT_EXCEEDED"
+    );
+    // 无法解析的查询直接拒绝
+    let response = request()
+        .method("POST")
+        .path("/api/foo/graphql/false")
+        .json(&json!({ "query": "{ foo(id: 1) { id }" }))
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        json_body(response.body())["errors"][0]["extensions"]["code"],
+        "GRAPHQL_PARSE_FAILED"
+    );
+}
+
+#[tokio::test]
+async fn rate_limited() {
+    let (_server, api) = server(
+        r#"
+        [plugins.foo.rate_limit]
+        capacity = 1
+        refill_per_sec = 0.001
+        "#,
+    );
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key("retry-after"));
+}
+
+// POST请求不论body的格式只消耗一个令牌，被限流的请求只记录一次
+#[tokio::test]
+async fn post_consumes_one_token() {
+    let (_server, api) = server(
+        r#"
+        [plugins.foo.rate_limit]
+        capacity = 2
+        refill_per_sec = 0.001
+        "#,
+    );
+    let post_json = || {
+        request()
+            .method("POST")
+            .path("/api/foo/graphql/dev")
+            .json(&json!({ "query": "{ foo(id: 1) { id } }" }))
+    };
+    let post_graphql = || {
+        request()
+            .method("POST")
+            .path("/api/foo/graphql/dev")
+            .header("content-type", "application/graphql")
+            .body("{ foo(id: 1) { id } }")
+    };
+    assert_eq!(post_json().reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(post_graphql().reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(
+        post_json().reply(&api).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        post_graphql().reply(&api).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let response = request().path("/admin/rate-limits").reply(&api).await;
+    let buckets = json_body(response.body());
+    assert_eq!(buckets.as_array().unwrap().len(), 1, "{}", buckets);
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    let count = |name: &str| {
+        metrics
+            .lines()
+            .filter(|line| line.starts_with(&format!("my_master_{}{{", name)))
+            .filter(|line| line.contains("plugin=\"foo\""))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .sum::<u64>()
+    };
+    assert_eq!(count("plugin_requests_total"), 4, "{}", metrics);
+    assert_eq!(count("plugin_errors_total"), 2, "{}", metrics);
+}
+
+#[tokio::test]
+async fn tampered_lib_is_refused() {
+    let (server, api) = server("");
+    let checksum = server._libs.0.join("lib_foo.so.sha256");
+    // 硬链接共用内容，只替换校验文件
+    fs::remove_file(&checksum).unwrap();
+    fs::write(&checksum, format!("{}  lib_foo.so\n", "0".repeat(64))).unwrap();
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.body(), "plugin checksum error");
+    let response = request().path("/plugins/foo/health").reply(&api).await;
+    assert!(json_body(response.body())["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("checksum"));
+}
+
+// 插件首次被并发请求时只加载一次
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_first_use_loads_once() {
+    let (_server, api) = server("");
+    let responses = join_all((0..16).map(|_| request().path(FOO_QUERY).reply(&api))).await;
+    for response in responses {
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    let lifecycle = |event: &str| {
+        metrics
+            .lines()
+            .filter(|line| line.contains("plugin_lifecycle_total{"))
+            .filter(|line| line.contains(&format!("event=\"{}\"", event)))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .sum::<u64>()
+    };
+    assert_eq!(lifecycle("load"), 1, "{}", metrics);
+    assert_eq!(lifecycle("reload"), 0, "{}", metrics);
+}
+
+// 请求指标只以加载过或配置了的插件作为标签，其他插件名合并为unknown
+#[tokio::test]
+async fn unknown_plugins_share_metric_label() {
+    let (_server, api) = server("[plugins.bar]\ntimeout_secs = 5\n");
+    for name in ["nosuch1", "nosuch2", "bar"] {
+        let path = format!("/api/{}/graphql/dev?query=%7Bbars%7Bid%7D%7D", name);
+        let response = request().path(&path).reply(&api).await;
+        assert!(response.status().is_client_error() || response.status().is_server_error());
+    }
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    let requests = |plugin: &str| {
+        metrics
+            .lines()
+            .filter(|line| line.starts_with("my_master_plugin_requests_total{"))
+            .filter(|line| line.contains(&format!("plugin=\"{}\"", plugin)))
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .sum::<u64>()
+    };
+    assert_eq!(requests("unknown"), 2, "{}", metrics);
+    assert_eq!(requests("bar"), 1, "{}", metrics);
+    assert_eq!(requests("foo"), 1, "{}", metrics);
+    assert!(!metrics.contains("nosuch"), "{}", metrics);
+}
+
+// 请求执行期间插件被卸载、重新加载，请求依然成功
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn reload_under_traffic() {
+    let (_server, api) = server("");
+    let queries = join_all((0..32).map(|_| request().path(FOO_QUERY).reply(&api)));
+    let reloads = async {
+        for _ in 0..8 {
+            request().path("/control/remove/foo").reply(&api).await;
+            request().path("/control/add/foo").reply(&api).await;
+        }
+    };
+    let (responses, _) = futures::join!(queries, reloads);
+    for response in responses {
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json_body(response.body()),
+            json!({ "data": { "foo": { "name": "foo3" } } })
+        );
+    }
+}
+
+#[tokio::test]
+async fn reload_reports_schema_changes() {
+    let (_server, api) = server("");
+    // 未加载时直接加载
+    let response = request().path("/control/reload/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json_body(response.body());
+    assert_eq!(report["reloaded"], true);
+    assert_eq!(report["changes"], json!([]));
+
+    // schema没有变化
+    let response = request().path("/control/reload/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        json_body(response.body()),
+        json!({
+            "plugin": "foo",
+            "reloaded": true,
+            "forced": false,
+            "breaking": 0,
+            "dangerous": 0,
+            "safe": 0,
+            "changes": []
+        })
+    );
+
+    let response = request().path("/control/reload/bar").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.body(), "no such plugin error");
+}
+
+// 重新加载被隔离的插件后解除隔离
+#[tokio::test]
+async fn reload_releases_quarantine() {
+    let (server, api) = server("");
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let previous = server.storage.read().await.get_handler("foo".to_string());
+    let previous = previous.unwrap();
+    {
+        let mut write_guard = server.storage.write().await;
+        assert!(write_guard.is_stable("foo", &previous));
+        let health = write_guard.health("foo").unwrap();
+        while !health.record_panic("boom".to_string()) {}
+        write_guard.remove_handler("foo".to_string());
+    }
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = request().path("/control/reload/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request().path(FOO_QUERY).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request().path("/plugins/foo/health").reply(&api).await;
+    assert_eq!(json_body(response.body())["state"], "loaded");
+    // 重新加载前取出的处理器不再是稳定版本，它的panic不会卸载新的处理器
+    assert!(!server.storage.read().await.is_stable("foo", &previous));
+}
+
+#[tokio::test]
+async fn breaking_reload_requires_force() {
+    let (server, api) = server("");
+    let light = "/api/foo/graphql/false?query=%7Bfoo(id%3A1)%7Bbars%7Blight%7D%7D%7D";
+    let response = request().path(light).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server._libs.install(breaking_fixture());
+    let response = request().path("/control/reload/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let report = json_body(response.body());
+    assert_eq!(report["reloaded"], false);
+    // 没有字段引用后Light类型也从schema中删除
+    assert_eq!(report["breaking"], 2);
+    assert_eq!(
+        report["changes"],
+        json!([
+            { "level": "breaking", "path": "Boo.light", "message": "field removed" },
+            { "level": "breaking", "path": "Light", "message": "type removed" }
+        ])
+    );
+    // 被阻止时保留已加载的插件
+    let response = request().path(light).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(json_body(response.body()).get("errors").is_none());
+
+    let response = request()
+        .path("/control/reload/foo?force=true")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json_body(response.body());
+    assert_eq!(report["reloaded"], true);
+    assert_eq!(report["forced"], true);
+    let response = request().path(light).reply(&api).await;
+    assert!(json_body(response.body())["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("light"));
+}
+
+// 请求foos，返回排序后的名称
+async fn foo_names<F>(api: &F, path: &str, tenant_header: Option<&str>) -> (StatusCode, Vec<String>)
+where
+    F: Filter + 'static,
+    F::Extract: Reply + Send,
+{
+    let mut req = request().path(&format!("{}?query=%7Bfoos%7Bname%7D%7D", path));
+    if let Some(tenant) = tenant_header {
+        req = req.header("x-tenant", tenant);
+    }
+    let response = req.reply(api).await;
+    let body: Value = serde_json::from_slice(response.body()).unwrap_or(Value::Null);
+    let mut names: Vec<String> = body["data"]["foos"]
+        .as_array()
+        .map(|foos| {
+            foos.iter()
+                .map(|item| item["name"].as_str().unwrap().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    (response.status(), names)
+}
+
+#[tokio::test]
+async fn tenants_select_datasets() {
+    let (_server, api) = server("");
+    let master = (StatusCode::OK, vec!["foo1".to_string(), "foo2".to_string()]);
+    let dev = (StatusCode::OK, vec!["foo3".to_string(), "foo4".to_string()]);
+    assert_eq!(
+        foo_names(&api, "/api/foo/graphql/master", None).await,
+        master
+    );
+    assert_eq!(foo_names(&api, "/api/foo/graphql/dev", None).await, dev);
+    // 兼容原有的flag路径
+    assert_eq!(
+        foo_names(&api, "/api/foo/graphql/false", None).await,
+        master
+    );
+    assert_eq!(foo_names(&api, "/api/foo/graphql/true", None).await, dev);
+    // 路径中没有租户时取请求头，都没有时为默认租户，路径中的租户优先
+    assert_eq!(foo_names(&api, "/api/foo/graphql", None).await, master);
+    assert_eq!(foo_names(&api, "/api/foo/graphql", Some("dev")).await, dev);
+    assert_eq!(
+        foo_names(&api, "/api/foo/graphql/master", Some("dev")).await,
+        master
+    );
+
+    // 租户之间的数据相互隔离
+    let response = request()
+        .path("/api/foo/graphql/master?query=%7Bfoo(id%3A3)%7Bname%7D%7D")
+        .reply(&api)
+        .await;
+    assert_eq!(
+        json_body(response.body()),
+        json!({ "data": { "foo": null } })
+    );
+
+    let response = request()
+        .path("/api/foo/graphql/staging?query=%7Bfoos%7Bname%7D%7D")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.body(), "no such tenant");
+
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    assert!(metrics.contains("tenant=\"dev\""), "{}", metrics);
+    assert!(!metrics.contains("tenant=\"staging\""), "{}", metrics);
+}
+
+#[tokio::test]
+async fn tenants_enable_plugins() {
+    let (_server, api) = server(
+        r#"
+        default_tenant = "acme"
+
+        [tenants.acme]
+        dataset = "dev"
+        plugins = ["foo"]
+
+        [tenants.globex]
+        dataset = "master"
+        plugins = ["bar"]
+        "#,
+    );
+    assert_eq!(
+        foo_names(&api, "/api/foo/graphql", None).await,
+        (StatusCode::OK, vec!["foo3".to_string(), "foo4".to_string()])
+    );
+    let response = request()
+        .path("/api/foo/graphql/globex?query=%7Bfoos%7Bname%7D%7D")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.body(), "plugin not enabled for tenant");
+    // 未配置时的默认租户不再存在
+    let response = request()
+        .path("/api/foo/graphql/false?query=%7Bfoos%7Bname%7D%7D")
+        .reply(&api)
+        .await;
+    assert_eq!(response.body(), "no such tenant");
+
+    let response = request().path("/api/foo/graphiql").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(String::from_utf8_lossy(response.body()).contains("/api/foo/graphql/acme"));
+    let response = request().path("/api/foo/graphiql/globex").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tenants_are_bound_to_credentials() {
+    let auth = json!({
+        "required": true,
+        "api_keys": [
+            {"key": "master-key", "subject": "master-client", "tenants": ["master"]},
+            {"key": "any-key", "subject": "any-client"}
+        ],
+        "jwt_keys": [
+            {"kid": "test", "algorithm": "HS256", "key": JWT_SECRET, "tenants": ["master", "dev"]}
+        ],
+        "jwt_issuer": "test-issuer"
+    });
+    let (_server, api) = auth_server(auth, "");
+    let status = |header: (&'static str, String), path: &'static str| {
+        let api = api.clone();
+        async move {
+            request()
+                .path(path)
+                .header(header.0, header.1)
+                .reply(&api)
+                .await
+                .status()
+        }
+    };
+    let master = "/api/foo/graphql/master?query=%7Bfoos%7Bname%7D%7D";
+    let dev = "/api/foo/graphql/dev?query=%7Bfoos%7Bname%7D%7D";
+    let key = |key: &str| ("x-api-key", key.to_string());
+    assert_eq!(status(key("master-key"), master).await, StatusCode::OK);
+    // 跨租户的请求被拒绝，请求头指定的租户以及graphiql页面同样校验
+    assert_eq!(status(key("master-key"), dev).await, StatusCode::FORBIDDEN);
+    let response = request()
+        .path("/api/foo/graphql?query=%7Bfoos%7Bname%7D%7D")
+        .header("x-api-key", "master-key")
+        .header("x-tenant", "dev")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        status(key("master-key"), "/api/foo/graphiql/dev").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(status(key("any-key"), dev).await, StatusCode::OK);
+
+    // token声明的租户与密钥配置的租户取交集
+    let bearer = |claims: Value| {
+        (
+            "authorization",
+            format!("Bearer {}", jwt(JWT_SECRET, claims)),
+        )
+    };
+    assert_eq!(
+        status(bearer(jwt_claims("jwt-client")), dev).await,
+        StatusCode::OK
+    );
+    let mut claims = jwt_claims("jwt-client");
+    claims["tenants"] = json!(["master"]);
+    assert_eq!(status(bearer(claims.clone()), master).await, StatusCode::OK);
+    assert_eq!(status(bearer(claims), dev).await, StatusCode::FORBIDDEN);
+}
+
+const LIGHT_QUERY: &str = "?query=%7Bfoo(id%3A1)%7Bbars%7Blight%7D%7D%7D";
+
+// 请求Boo.light，返回是否成功。金丝雀版本的foo删除了该字段
+async fn has_light<F>(api: &F, tenant: &str, version: Option<&str>) -> bool
+where
+    F: Filter + 'static,
+    F::Extract: Reply + Send,
+{
+    let mut req = request().path(&format!("/api/foo/graphql/{}{}", tenant, LIGHT_QUERY));
+    if let Some(version) = version {
+        req = req.header("x-plugin-version", version);
+    }
+    let response = req.reply(api).await;
+    response.status() == StatusCode::OK && json_body(response.body()).get("errors").is_none()
+}
+
+#[tokio::test]
+async fn canary_routing() {
+    let (server, api) = server(
+        r#"
+        [tenants.master]
+
+        [tenants.dev]
+        versions = { foo = "v2" }
+
+        [tenants.prod]
+        dataset = "master"
+        versions = { foo = "stable" }
+        "#,
+    );
+    let response = request().path("/control/canary/foo").reply(&api).await;
+    assert_eq!(response.body(), "handler not found");
+    assert!(has_light(&api, "master", None).await);
+
+    server._libs.install_canary(breaking_fixture(), "v2");
+    let response = request()
+        .path("/control/canary/foo?version=v2&weight=30")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let status = json_body(response.body());
+    assert_eq!(status["version"], "0.1.0");
+    assert_eq!(status["canaries"][0]["version"], "v2");
+    assert_eq!(status["canaries"][0]["weight"], 30);
+
+    // 请求头指定的版本优先，其次是租户固定的版本
+    assert!(!has_light(&api, "master", Some("v2")).await);
+    assert!(has_light(&api, "master", Some("stable")).await);
+    assert!(has_light(&api, "dev", Some("0.1.0")).await);
+    assert!(!has_light(&api, "dev", None).await);
+    assert!(has_light(&api, "prod", None).await);
+    let response = request()
+        .path(&format!("/api/foo/graphql/master{}", LIGHT_QUERY))
+        .header("x-plugin-version", "v3")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.body(), "no such version");
+
+    // 每100个请求中按权重分配
+    let mut canary = 0;
+    for _ in 0..100 {
+        if !has_light(&api, "master", None).await {
+            canary += 1;
+        }
+    }
+    assert_eq!(canary, 30);
+
+    let cases = [
+        ("/control/canary/foo?version=v2", StatusCode::CONFLICT),
+        // 金丝雀版本必须指定版本名，且该版本的编译产物必须存在
+        ("/control/canary/foo", StatusCode::BAD_REQUEST),
+        (
+            "/control/canary/foo?version=v3",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+        ("/control/canary/foo?version=stable", StatusCode::CONFLICT),
+        ("/control/canary/foo?version=a%20b", StatusCode::BAD_REQUEST),
+        (
+            "/control/canary/foo?version=v2&weight=101",
+            StatusCode::BAD_REQUEST,
+        ),
+        ("/control/canary/foo?version=v2&weight=80", StatusCode::OK),
+        (
+            "/control/canary/foo?version=v3&weight=30",
+            StatusCode::BAD_REQUEST,
+        ),
+    ];
+    for (path, status) in cases.iter() {
+        let response = request().path(path).reply(&api).await;
+        assert_eq!(response.status(), *status, "{}", path);
+    }
+
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    for expected in [
+        "plugin_requests_total{plugin=\"foo\",tenant=\"dev\",version=\"v2\"} 1",
+        "plugin_requests_total{plugin=\"foo\",tenant=\"prod\",version=\"0.1.0\"} 1",
+        "plugin_lifecycle_total{event=\"canary\",plugin=\"foo\",version=\"v2\"} 1",
+    ]
+    .iter()
+    {
+        assert!(metrics.contains(expected), "{}", metrics);
+    }
+}
+
+#[tokio::test]
+async fn promote_and_rollback_canary() {
+    let (server, api) = server("[eviction]\npolicy = \"all\"\n");
+    assert!(has_light(&api, "master", None).await);
+    server._libs.install_canary(breaking_fixture(), "v2");
+    let canary = "/control/canary/foo?version=v2";
+    let response = request().path(canary).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // 权重为0时只有指定版本的请求使用金丝雀版本
+    assert!(has_light(&api, "master", None).await);
+    assert!(!has_light(&api, "master", Some("v2")).await);
+
+    let response = request().path("/control/rollback/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response.body())["canaries"], json!([]));
+    // 回滚后稳定版本被卸载再重新加载，依然是原来的稳定版本
+    assert_eq!(server.storage.write().await.evict(), ["foo"]);
+    assert!(has_light(&api, "master", None).await);
+    let response = request()
+        .path("/control/rollback/foo?version=v2")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = request().path("/control/promote/foo").reply(&api).await;
+    assert_eq!(response.body(), "no such version");
+
+    let response = request().path(canary).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request().path("/control/promote/foo").reply(&api).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let status = json_body(response.body());
+    assert_eq!(status["version"], "v2");
+    assert_eq!(status["canaries"], json!([]));
+    assert!(!has_light(&api, "master", None).await);
+    assert!(!has_light(&api, "master", Some("v2")).await);
+    // 提升时安装为稳定版本的编译产物，卸载后重新加载的是提升后的版本，健康状态重新统计
+    assert_eq!(server.storage.write().await.evict(), ["foo"]);
+    assert!(!has_light(&api, "master", None).await);
+    let response = request().path("/plugins/foo/health").reply(&api).await;
+    let health = json_body(response.body());
+    assert_eq!(health["version"], "0.1.0");
+    assert_eq!(health["panic_count"], 0);
+
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    for event in ["canary", "promote", "rollback"].iter() {
+        let expected = format!(
+            "plugin_lifecycle_total{{event=\"{}\",plugin=\"foo\",version=\"v2\"}}",
+            event
+        );
+        assert!(metrics.contains(&expected), "{}", metrics);
+    }
+    // 提升前指定v2的请求，以及提升后稳定版本的请求都按v2记录
+    assert!(metrics
+        .contains("plugin_requests_total{plugin=\"foo\",tenant=\"master\",version=\"v2\"} 3"));
+}
+
+// 等待影子流量的比较结果达到指定的请求数
+async fn shadow_report<F>(api: &F, requests: u64) -> Value
+where
+    F: Filter + 'static,
+    F::Extract: Reply + Send,
+{
+    for _ in 0..100 {
+        let response = request().path("/admin/shadow").reply(api).await;
+        let report = json_body(response.body());
+        if report[0]["requests"].as_u64().unwrap_or(0) >= requests {
+            return report;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("shadow requests not recorded");
+}
+
+#[tokio::test]
+async fn shadow_traffic_is_compared() {
+    let (server, api) = server("");
+    assert!(has_light(&api, "master", None).await);
+    server._libs.install_canary(breaking_fixture(), "v2");
+    let response = request()
+        .path("/control/canary/foo?version=v2&shadow=101")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.body(), "invalid shadow sample rate");
+    let response = request()
+        .path("/control/canary/foo?version=v2&shadow=100")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let status = json_body(response.body());
+    assert_eq!(status["canaries"][0]["weight"], 0);
+    assert_eq!(status["canaries"][0]["shadow"], 100);
+
+    // 客户端收到的始终是稳定版本的响应
+    assert!(has_light(&api, "master", None).await);
+    let report = shadow_report(&api, 1).await;
+    assert_eq!(report[0]["plugin"], "foo");
+    assert_eq!(report[0]["version"], "v2");
+    assert_eq!(report[0]["mismatches"], 1);
+    let mismatch = &report[0]["recent_mismatches"][0];
+    assert_eq!(mismatch["tenant"], "master");
+    assert_eq!(mismatch["primary_status"], 200);
+    assert_eq!(mismatch["shadow_status"], 400);
+    assert!(mismatch["query"].as_str().unwrap().contains("light"));
+    assert!(mismatch["paths"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("/errors")));
+    // 只记录不一致的位置，不保留响应体
+    assert!(mismatch.get("primary").is_none());
+    assert!(mismatch.get("shadow").is_none());
+
+    assert_eq!(
+        foo_names(&api, "/api/foo/graphql/master", None).await,
+        (StatusCode::OK, vec!["foo1".to_string(), "foo2".to_string()])
+    );
+    let report = shadow_report(&api, 2).await;
+    assert_eq!(report[0]["requests"], 2);
+    assert_eq!(report[0]["mismatches"], 1);
+    assert!(report[0]["shadow_avg_ms"].as_f64().unwrap() > 0.0);
+
+    let response = request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(response.body()).to_string();
+    for outcome in ["match", "mismatch"].iter() {
+        let expected = format!(
+            "shadow_requests_total{{outcome=\"{}\",plugin=\"foo\",version=\"v2\"}} 1",
+            outcome
+        );
+        assert!(metrics.contains(&expected), "{}", metrics);
+    }
+    // 影子流量不计入请求指标
+    assert!(
+        !metrics.contains("plugin_requests_total{plugin=\"foo\",tenant=\"master\",version=\"v2\"}")
+    );
+
+    // 回滚后删除该版本的比较结果
+    let response = request()
+        .path("/control/rollback/foo?version=v2")
+        .reply(&api)
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request().path("/admin/shadow").reply(&api).await;
+    assert_eq!(json_body(response.body()), json!([]));
+}
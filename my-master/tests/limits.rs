@@ -0,0 +1,136 @@
+//! 查询限制的分析与检查
+use std::collections::HashMap;
+
+use my_interface::QueryLimits;
+use my_master::{
+    config::Config,
+    limits::{analyze_query, check_query, QueryCheckError, QueryLimitSettings, QueryStats},
+};
+
+fn stats(query: &str) -> QueryStats {
+    analyze_query(query, &HashMap::new()).expect("query is valid")
+}
+
+#[test]
+fn depth_counts_nested_fields() {
+    assert_eq!(stats("{ foo(id: 1) { id } }").depth, 2);
+    assert_eq!(stats("{ foo(id: 1) { bars { light } } }").depth, 3);
+    // 多个operation时取最大值
+    let query = "query A { foo(id: 1) { id } } query B { foos { bars { id } } }";
+    assert_eq!(stats(query).depth, 3);
+}
+
+#[test]
+fn complexity_uses_field_costs() {
+    let mut field_costs = HashMap::new();
+    field_costs.insert("bars".to_string(), 5);
+    let query = "{ foo(id: 1) { id bars { id } } }";
+    assert_eq!(stats(query).complexity, 4);
+    assert_eq!(analyze_query(query, &field_costs).unwrap().complexity, 8);
+}
+
+#[test]
+fn aliases_are_counted() {
+    let query = "{ a: foo(id: 1) { id } b: foo(id: 2) { n: name } }";
+    assert_eq!(stats(query).aliases, 3);
+}
+
+#[test]
+fn fragments_are_expanded() {
+    let query = "{ foo(id: 1) { ...F } } fragment F on Foo { bars { id } }";
+    assert_eq!(stats(query).depth, 3);
+    // 循环引用的片段只展开一次
+    let query =
+        "{ foo(id: 1) { ...A } } fragment A on Foo { id ...B } fragment B on Foo { name ...A }";
+    assert_eq!(
+        stats(query),
+        QueryStats {
+            depth: 2,
+            complexity: 3,
+            aliases: 0
+        }
+    );
+}
+
+#[test]
+fn repeated_fragments_are_analyzed_once() {
+    // 每层片段引用下一层两次，展开后的字段数随层数指数增长，统计只展开每个片段一次
+    let mut query = "{ foo(id: 1) { ...F0 } }".to_string();
+    for i in 0..64 {
+        query.push_str(&format!(
+            " fragment F{} on Foo {{ ...F{} ...F{} }}",
+            i,
+            i + 1,
+            i + 1
+        ));
+    }
+    query.push_str(" fragment F64 on Foo { id }");
+    let stats = stats(&query);
+    assert_eq!(stats.depth, 2);
+    assert_eq!(stats.complexity, usize::MAX);
+}
+
+#[test]
+fn invalid_query_is_rejected() {
+    assert!(analyze_query("{ foo(id: 1) { id }", &HashMap::new()).is_err());
+    assert!(matches!(
+        check_query("", &QueryLimits::default()),
+        Err(QueryCheckError::Invalid(_))
+    ));
+}
+
+#[test]
+fn limits_are_checked() {
+    let limits = QueryLimits {
+        max_depth: Some(2),
+        max_aliases: Some(1),
+        ..QueryLimits::default()
+    };
+    assert_eq!(check_query("{ foo(id: 1) { id } }", &limits), Ok(()));
+    assert!(matches!(
+        check_query("{ foo(id: 1) { bars { id } } }", &limits),
+        Err(QueryCheckError::Exceeded(message)) if message.contains("depth")
+    ));
+    assert!(matches!(
+        check_query("{ a: foo(id: 1) { id } b: foo(id: 2) { id } }", &limits),
+        Err(QueryCheckError::Exceeded(message)) if message.contains("aliases")
+    ));
+}
+
+#[test]
+fn configured_limits_override_declared() {
+    let config: Config = toml::from_str(
+        r#"
+        [plugins."*".limits]
+        max_depth = 5
+        [plugins.foo.limits]
+        max_depth = 3
+        field_costs = { bars = 10 }
+        "#,
+    )
+    .unwrap();
+    let settings = QueryLimitSettings::from_config(&config);
+    let mut field_costs = HashMap::new();
+    field_costs.insert("bars".to_string(), 5);
+    field_costs.insert("foos".to_string(), 2);
+    let declared = QueryLimits {
+        max_depth: Some(10),
+        max_batch_size: Some(4),
+        field_costs,
+        ..QueryLimits::default()
+    };
+
+    let foo = settings.limits_of("foo", declared.clone());
+    assert_eq!(foo.max_depth, Some(3));
+    assert_eq!(foo.max_batch_size, Some(4));
+    assert_eq!(foo.field_costs["bars"], 10);
+    assert_eq!(foo.field_costs["foos"], 2);
+    assert_eq!(settings.limits_of("bar", declared).max_depth, Some(5));
+
+    let settings = QueryLimitSettings::from_config(&Config::default());
+    let declared = QueryLimits {
+        max_depth: Some(10),
+        ..QueryLimits::default()
+    };
+    assert_eq!(settings.limits_of("foo", declared.clone()), declared);
+}
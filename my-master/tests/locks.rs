@@ -0,0 +1,33 @@
+//! 按插件区分的编译与加载锁
+use std::sync::Arc;
+
+use my_master::PluginLocks;
+
+#[tokio::test]
+async fn same_plugin_shares_lock_while_held() {
+    let locks = PluginLocks::default();
+    let foo = locks.lock_of("foo");
+    assert!(Arc::ptr_eq(&foo, &locks.lock_of("foo")));
+    assert!(!Arc::ptr_eq(&foo, &locks.lock_of("bar")));
+
+    let _guard = foo.lock().await;
+    assert!(locks.lock_of("foo").try_lock().is_err());
+    assert!(locks.lock_of("bar").try_lock().is_ok());
+}
+
+#[test]
+fn released_locks_are_removed() {
+    let locks = PluginLocks::default();
+    // 任意的插件名都会创建锁，释放后不再保留
+    for i in 0..100 {
+        let lock = locks.lock_of(&format!("unknown{}", i));
+        assert_eq!(locks.len(), 1);
+        drop(lock);
+    }
+    assert!(locks.is_empty());
+
+    let foo = locks.lock_of("foo");
+    let _bar = locks.lock_of("bar");
+    drop(foo);
+    assert_eq!(locks.len(), 1);
+}
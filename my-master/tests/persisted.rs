@@ -0,0 +1,99 @@
+//! 持久化查询的缓存与白名单
+use std::collections::HashSet;
+
+use my_master::persisted::{
+    sha256_hex, Extensions, PersistedQueryError, PersistedQueryExtension, PersistedQueryStore,
+};
+
+fn extensions(query: &str) -> Option<Extensions> {
+    Some(Extensions {
+        persisted_query: Some(PersistedQueryExtension {
+            version: 1,
+            sha256_hash: sha256_hex(query.as_bytes()),
+        }),
+    })
+}
+
+// 只发送hash时查找已缓存的查询
+fn lookup(store: &PersistedQueryStore, query: &str) -> Result<Option<String>, PersistedQueryError> {
+    store.resolve("foo", "schema", None, extensions(query))
+}
+
+#[test]
+fn cached_queries_are_bounded() {
+    let store = PersistedQueryStore::new(HashSet::new());
+    let query = |i: usize| format!("{{ foo(id: {}) {{ id }} }}", i);
+    for i in 0..1001 {
+        let resolved = store.resolve("foo", "schema", Some(query(i)), extensions(&query(i)));
+        assert_eq!(resolved.unwrap(), Some(query(i)));
+    }
+    // 超出上限时淘汰最久未使用的查询
+    assert!(matches!(
+        lookup(&store, &query(0)),
+        Err(PersistedQueryError::NotFound)
+    ));
+    assert_eq!(lookup(&store, &query(1000)).unwrap(), Some(query(1000)));
+}
+
+#[test]
+fn registered_queries_are_not_evicted() {
+    let store = PersistedQueryStore::new(HashSet::new());
+    let registered = "{ foo(id: 1) { name } }".to_string();
+    store.register("foo", "schema", registered.clone());
+    for i in 0..1001 {
+        let query = format!("{{ foo(id: {}) {{ id }} }}", i);
+        store
+            .resolve("foo", "schema", Some(query.clone()), extensions(&query))
+            .unwrap();
+    }
+    assert_eq!(lookup(&store, &registered).unwrap(), Some(registered));
+}
+
+#[test]
+fn allow_list_ignores_cached_queries() {
+    let mut allow_list = HashSet::new();
+    allow_list.insert("foo".to_string());
+    let store = PersistedQueryStore::new(allow_list);
+    let query = "{ foo(id: 1) { id } }".to_string();
+    let resolved = store.resolve("foo", "schema", Some(query.clone()), extensions(&query));
+    assert!(matches!(resolved, Err(PersistedQueryError::NotAllowed)));
+    store.register("foo", "schema", query.clone());
+    assert_eq!(lookup(&store, &query).unwrap(), Some(query));
+}
+
+#[test]
+fn schemas_keep_separate_queries() {
+    let mut allow_list = HashSet::new();
+    allow_list.insert("foo".to_string());
+    let store = PersistedQueryStore::new(allow_list);
+    let query = "{ foo(id: 1) { id } }".to_string();
+    store.register("foo", "stable", query.clone());
+    // 金丝雀版本的请求不会清空稳定版本注册的查询
+    let canary = store.resolve("foo", "canary", Some(query.clone()), None);
+    assert!(matches!(canary, Err(PersistedQueryError::NotAllowed)));
+    let stable = store.resolve("foo", "stable", Some(query.clone()), None);
+    assert_eq!(stable.unwrap(), Some(query.clone()));
+
+    store.register("foo", "canary", query.clone());
+    let canary = store.resolve("foo", "canary", None, extensions(&query));
+    assert_eq!(canary.unwrap(), Some(query));
+}
+
+#[test]
+fn unused_schemas_are_dropped() {
+    let store = PersistedQueryStore::new(HashSet::new());
+    let query = "{ foo(id: 1) { id } }".to_string();
+    for schema in ["s0", "s1", "s2", "s3"].iter() {
+        store.register("foo", schema, query.clone());
+    }
+    // s0最近使用过，超出上限时删除最久未使用的s1
+    store
+        .resolve("foo", "s0", None, extensions(&query))
+        .unwrap();
+    store.register("foo", "s4", query.clone());
+    let resolve = |schema: &str| store.resolve("foo", schema, None, extensions(&query));
+    for schema in ["s0", "s2", "s3", "s4"].iter() {
+        assert_eq!(resolve(schema).unwrap(), Some(query.clone()));
+    }
+    assert!(matches!(resolve("s1"), Err(PersistedQueryError::NotFound)));
+}
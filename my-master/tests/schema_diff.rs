@@ -0,0 +1,247 @@
+use my_master::schema_diff::{ChangeLevel, SchemaDiff};
+
+const BASE: &str = r#"
+schema { query: Query }
+
+type Query {
+  foo(id: Int!): Foo
+  foos(limit: Int = 10): [Foo!]!
+  search(filter: Filter): [Node!]!
+}
+
+interface Node { id: Int! }
+
+type Foo implements Node {
+  id: Int!
+  name: String
+  kind: Kind!
+}
+
+type Bar implements Node { id: Int! }
+
+union Result = Foo | Bar
+
+enum Kind { SMALL LARGE }
+
+input Filter {
+  name: String
+  kind: Kind
+}
+"#;
+
+// 在BASE上替换一段SDL后比较
+fn diff(from: &str, to: &str) -> Vec<(ChangeLevel, String, String)> {
+    assert!(BASE.contains(from), "{} not in schema", from);
+    SchemaDiff::between(BASE, &BASE.replacen(from, to, 1))
+        .unwrap()
+        .changes
+        .into_iter()
+        .map(|change| (change.level, change.path, change.message))
+        .collect()
+}
+
+fn levels(from: &str, to: &str) -> Vec<(ChangeLevel, String)> {
+    diff(from, to)
+        .into_iter()
+        .map(|(level, path, _)| (level, path))
+        .collect()
+}
+
+#[test]
+fn identical_schema_has_no_changes() {
+    let diff = SchemaDiff::between(BASE, BASE).unwrap();
+    assert!(diff.changes.is_empty());
+    assert!(!diff.is_breaking());
+}
+
+#[test]
+fn removed_field_is_breaking() {
+    assert_eq!(
+        levels("  name: String\n  kind", "  kind"),
+        [(ChangeLevel::Breaking, "Foo.name".to_string())]
+    );
+}
+
+#[test]
+fn added_field_and_type_are_safe() {
+    let changes = levels(
+        "  kind: Kind!\n}",
+        "  kind: Kind!\n  size: Int\n}\n\ntype Baz { id: Int }",
+    );
+    assert_eq!(
+        changes,
+        [
+            (ChangeLevel::Safe, "Baz".to_string()),
+            (ChangeLevel::Safe, "Foo.size".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn output_type_may_only_become_stricter() {
+    assert_eq!(
+        diff("  name: String\n", "  name: String!\n"),
+        [(
+            ChangeLevel::Safe,
+            "Foo.name".to_string(),
+            "field type changed from String to String!".to_string()
+        )]
+    );
+    assert_eq!(
+        levels("  kind: Kind!\n", "  kind: Kind\n"),
+        [(ChangeLevel::Breaking, "Foo.kind".to_string())]
+    );
+    assert_eq!(
+        levels("  name: String\n", "  name: Int\n"),
+        [(ChangeLevel::Breaking, "Foo.name".to_string())]
+    );
+    assert_eq!(
+        levels(
+            "foos(limit: Int = 10): [Foo!]!",
+            "foos(limit: Int = 10): [Foo]!"
+        ),
+        [(ChangeLevel::Breaking, "Query.foos".to_string())]
+    );
+}
+
+#[test]
+fn argument_changes() {
+    // 参数只能放宽
+    assert_eq!(
+        levels("foo(id: Int!)", "foo(id: Int)"),
+        [(ChangeLevel::Safe, "Query.foo(id)".to_string())]
+    );
+    assert_eq!(
+        levels("foos(limit: Int = 10)", "foos(limit: Int! = 10)"),
+        [(ChangeLevel::Breaking, "Query.foos(limit)".to_string())]
+    );
+    assert_eq!(
+        levels("foo(id: Int!)", "foo"),
+        [(ChangeLevel::Breaking, "Query.foo(id)".to_string())]
+    );
+    assert_eq!(
+        levels("foo(id: Int!)", "foo(id: Int!, version: Int!)"),
+        [(ChangeLevel::Breaking, "Query.foo(version)".to_string())]
+    );
+    assert_eq!(
+        levels("foo(id: Int!)", "foo(id: Int!, version: Int! = 1)"),
+        [(ChangeLevel::Dangerous, "Query.foo(version)".to_string())]
+    );
+    assert_eq!(
+        diff("foos(limit: Int = 10)", "foos(limit: Int = 20)"),
+        [(
+            ChangeLevel::Dangerous,
+            "Query.foos(limit)".to_string(),
+            "default value changed from 10 to 20".to_string()
+        )]
+    );
+}
+
+#[test]
+fn input_object_changes() {
+    assert_eq!(
+        levels("  kind: Kind\n}", "}"),
+        [(ChangeLevel::Breaking, "Filter(kind)".to_string())]
+    );
+    assert_eq!(
+        levels("  kind: Kind\n}", "  kind: Kind\n  size: Int\n}"),
+        [(ChangeLevel::Dangerous, "Filter(size)".to_string())]
+    );
+    assert_eq!(
+        levels("  kind: Kind\n}", "  kind: Kind\n  size: Int!\n}"),
+        [(ChangeLevel::Breaking, "Filter(size)".to_string())]
+    );
+}
+
+#[test]
+fn enum_and_union_changes() {
+    assert_eq!(
+        levels("enum Kind { SMALL LARGE }", "enum Kind { SMALL }"),
+        [(ChangeLevel::Breaking, "Kind.LARGE".to_string())]
+    );
+    assert_eq!(
+        levels(
+            "enum Kind { SMALL LARGE }",
+            "enum Kind { SMALL LARGE HUGE }"
+        ),
+        [(ChangeLevel::Dangerous, "Kind.HUGE".to_string())]
+    );
+    assert_eq!(
+        levels(
+            "enum Kind { SMALL LARGE }",
+            "enum Kind { SMALL LARGE @deprecated }"
+        ),
+        [(ChangeLevel::Safe, "Kind.LARGE".to_string())]
+    );
+    assert_eq!(
+        diff("union Result = Foo | Bar", "union Result = Foo"),
+        [(
+            ChangeLevel::Breaking,
+            "Result".to_string(),
+            "member Bar removed".to_string()
+        )]
+    );
+    assert_eq!(
+        levels("type Bar implements Node", "type Bar"),
+        [(ChangeLevel::Breaking, "Bar".to_string())]
+    );
+}
+
+#[test]
+fn type_changes() {
+    assert_eq!(
+        levels("type Bar implements Node { id: Int! }", ""),
+        [(ChangeLevel::Breaking, "Bar".to_string())]
+    );
+    assert_eq!(
+        diff("enum Kind { SMALL LARGE }", "scalar Kind"),
+        [(
+            ChangeLevel::Breaking,
+            "Kind".to_string(),
+            "type changed from enum to scalar".to_string()
+        )]
+    );
+    assert_eq!(
+        levels(
+            "  name: String\n  kind",
+            "  name: String @deprecated\n  kind"
+        ),
+        [(ChangeLevel::Safe, "Foo.name".to_string())]
+    );
+}
+
+#[test]
+fn root_type_changes() {
+    let changes = levels(
+        "schema { query: Query }",
+        "schema { query: Query mutation: Mutation }\n\ntype Mutation { ping: Int }",
+    );
+    assert_eq!(
+        changes,
+        [
+            (ChangeLevel::Safe, "Mutation".to_string()),
+            (ChangeLevel::Safe, "schema.mutation".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn diff_counts_levels() {
+    let diff = SchemaDiff::between(
+        BASE,
+        &BASE
+            .replacen("  name: String\n", "  name: String!\n", 1)
+            .replacen("SMALL LARGE", "SMALL LARGE HUGE", 1)
+            .replacen("foo(id: Int!)", "foo(id: String!)", 1),
+    )
+    .unwrap();
+    assert!(diff.is_breaking());
+    assert_eq!(diff.count(ChangeLevel::Breaking), 1);
+    assert_eq!(diff.count(ChangeLevel::Dangerous), 1);
+    assert_eq!(diff.count(ChangeLevel::Safe), 1);
+}
+
+#[test]
+fn invalid_sdl_is_an_error() {
+    assert!(SchemaDiff::between(BASE, "type Foo {").is_err());
+}
@@ -0,0 +1,95 @@
+use std::{collections::HashMap, time::Duration};
+
+use bytes::Bytes;
+use my_master::shadow::{json_diff, ShadowRequest, ShadowResponse, ShadowStore};
+use serde_json::json;
+use warp::http::StatusCode;
+
+#[test]
+fn identical_values_have_no_diff() {
+    let value = json!({ "data": { "foos": [{ "name": "foo1" }, { "name": "foo2" }] } });
+    assert!(json_diff(&value, &value).is_empty());
+}
+
+#[test]
+fn diff_reports_json_pointers() {
+    let primary = json!({
+        "data": { "foos": [{ "name": "foo1" }, { "name": "foo2" }], "a/b": 1 }
+    });
+    let shadow = json!({
+        "data": { "foos": [{ "name": "foo1" }, { "name": "fooX" }, { "name": "foo3" }], "a/b": 2 },
+        "errors": []
+    });
+    assert_eq!(
+        json_diff(&primary, &shadow),
+        ["/data/a~1b", "/data/foos/1/name", "/data/foos/2", "/errors"]
+    );
+    assert_eq!(json_diff(&json!(1), &json!("1")), [""]);
+}
+
+#[test]
+fn diff_is_bounded() {
+    let primary = json!((0..100).collect::<Vec<_>>());
+    let shadow = json!((1..101).collect::<Vec<_>>());
+    assert_eq!(json_diff(&primary, &shadow).len(), 20);
+}
+
+#[test]
+fn only_queries_are_mirrored() {
+    let get = |query: &str| {
+        let mut qry = HashMap::new();
+        qry.insert("query".to_string(), query.to_string());
+        ShadowRequest::Get(qry)
+    };
+    assert!(get("{ foos { name } }").is_read_only());
+    assert!(get("query Foos {\n  foos { name }\n}").is_read_only());
+    assert!(!get("mutation { addFoo(name: \"x\") { id } }").is_read_only());
+    assert!(!get("query A { foos { id } } mutation B { addFoo { id } }").is_read_only());
+    assert!(!get("{ foos ").is_read_only());
+    assert!(!ShadowRequest::Get(HashMap::new()).is_read_only());
+    assert!(ShadowRequest::PostGraphql(Bytes::from("{ foo(id: 1) { name } }")).is_read_only());
+}
+
+fn response(body: &str) -> ShadowResponse {
+    ShadowResponse {
+        status: StatusCode::OK,
+        body: body.as_bytes().to_vec(),
+        elapsed: Duration::from_millis(1),
+    }
+}
+
+#[test]
+fn mismatches_keep_only_paths() {
+    let store = ShadowStore::default();
+    let (primary, shadow) = (
+        response(r#"{"data":{"a":1}}"#),
+        response(r#"{"data":{"a":2}}"#),
+    );
+    store.record(
+        "foo",
+        "v2",
+        "master",
+        "{ a }".to_string(),
+        &primary,
+        &shadow,
+    );
+    let report = serde_json::to_value(store.snapshot()).unwrap();
+    let mismatch = &report[0]["recent_mismatches"][0];
+    assert_eq!(mismatch["paths"], json!(["/data/a"]));
+    assert!(mismatch.get("primary").is_none());
+    assert!(mismatch.get("shadow").is_none());
+
+    store.clear("foo", "v2");
+    assert!(store.snapshot().is_empty());
+}
+
+#[test]
+fn samples_are_dropped_when_too_many_in_flight() {
+    let store = ShadowStore::default();
+    let permits: Vec<_> = std::iter::from_fn(|| store.try_acquire("foo", "v2")).collect();
+    assert_eq!(permits.len(), 64);
+    assert!(store.try_acquire("foo", "v2").is_none());
+    assert_eq!(store.snapshot()[0].dropped, 2);
+    drop(permits);
+    assert!(store.try_acquire("foo", "v2").is_some());
+}
@@ -1,15 +1,19 @@
 use proc_macro2::TokenStream;
 use quote::quote;
 
+use crate::deps::Dependency;
+
 pub fn genernate_tokens() -> TokenStream {
     let imports = genernate_imports();
     let objects = genernate_objects();
     let graphql_intf = genernate_graphql_intf();
+    let metadata = genernate_metadata();
     let handler = genernate_handler();
     quote! {
         #imports
         #objects
         #graphql_intf
+        #metadata
         #handler
 
         #[no_mangle]
@@ -19,6 +23,38 @@ pub fn genernate_tokens() -> TokenStream {
     }
 }
 
+/// 编译时额外引入的依赖，示例插件只使用生成项目固定的依赖
+pub fn dependencies() -> Vec<Dependency> {
+    Vec::new()
+}
+
+/// 生成编译为wasm32-wasi的插件代码，schema与原生插件一致，数据通过宿主函数访问
+pub fn genernate_wasm_tokens() -> TokenStream {
+    let objects = genernate_objects();
+    let graphql_intf = genernate_graphql_intf();
+    let metadata = genernate_metadata();
+    quote! {
+        use juniper::{graphql_object, EmptyMutation, EmptySubscription, FieldResult, RootNode};
+        use my_interface::{guest, Bar, DataContext, Light, PluginMetadata, QueryLimits};
+        use std::collections::HashMap;
+
+        #objects
+        #graphql_intf
+        #metadata
+
+        #[no_mangle]
+        pub extern "C" fn plugin_alloc(len: u32) -> u32 {
+            guest::alloc(len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_handle(ptr: u32, len: u32) -> u64 {
+            let schema = RootNode::new(BarQuery, EmptyMutation::<DataContext>::new(), EmptySubscription::<DataContext>::new());
+            guest::handle(&schema, plugin_metadata(), ptr, len)
+        }
+    }
+}
+
 fn genernate_imports() -> TokenStream {
     quote! {
         use anyhow::anyhow;
@@ -27,7 +63,10 @@ fn genernate_imports() -> TokenStream {
             futures::FutureExt, graphql_object, http::GraphQLRequest, DefaultScalarValue, EmptyMutation,
             EmptySubscription, FieldResult, RootNode,
         };
-        use my_interface::{build_response, Bar, DataContext, Foo, GraphqlRequestHandler, Light};
+        use my_interface::{
+            build_response, catch_plugin_panic, Bar, DataContext, Foo, GraphqlRequestHandler, Light, PluginMetadata,
+            QueryLimits,
+        };
         use std::{collections::HashMap, marker::Send, sync::Arc};
     }
 }
@@ -79,6 +118,22 @@ fn genernate_graphql_intf() -> TokenStream {
     }
 }
 
+fn genernate_metadata() -> TokenStream {
+    quote! {
+        fn plugin_metadata() -> PluginMetadata {
+            let field_costs = HashMap::new();
+            PluginMetadata::new(
+                String::from("bar"),
+                String::from("0.1.0"),
+                QueryLimits {
+                    field_costs,
+                    ..QueryLimits::default()
+                },
+            )
+        }
+    }
+}
+
 fn genernate_handler() -> TokenStream {
     quote! {
         #[derive(Clone)]
@@ -110,13 +165,21 @@ fn genernate_handler() -> TokenStream {
                 String::from("bar")
             }
 
+            fn metadata(&self) -> PluginMetadata {
+                plugin_metadata()
+            }
+
+            fn schema_sdl(&self) -> String {
+                self.schema.as_schema_language()
+            }
+
             async fn get_request_handle(
                 &self,
                 context: DataContext,
                 mut qry: HashMap<String, String>,
             ) -> Result<warp::http::Response<Vec<u8>>, warp::Rejection> {
                 let schema = self.schema.clone();
-                async move {
+                catch_plugin_panic(async move {
                     let req = GraphQLRequest::new(
                         qry.remove("query")
                             .ok_or_else(|| anyhow!("Missing GraphQL query string in query parameters"))?,
@@ -130,7 +193,7 @@ fn genernate_handler() -> TokenStream {
 
                     Ok((serde_json::to_vec(&resp)?, resp.is_ok()))
                 }
-                .then(|res| async move { Ok::<_, warp::Rejection>(build_response(res)) })
+                .then(|res| async move { Ok::<_, warp::Rejection>(build_response(res)) }))
                 .await
             }
 
@@ -140,7 +203,7 @@ fn genernate_handler() -> TokenStream {
                 req: juniper::http::GraphQLBatchRequest<DefaultScalarValue>,
             ) -> Result<warp::http::Response<Vec<u8>>, warp::Rejection> {
                 let schema = self.schema.clone();
-                async move {
+                catch_plugin_panic(async move {
                     let resp = req.execute(&schema, &context).await;
 
                     Ok::<_, warp::Rejection>(build_response(
@@ -148,7 +211,7 @@ fn genernate_handler() -> TokenStream {
                             .map(|json| (json, resp.is_ok()))
                             .map_err(Into::into),
                     ))
-                }
+                })
                 .await
             }
 
@@ -158,7 +221,7 @@ fn genernate_handler() -> TokenStream {
                 body: bytes::Bytes,
             ) -> Result<warp::http::Response<Vec<u8>>, warp::Rejection> {
                 let schema = self.schema.clone();
-                async move {
+                catch_plugin_panic(async move {
                     let query = std::str::from_utf8(body.as_ref())
                         .map_err(|e| anyhow!("Request body query is not a valid UTF-8 string: {}", e))?;
                     let req = GraphQLRequest::new(query.into(), None, None);
@@ -167,7 +230,7 @@ fn genernate_handler() -> TokenStream {
 
                     Ok((serde_json::to_vec(&resp)?, resp.is_ok()))
                 }
-                .then(|res| async { Ok::<_, warp::Rejection>(build_response(res)) })
+                .then(|res| async { Ok::<_, warp::Rejection>(build_response(res)) }))
                 .await
             }
         }
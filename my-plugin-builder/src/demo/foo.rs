@@ -1,15 +1,19 @@
 use proc_macro2::TokenStream;
 use quote::quote;
 
+use crate::deps::Dependency;
+
 pub fn genernate_tokens() -> TokenStream {
     let imports = genernate_imports();
     let objects = genernate_objects();
     let graphql_intf = genernate_graphql_intf();
+    let metadata = genernate_metadata();
     let handler = genernate_handler();
     quote! {
         #imports
         #objects
         #graphql_intf
+        #metadata
         #handler
 
         #[no_mangle]
@@ -19,6 +23,38 @@ pub fn genernate_tokens() -> TokenStream {
     }
 }
 
+/// 编译时额外引入的依赖，示例插件只使用生成项目固定的依赖
+pub fn dependencies() -> Vec<Dependency> {
+    Vec::new()
+}
+
+/// 生成编译为wasm32-wasi的插件代码，schema与原生插件一致，数据通过宿主函数访问
+pub fn genernate_wasm_tokens() -> TokenStream {
+    let objects = genernate_objects();
+    let graphql_intf = genernate_graphql_intf();
+    let metadata = genernate_metadata();
+    quote! {
+        use juniper::{graphql_object, EmptyMutation, EmptySubscription, FieldResult, RootNode};
+        use my_interface::{guest, require_scope, Bar, DataContext, Foo, Light, PluginMetadata, QueryLimits};
+        use std::collections::HashMap;
+
+        #objects
+        #graphql_intf
+        #metadata
+
+        #[no_mangle]
+        pub extern "C" fn plugin_alloc(len: u32) -> u32 {
+            guest::alloc(len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_handle(ptr: u32, len: u32) -> u64 {
+            let schema = RootNode::new(FooQuery, EmptyMutation::<DataContext>::new(), EmptySubscription::<DataContext>::new());
+            guest::handle(&schema, plugin_metadata(), ptr, len)
+        }
+    }
+}
+
 fn genernate_imports() -> TokenStream {
     quote! {
         use anyhow::anyhow;
@@ -27,7 +63,10 @@ fn genernate_imports() -> TokenStream {
             futures::FutureExt, graphql_object, http::GraphQLRequest, DefaultScalarValue, EmptyMutation,
             EmptySubscription, FieldResult, RootNode,
         };
-        use my_interface::{build_response, Bar, DataContext, Foo, GraphqlRequestHandler, Light};
+        use my_interface::{
+            build_response, catch_plugin_panic, require_scope, Bar, DataContext, Foo, GraphqlRequestHandler, Light,
+            PluginMetadata, QueryLimits,
+        };
         use std::{collections::HashMap, marker::Send, sync::Arc};
     }
 }
@@ -74,6 +113,11 @@ fn genernate_objects() -> TokenStream {
             fn name(&self) -> String {
                 self.po.name.clone()
             }
+            #[graphql(description = "requires scope `foo:internal`")]
+            fn bar_ids(&self, context: &DataContext) -> FieldResult<Vec<i32>> {
+                require_scope(context, "foo:internal")?;
+                Ok(self.po.bar_ids.clone())
+            }
             fn bars(&self, context: &DataContext) -> Vec<BarObject> {
                 context
                     .get_bars_by_ids(self.po.bar_ids.clone())
@@ -107,6 +151,23 @@ fn genernate_graphql_intf() -> TokenStream {
     }
 }
 
+fn genernate_metadata() -> TokenStream {
+    quote! {
+        fn plugin_metadata() -> PluginMetadata {
+            let mut field_costs = HashMap::new();
+            field_costs.insert("bars".to_string(), 5);
+            PluginMetadata::new(
+                String::from("foo"),
+                String::from("0.1.0"),
+                QueryLimits {
+                    field_costs,
+                    ..QueryLimits::default()
+                },
+            )
+        }
+    }
+}
+
 fn genernate_handler() -> TokenStream {
     quote! {
         #[derive(Clone)]
@@ -138,13 +199,21 @@ fn genernate_handler() -> TokenStream {
                 String::from("foo")
             }
 
+            fn metadata(&self) -> PluginMetadata {
+                plugin_metadata()
+            }
+
+            fn schema_sdl(&self) -> String {
+                self.schema.as_schema_language()
+            }
+
             async fn get_request_handle(
                 &self,
                 context: DataContext,
                 mut qry: HashMap<String, String>,
             ) -> Result<warp::http::Response<Vec<u8>>, warp::Rejection> {
                 let schema = self.schema.clone();
-                async move {
+                catch_plugin_panic(async move {
                     let req = GraphQLRequest::new(
                         qry.remove("query")
                             .ok_or_else(|| anyhow!("Missing GraphQL query string in query parameters"))?,
@@ -158,7 +227,7 @@ fn genernate_handler() -> TokenStream {
 
                     Ok((serde_json::to_vec(&resp)?, resp.is_ok()))
                 }
-                .then(|res| async move { Ok::<_, warp::Rejection>(build_response(res)) })
+                .then(|res| async move { Ok::<_, warp::Rejection>(build_response(res)) }))
                 .await
             }
 
@@ -168,7 +237,7 @@ fn genernate_handler() -> TokenStream {
                 req: juniper::http::GraphQLBatchRequest<DefaultScalarValue>,
             ) -> Result<warp::http::Response<Vec<u8>>, warp::Rejection> {
                 let schema = self.schema.clone();
-                async move {
+                catch_plugin_panic(async move {
                     let resp = req.execute(&schema, &context).await;
 
                     Ok::<_, warp::Rejection>(build_response(
@@ -176,7 +245,7 @@ fn genernate_handler() -> TokenStream {
                             .map(|json| (json, resp.is_ok()))
                             .map_err(Into::into),
                     ))
-                }
+                })
                 .await
             }
 
@@ -186,7 +255,7 @@ fn genernate_handler() -> TokenStream {
                 body: bytes::Bytes,
             ) -> Result<warp::http::Response<Vec<u8>>, warp::Rejection> {
                 let schema = self.schema.clone();
-                async move {
+                catch_plugin_panic(async move {
                     let query = std::str::from_utf8(body.as_ref())
                         .map_err(|e| anyhow!("Request body query is not a valid UTF-8 string: {}", e))?;
                     let req = GraphQLRequest::new(query.into(), None, None);
@@ -195,7 +264,7 @@ fn genernate_handler() -> TokenStream {
 
                     Ok((serde_json::to_vec(&resp)?, resp.is_ok()))
                 }
-                .then(|res| async { Ok::<_, warp::Rejection>(build_response(res)) })
+                .then(|res| async { Ok::<_, warp::Rejection>(build_response(res)) }))
                 .await
             }
         }
@@ -1,2 +1,27 @@
+use proc_macro2::TokenStream;
+
+use crate::{deps::Dependency, PluginTarget};
+
 pub mod bar;
 pub mod foo;
+
+/// 插件的定义：生成的代码以及编译时额外引入的依赖，依赖由插件声明，主服务的配置只决定允许哪些依赖
+pub struct PluginDefinition {
+    pub tokens: TokenStream,
+    pub dependencies: Vec<Dependency>,
+}
+
+/// 示例插件的定义，不支持的插件返回None
+pub fn definition(name: &str, target: PluginTarget) -> Option<PluginDefinition> {
+    let (tokens, dependencies) = match (name, target) {
+        ("foo", PluginTarget::Native) => (foo::genernate_tokens(), foo::dependencies()),
+        ("foo", PluginTarget::Wasm) => (foo::genernate_wasm_tokens(), foo::dependencies()),
+        ("bar", PluginTarget::Native) => (bar::genernate_tokens(), bar::dependencies()),
+        ("bar", PluginTarget::Wasm) => (bar::genernate_wasm_tokens(), bar::dependencies()),
+        _ => return None,
+    };
+    Some(PluginDefinition {
+        tokens,
+        dependencies,
+    })
+}
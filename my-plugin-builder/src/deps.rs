@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use crate::{errors::BuildError, PluginTarget};
+
+/// 生成的插件项目固定引入的依赖，插件声明的依赖不能与其重名
+const BUILTIN_NATIVE: &[&str] = &[
+    "my-interface",
+    "anyhow",
+    "async-trait",
+    "bytes",
+    "dyn-clone",
+    "juniper",
+    "juniper_warp",
+    "warp",
+    "serde",
+    "serde_json",
+];
+const BUILTIN_WASM: &[&str] = &["my-interface", "juniper", "serde", "serde_json"];
+
+/// 插件声明的额外依赖，只支持crates.io（或替换后的vendor目录）中的依赖
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    /// 版本要求，如`0.4`、`=1.2.3`
+    pub version: String,
+    pub features: Vec<String>,
+    pub default_features: bool,
+}
+
+impl Dependency {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            features: Vec::new(),
+            default_features: true,
+        }
+    }
+    pub fn with_features(mut self, features: &[&str]) -> Self {
+        self.features = features.iter().map(|f| f.to_string()).collect();
+        self
+    }
+    pub fn without_default_features(mut self) -> Self {
+        self.default_features = false;
+        self
+    }
+
+    // 写入Cargo.toml的一行依赖，校验后字段中不会出现需要转义的字符
+    pub(crate) fn to_toml(&self) -> String {
+        let mut spec = format!("version = \"{}\"", self.version);
+        if !self.default_features {
+            spec.push_str(", default-features = false");
+        }
+        if !self.features.is_empty() {
+            let features: Vec<String> =
+                self.features.iter().map(|f| format!("\"{}\"", f)).collect();
+            spec.push_str(&format!(", features = [{}]", features.join(", ")));
+        }
+        format!("{} = {{ {} }}", self.name, spec)
+    }
+}
+
+/// 校验插件声明的依赖：必须在白名单中，不能与固定依赖重名，名称、版本、feature只能包含合法的字符
+pub fn validate_dependencies(
+    dependencies: &[Dependency],
+    allowed: &HashSet<String>,
+    target: PluginTarget,
+) -> Result<(), BuildError> {
+    let builtin = match target {
+        PluginTarget::Native => BUILTIN_NATIVE,
+        PluginTarget::Wasm => BUILTIN_WASM,
+    };
+    let mut names = HashSet::new();
+    for dep in dependencies {
+        let invalid =
+            |reason: &str| BuildError::DependencyError(format!("{}: {}", dep.name, reason));
+        if !is_identifier(&dep.name) {
+            return Err(invalid("invalid crate name"));
+        }
+        if builtin.contains(&dep.name.as_str()) {
+            return Err(invalid("conflicts with a built-in dependency"));
+        }
+        if !allowed.contains(&dep.name) {
+            return Err(invalid("not in the allow-list"));
+        }
+        if !names.insert(dep.name.as_str()) {
+            return Err(invalid("declared more than once"));
+        }
+        if dep.version.trim().is_empty()
+            || !dep
+                .version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".*^~=<>, +-".contains(c))
+        {
+            return Err(invalid("invalid version requirement"));
+        }
+        if let Some(feature) = dep
+            .features
+            .iter()
+            .find(|f| !f.split('/').all(is_identifier))
+        {
+            return Err(invalid(&format!("invalid feature {}", feature)));
+        }
+    }
+    Ok(())
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
@@ -10,6 +10,14 @@ pub enum BuildError {
     BuildProjectError(String),
     #[error("move lib error: \n{0}")]
     MoveLibError(String),
+    #[error("invalid dependency: \n{0}")]
+    DependencyError(String),
+    #[error("lockfile error: \n{0}")]
+    LockfileError(String),
+    #[error("checksum mismatch: \n{0}")]
+    ChecksumMismatch(String),
+    #[error("signature error: \n{0}")]
+    SignatureError(String),
     #[error(transparent)]
     Utf8Error(#[from] std::str::Utf8Error),
     #[error(transparent)]
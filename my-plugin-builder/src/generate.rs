@@ -1,39 +1,91 @@
 use std::{
-    fs::{create_dir_all, remove_dir_all, File},
-    io::Write,
+    fs::{copy, create_dir_all, remove_dir_all, File},
+    io::{self, Write},
+    path::{absolute, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
+use my_interface::PluginName;
 use proc_macro2::TokenStream;
+use quote::quote;
 
-use crate::errors::BuildError;
+use crate::{
+    deps::Dependency, errors::BuildError, install::write_atomic, BuildOptions, PluginTarget,
+};
+
+// 本进程创建的临时项目的序号，与进程号一起保证目录唯一
+static TMP_PROJECT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 编译插件的临时项目，释放时删除目录
+pub struct TmpProject {
+    path: PathBuf,
+}
+
+impl TmpProject {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TmpProject {
+    fn drop(&mut self) {
+        if let Err(e) = remove_dir_all(&self.path) {
+            log::warn!("clean folder {} failed: {}", self.path.display(), e);
+        }
+    }
+}
 
 /// 创建动态链接包目录
-pub fn create_lib_folder_if_not_exist() {
-    create_dir_all("./libs").expect("create libs folder failed.")
+pub fn create_lib_folder_if_not_exist(options: &BuildOptions) -> Result<(), BuildError> {
+    create_dir_all(&options.libs_dir).map_err(BuildError::IOError)
 }
 
-/// 创建临时项目目录
-pub fn create_tmp_folder(name: &String) -> Result<(), BuildError> {
-    let path = format!("./tmp_{}_project/src", name.to_lowercase());
-    create_dir_all(path)
-        .map(|_| ())
-        .map_err(|e| BuildError::IOError(e))
+/// 创建临时项目目录，每次编译使用不同的目录，同一插件同时编译也不会互相覆盖
+pub fn create_tmp_folder(
+    name: &PluginName,
+    options: &BuildOptions,
+) -> Result<TmpProject, BuildError> {
+    let seq = TMP_PROJECT_SEQ.fetch_add(1, Ordering::Relaxed);
+    let path = options.workspace.join(format!(
+        "tmp_{}_{}_{}_project",
+        name,
+        std::process::id(),
+        seq
+    ));
+    // 进程号可能被复用，清理之前异常退出时遗留的目录
+    if path.exists() {
+        remove_dir_all(&path)?;
+    }
+    create_dir_all(path.join("src")).map_err(BuildError::IOError)?;
+    Ok(TmpProject { path })
 }
 
-/// 删除临时项目目录
-pub fn clean_tmp_folder(name: &String) {
-    let path = format!("./tmp_{}_project", name.to_lowercase());
-    remove_dir_all(path).expect("clean folder failed.")
+// 临时项目中my-interface的依赖，使用绝对路径，与临时项目所在的目录无关
+fn interface_dep(options: &BuildOptions, extra: &str) -> Result<String, BuildError> {
+    let path = absolute(&options.interface_path)?;
+    Ok(format!(
+        r#"{{ path = {:?}, version = "*"{} }}"#,
+        path.display().to_string(),
+        extra
+    ))
 }
 
 /// 创建临时项目cargo.toml文件
-pub fn create_cargo_toml(name: &String) -> Result<(), BuildError> {
-    let lower_name = name.to_lowercase();
-    let path = format!("./tmp_{}_project/Cargo.toml", &lower_name);
-    let mut file = File::create(path)?;
+pub fn create_cargo_toml(
+    project: &TmpProject,
+    name: &PluginName,
+    dependencies: &[Dependency],
+    target: PluginTarget,
+    options: &BuildOptions,
+) -> Result<(), BuildError> {
+    if target == PluginTarget::Wasm {
+        return create_wasm_cargo_toml(project, name, dependencies, options);
+    }
+    let mut file = File::create(project.path().join("Cargo.toml"))?;
 
-    let interface_dep_code = r#"{ path = "../my-interface", version = "*" }"#;
-    let juniper_dep_code = r#"{version = "0.15.6", features = ["expose-test-schema"]}"#;
+    let interface_dep_code = interface_dep(options, "")?;
+    // 不开启juniper的`expose-test-schema`：其中示例schema生成的代码不固定，同一插件两次编译的产物不一致
+    let juniper_dep_code = r#"{version = "0.15.6"}"#;
     let code = format!(
         r#"
     [package]
@@ -55,25 +107,156 @@ pub fn create_cargo_toml(name: &String) -> Result<(), BuildError> {
     warp = "0.3"
     serde = "1.0"
     serde_json = "1.0"
+    {extra_deps}
     
     [lib]
     name = "_{lib_name}"
     crate-type = ["dylib"] 
     "#,
-        package_name = &lower_name,
+        package_name = name,
         interface_dep = interface_dep_code,
         juniper_dep = juniper_dep_code,
-        lib_name = &lower_name
+        extra_deps = extra_deps(dependencies),
+        lib_name = name
     );
     file.write_all(code.as_bytes())
         .map(|_| ())
-        .map_err(|e| BuildError::IOError(e))
+        .map_err(BuildError::IOError)
+}
+
+/// 创建wasm插件临时项目的cargo.toml文件，不依赖warp等无法编译为wasm的依赖
+fn create_wasm_cargo_toml(
+    project: &TmpProject,
+    name: &PluginName,
+    dependencies: &[Dependency],
+    options: &BuildOptions,
+) -> Result<(), BuildError> {
+    let mut file = File::create(project.path().join("Cargo.toml"))?;
+
+    let interface_dep_code = interface_dep(
+        options,
+        r#", default-features = false, features = ["wasm-guest"]"#,
+    )?;
+    let juniper_dep_code = r#"{version = "0.15.6"}"#;
+    let code = format!(
+        r#"
+    [package]
+    name = "{package_name}-plugin"
+    version = "0.1.0"
+    authors = ["jackywong@mail.com"]
+    edition = "2018"
+
+    [workspace]
+
+    [dependencies]
+    my-interface = {interface_dep}
+    juniper = {juniper_dep}
+    serde = "1.0"
+    serde_json = "1.0"
+    {extra_deps}
+
+    [lib]
+    name = "_{lib_name}"
+    crate-type = ["cdylib"]
+    "#,
+        package_name = name,
+        interface_dep = interface_dep_code,
+        juniper_dep = juniper_dep_code,
+        extra_deps = extra_deps(dependencies),
+        lib_name = name
+    );
+    file.write_all(code.as_bytes())
+        .map(|_| ())
+        .map_err(BuildError::IOError)
+}
+
+// 插件声明的额外依赖，每行一个
+fn extra_deps(dependencies: &[Dependency]) -> String {
+    dependencies
+        .iter()
+        .map(Dependency::to_toml)
+        .collect::<Vec<_>>()
+        .join("\n    ")
+}
+
+/// 创建临时项目的.cargo/config.toml：将临时目录映射为固定的路径，编译产物与目录名无关；
+/// 配置了vendor目录时以vendor目录替换crates.io
+pub fn create_cargo_config(project: &TmpProject, options: &BuildOptions) -> Result<(), BuildError> {
+    let config_dir = project.path().join(".cargo");
+    create_dir_all(&config_dir)?;
+    let mut file = File::create(config_dir.join("config.toml"))?;
+    let mut code = format!(
+        r#"
+    [build]
+    rustflags = ["--remap-path-prefix", {:?}]
+    "#,
+        format!("{}=/plugin", absolute(project.path())?.display())
+    );
+    if let Some(vendor_dir) = &options.vendor_dir {
+        code.push_str(&format!(
+            r#"
+    [source.crates-io]
+    replace-with = "vendored-sources"
+
+    [source.vendored-sources]
+    directory = {:?}
+    "#,
+            absolute(vendor_dir)?.display().to_string()
+        ));
+    }
+    file.write_all(code.as_bytes())
+        .map(|_| ())
+        .map_err(BuildError::IOError)
+}
+
+/// 复制Cargo.lock到临时项目：配置了`lockfile`时使用主服务的Cargo.lock，
+/// 否则使用上次编译保存的Cargo.lock，重新编译时使用相同版本的依赖
+pub fn restore_lockfile(
+    project: &TmpProject,
+    name: &PluginName,
+    target: PluginTarget,
+    options: &BuildOptions,
+) -> Result<(), BuildError> {
+    let source = match &options.lockfile {
+        Some(lockfile) => lockfile.clone(),
+        None => options.lockfile_path(name, target),
+    };
+    if source.exists() {
+        copy(source, project.path().join("Cargo.lock"))?;
+    } else if options.lockfile.is_some() {
+        return Err(BuildError::LockfileError(format!(
+            "{} not found",
+            source.display()
+        )));
+    }
+    Ok(())
+}
+
+/// 将临时项目的Cargo.lock与编译产物保存在一起
+pub fn save_lockfile(
+    project: &TmpProject,
+    name: &PluginName,
+    target: PluginTarget,
+    options: &BuildOptions,
+) -> Result<(), BuildError> {
+    let mut lockfile = File::open(project.path().join("Cargo.lock"))?;
+    write_atomic(&options.lockfile_path(name, target), |file| {
+        io::copy(&mut lockfile, file).map(|_| ())
+    })?;
+    Ok(())
+}
+
+/// 原生插件导出的接口版本，主服务加载前先读取该符号，与自身的版本不一致时拒绝加载
+pub fn interface_version_tokens() -> TokenStream {
+    quote! {
+        #[no_mangle]
+        pub static PLUGIN_INTERFACE_VERSION: u32 = my_interface::INTERFACE_VERSION;
+    }
 }
 
-pub fn create_source(name: &String, tokens: TokenStream) -> Result<(), BuildError> {
-    let path = format!("./tmp_{}_project/src/lib.rs", name.to_lowercase());
-    let mut file = File::create(path)?;
+pub fn create_source(project: &TmpProject, tokens: TokenStream) -> Result<(), BuildError> {
+    let mut file = File::create(project.path().join("src").join("lib.rs"))?;
     file.write_all(tokens.to_string().as_bytes())
         .map(|_| ())
-        .map_err(|e| BuildError::IOError(e))
+        .map_err(BuildError::IOError)
 }
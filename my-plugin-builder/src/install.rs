@@ -0,0 +1,244 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use my_interface::INTERFACE_VERSION;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    errors::BuildError,
+    signing::{read_signing_key, signature_path, signature_sidecar},
+};
+
+// 本进程写入的临时文件的序号，与进程号一起保证文件名唯一
+static TMP_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 编译产物的sha256校验文件路径，如`lib_foo.so.sha256`
+pub fn checksum_path(artifact: &Path) -> PathBuf {
+    let mut path = OsString::from(artifact.as_os_str());
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// 编译产物的接口版本文件路径，如`lib_foo.so.interface`，记录编译时my-interface的`INTERFACE_VERSION`
+pub fn interface_version_path(artifact: &Path) -> PathBuf {
+    let mut path = OsString::from(artifact.as_os_str());
+    path.push(".interface");
+    PathBuf::from(path)
+}
+
+/// 编译产物编译时的接口版本，没有或无法解析接口版本文件时返回None
+pub fn interface_version_of(artifact: &Path) -> Option<u32> {
+    fs::read_to_string(interface_version_path(artifact))
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+}
+
+/// 文件内容的sha256
+pub fn sha256_file(path: &Path) -> Result<String, BuildError> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// 原子地写入文件：先写入同目录下的临时文件并fsync，再重命名为目标文件。
+/// 读取方只会看到旧文件或完整的新文件
+pub fn write_atomic(
+    dest: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let dir = dest.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(
+        ".{}.tmp-{}-{}",
+        file_name,
+        std::process::id(),
+        TMP_FILE_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&tmp)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, dest));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // 持久化目录项，保证重命名在断电后依然生效
+    File::open(dir)?.sync_all()
+}
+
+// 安装过程中以编译产物的sha256命名的附属文件，如`.lib_foo.so.sha256.<sha256>`
+fn pending_path(sidecar: &Path, checksum: &str) -> PathBuf {
+    let file_name = sidecar.file_name().unwrap_or_default().to_string_lossy();
+    sidecar.with_file_name(format!(".{}.{}", file_name, checksum))
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// 读取与编译产物内容对应的附属文件（sha256校验文件、签名文件），不存在时返回None。
+/// 安装时附属文件先以内容命名写入，替换编译产物后再重命名为正式的文件：
+/// 正式的文件与内容不匹配时依次读取以内容命名的文件与重命名后的正式文件，与安装同时进行时也能读到匹配的附属文件
+pub fn read_sidecar(
+    sidecar: &Path,
+    checksum: &str,
+    matches: impl Fn(&str) -> bool,
+) -> io::Result<Option<String>> {
+    let current = read_if_exists(sidecar)?;
+    if current.as_deref().is_some_and(&matches) {
+        return Ok(current);
+    }
+    if let Some(pending) = read_if_exists(&pending_path(sidecar, checksum))? {
+        return Ok(Some(pending));
+    }
+    Ok(read_if_exists(sidecar)?.or(current))
+}
+
+/// 安装编译产物：附属文件先以内容命名写入，再原子地复制编译产物到目标位置，最后将附属文件重命名为正式的文件。
+/// 替换编译产物前失败时清理已写入的附属文件
+pub fn install_with_sidecars(
+    src: &Path,
+    dest: &Path,
+    checksum: &str,
+    sidecars: &[(PathBuf, String)],
+) -> Result<(), BuildError> {
+    let mut pending = Vec::new();
+    let mut installed = false;
+    let mut install = || -> io::Result<()> {
+        for (sidecar, content) in sidecars {
+            let path = pending_path(sidecar, checksum);
+            write_atomic(&path, |file| file.write_all(content.as_bytes()))?;
+            pending.push((path, sidecar));
+        }
+        write_atomic(dest, |file| {
+            io::copy(&mut File::open(src)?, file).map(|_| ())
+        })?;
+        installed = true;
+        for (path, sidecar) in pending.iter() {
+            fs::rename(path, sidecar)?;
+        }
+        Ok(())
+    };
+    let result = install();
+    // 编译产物已替换时保留以内容命名的附属文件，读取方依然可以校验
+    if result.is_err() && !installed {
+        for (path, _) in pending.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+    Ok(result?)
+}
+
+/// 安装编译产物，并在旁边写入sha256校验文件与接口版本文件；指定私钥时先签名临时的编译产物，签名文件与校验文件一起安装
+pub fn install_artifact(
+    src: &Path,
+    dest: &Path,
+    signing_key: Option<&Path>,
+) -> Result<(), BuildError> {
+    let key = signing_key.map(read_signing_key).transpose()?;
+    let checksum = sha256_file(src)?;
+    let mut sidecars = vec![
+        checksum_sidecar(dest, &checksum),
+        (
+            interface_version_path(dest),
+            format!("{}\n", INTERFACE_VERSION),
+        ),
+    ];
+    if let Some(key) = &key {
+        sidecars.push(signature_sidecar(dest, &checksum, key));
+    }
+    install_with_sidecars(src, dest, &checksum, &sidecars)
+}
+
+// sha256校验文件的内容，与`sha256sum`的输出格式一致
+fn checksum_sidecar(dest: &Path, checksum: &str) -> (PathBuf, String) {
+    let line = format!(
+        "{}  {}\n",
+        checksum,
+        dest.file_name().unwrap_or_default().to_string_lossy()
+    );
+    (checksum_path(dest), line)
+}
+
+// 校验文件中记录的sha256
+fn recorded_checksum(content: &str) -> &str {
+    content.split_whitespace().next().unwrap_or_default()
+}
+
+/// 将金丝雀版本的编译产物提升为稳定版本的编译产物：连同接口版本文件、签名文件与Cargo.lock一起安装到dest。
+/// checksum为金丝雀版本加载时的sha256，编译产物在加载后被重新编译时拒绝提升；
+/// 金丝雀版本没有的附属文件在安装后从dest删除，不会与新的编译产物混用
+pub fn promote_artifact(src: &Path, dest: &Path, checksum: &str) -> Result<(), BuildError> {
+    let actual = sha256_file(src)?;
+    if actual != checksum {
+        return Err(BuildError::ChecksumMismatch(format!(
+            "{} changed since it was loaded",
+            src.display()
+        )));
+    }
+    let mut sidecars = vec![checksum_sidecar(dest, checksum)];
+    let mut missing = Vec::new();
+    for sidecar in [interface_version_path, signature_path, lockfile_path] {
+        match read_if_exists(&sidecar(src))? {
+            Some(content) => sidecars.push((sidecar(dest), content)),
+            None => missing.push(sidecar(dest)),
+        }
+    }
+    install_with_sidecars(src, dest, checksum, &sidecars)?;
+    for path in missing {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 与编译产物一起保存的Cargo.lock的路径，如`lib_foo.so.Cargo.lock`
+pub fn lockfile_path(artifact: &Path) -> PathBuf {
+    let mut path = OsString::from(artifact.as_os_str());
+    path.push(".Cargo.lock");
+    PathBuf::from(path)
+}
+
+/// 校验编译产物的sha256，没有校验文件时返回false，与校验文件不一致时返回错误
+pub fn verify_artifact(path: &Path) -> Result<bool, BuildError> {
+    let actual = sha256_file(path)?;
+    verify_checksum(path, &actual)
+}
+
+/// 以已计算的sha256校验编译产物，用于校验与加载同一份副本
+pub fn verify_checksum(path: &Path, actual: &str) -> Result<bool, BuildError> {
+    let content = read_sidecar(&checksum_path(path), actual, |content| {
+        recorded_checksum(content).eq_ignore_ascii_case(actual)
+    })?;
+    let expected = match &content {
+        Some(content) => recorded_checksum(content),
+        None => return Ok(false),
+    };
+    if expected.eq_ignore_ascii_case(actual) {
+        Ok(true)
+    } else {
+        Err(BuildError::ChecksumMismatch(format!(
+            "{}: expected {}, got {}",
+            path.display(),
+            expected,
+            actual
+        )))
+    }
+}
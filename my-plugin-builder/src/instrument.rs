@@ -0,0 +1,106 @@
+use proc_macro2::{TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{
+    parse_quote,
+    visit_mut::{self, VisitMut},
+    Attribute, ImplItem, ItemImpl, LitStr, Type,
+};
+
+/// 为插件中所有`#[graphql_object]`的resolver插入字段追踪，span由主服务通过`my_interface::trace_field`创建
+pub fn instrument_resolvers(tokens: TokenStream) -> syn::Result<TokenStream> {
+    let mut file: syn::File = syn::parse2(tokens)?;
+    ResolverInstrumenter.visit_file_mut(&mut file);
+    Ok(file.into_token_stream())
+}
+
+struct ResolverInstrumenter;
+
+impl VisitMut for ResolverInstrumenter {
+    fn visit_item_impl_mut(&mut self, item: &mut ItemImpl) {
+        visit_mut::visit_item_impl_mut(self, item);
+        let object_attr = match item
+            .attrs
+            .iter()
+            .find(|attr| is_attr(attr, "graphql_object"))
+        {
+            Some(attr) => attr,
+            None => return,
+        };
+        let object = match (name_arg(object_attr), item.self_ty.as_ref()) {
+            (Some(name), _) => name,
+            (None, Type::Path(path)) => match path.path.segments.last() {
+                Some(segment) => segment.ident.to_string(),
+                None => return,
+            },
+            _ => return,
+        };
+        for impl_item in item.items.iter_mut() {
+            if let ImplItem::Method(method) = impl_item {
+                let graphql_attr = method.attrs.iter().find(|attr| is_attr(attr, "graphql"));
+                if graphql_attr.map(has_ignore_arg).unwrap_or(false) {
+                    continue;
+                }
+                let field = graphql_attr
+                    .and_then(name_arg)
+                    .unwrap_or_else(|| to_camel_case(&method.sig.ident.to_string()));
+                let stmt = parse_quote! {
+                    let _field_span = my_interface::trace_field(#object, #field);
+                };
+                method.block.stmts.insert(0, stmt);
+            }
+        }
+    }
+}
+
+fn is_attr(attr: &Attribute, name: &str) -> bool {
+    attr.path
+        .segments
+        .last()
+        .map(|segment| segment.ident == name)
+        .unwrap_or(false)
+}
+
+// 属性参数中的`name = "..."`。参数中可能包含`context = DataContext`这类非字面量的值，无法用parse_meta解析
+fn name_arg(attr: &Attribute) -> Option<String> {
+    let tokens: Vec<TokenTree> = attr_args(attr);
+    tokens.windows(3).find_map(|window| match window {
+        [TokenTree::Ident(ident), TokenTree::Punct(punct), TokenTree::Literal(lit)]
+            if ident == "name" && punct.as_char() == '=' =>
+        {
+            syn::parse2::<LitStr>(quote!(#lit))
+                .ok()
+                .map(|lit| lit.value())
+        }
+        _ => None,
+    })
+}
+
+fn has_ignore_arg(attr: &Attribute) -> bool {
+    attr_args(attr).iter().any(
+        |token| matches!(token, TokenTree::Ident(ident) if ident == "ignore" || ident == "skip"),
+    )
+}
+
+fn attr_args(attr: &Attribute) -> Vec<TokenTree> {
+    match attr.tokens.clone().into_iter().next() {
+        Some(TokenTree::Group(group)) => group.stream().into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+// 与juniper一致，字段名默认由snake_case转换为camelCase
+fn to_camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper = false;
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' && i > 0 {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
@@ -1,50 +1,196 @@
-use std::{process::Command, str::from_utf8};
+use std::{collections::HashSet, path::PathBuf, process::Command, str::from_utf8};
 
+use deps::{validate_dependencies, Dependency};
 use errors::BuildError;
 use generate::*;
-use my_interface::get_lib_suffix;
+use install::{install_artifact, interface_version_of};
+use instrument::instrument_resolvers;
+use lockfile::lock_generated_package;
+use my_interface::{get_lib_suffix, PluginName, INTERFACE_VERSION};
 use proc_macro2::TokenStream;
 
 pub mod demo;
+pub mod deps;
 pub mod errors;
 mod generate;
+pub mod install;
+mod instrument;
+mod lockfile;
+pub mod signing;
 
-pub fn build_plugin(name: String, tokens: TokenStream) -> Result<(), BuildError> {
-    let project_path = format!("./tmp_{}_project", &name.to_lowercase());
-    create_lib_folder_if_not_exist();
-    create_tmp_folder(&name)?;
-    create_cargo_toml(&name)?;
-    create_source(&name, tokens)?;
+/// wasm插件的编译目标，新版rustc中wasm32-wasi已更名为wasm32-wasip1
+pub const WASM_TARGET: &str = "wasm32-wasip1";
+
+/// 插件的编译目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginTarget {
+    /// 原生动态链接包，需要与主服务的rustc版本、操作系统一致
+    Native,
+    /// wasm32-wasi，由主服务内嵌的wasm运行时执行
+    Wasm,
+}
+
+impl PluginTarget {
+    /// 编译产物的后缀
+    pub fn lib_suffix(&self) -> String {
+        match self {
+            PluginTarget::Native => get_lib_suffix(),
+            PluginTarget::Wasm => "wasm".to_string(),
+        }
+    }
+    /// 编译目标的名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PluginTarget::Native => "native",
+            PluginTarget::Wasm => "wasm",
+        }
+    }
+}
+
+/// 编译插件使用的目录
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// 临时项目所在的目录
+    pub workspace: PathBuf,
+    /// 编译产物存放的目录
+    pub libs_dir: PathBuf,
+    /// my-interface的路径，临时项目以路径依赖引用
+    pub interface_path: PathBuf,
+    /// 插件允许声明的额外依赖
+    pub allowed_dependencies: HashSet<String>,
+    /// `cargo vendor`生成的目录，配置后以其替换crates.io，无需访问网络
+    pub vendor_dir: Option<PathBuf>,
+    /// 主服务的Cargo.lock，配置后插件的依赖版本与主服务一致
+    pub lockfile: Option<PathBuf>,
+    /// 以`--offline`编译，不访问网络
+    pub offline: bool,
+    /// 以`--frozen`编译，不访问网络，且所有依赖都必须已被Cargo.lock锁定
+    pub frozen: bool,
+    /// PKCS#8 PEM格式的ed25519私钥，配置后编译产物安装前以其签名
+    pub signing_key: Option<PathBuf>,
+    /// 金丝雀版本的版本名，指定时编译产物安装为`lib_<插件名>@<版本名>.<后缀>`，不影响稳定版本的编译产物。
+    /// 版本名会被拼接到路径中，由调用方校验
+    pub version: Option<String>,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            workspace: PathBuf::from("."),
+            libs_dir: PathBuf::from("./libs"),
+            interface_path: PathBuf::from("./my-interface"),
+            allowed_dependencies: HashSet::new(),
+            vendor_dir: None,
+            lockfile: None,
+            offline: false,
+            frozen: false,
+            signing_key: None,
+            version: None,
+        }
+    }
+}
+
+impl BuildOptions {
+    /// 插件编译产物的路径，指定了版本时为该金丝雀版本的编译产物
+    pub fn lib_path(&self, name: &PluginName, target: PluginTarget) -> PathBuf {
+        match &self.version {
+            Some(version) => {
+                self.libs_dir
+                    .join(format!("lib_{}@{}.{}", name, version, target.lib_suffix()))
+            }
+            None => self
+                .libs_dir
+                .join(format!("lib_{}.{}", name, target.lib_suffix())),
+        }
+    }
+    /// 金丝雀版本的编译选项，其余选项不变
+    pub fn for_version(&self, version: &str) -> Self {
+        Self {
+            version: Some(version.to_string()),
+            ..self.clone()
+        }
+    }
+    /// 与编译产物一起保存的Cargo.lock的路径
+    pub fn lockfile_path(&self, name: &PluginName, target: PluginTarget) -> PathBuf {
+        install::lockfile_path(&self.lib_path(name, target))
+    }
+    /// 编译产物已存在，并且编译时的接口版本与当前一致，不需要重新编译
+    pub fn is_up_to_date(&self, name: &PluginName, target: PluginTarget) -> bool {
+        let path = self.lib_path(name, target);
+        path.exists() && interface_version_of(&path) == Some(INTERFACE_VERSION)
+    }
+}
+
+/// 编译原生动态链接包插件
+pub fn build_plugin(name: PluginName, tokens: TokenStream) -> Result<(), BuildError> {
+    build_plugin_for(
+        name,
+        tokens,
+        &[],
+        PluginTarget::Native,
+        &BuildOptions::default(),
+    )
+}
+
+/// 编译插件，dependencies为插件声明的额外依赖，需要在`allowed_dependencies`中。
+/// 编译使用的Cargo.lock与编译产物一起保存，重新编译时沿用
+pub fn build_plugin_for(
+    name: PluginName,
+    tokens: TokenStream,
+    dependencies: &[Dependency],
+    target: PluginTarget,
+    options: &BuildOptions,
+) -> Result<(), BuildError> {
+    validate_dependencies(dependencies, &options.allowed_dependencies, target)?;
+    // resolver自动插入字段追踪
+    let mut tokens =
+        instrument_resolvers(tokens).map_err(|e| BuildError::CreateSrcError(e.to_string()))?;
+    if target == PluginTarget::Native {
+        tokens.extend(interface_version_tokens());
+    }
+    create_lib_folder_if_not_exist(options)?;
+    // 临时项目离开作用域时删除，编译失败或返回错误时同样会清理
+    let project = create_tmp_folder(&name, options)?;
+    create_cargo_toml(&project, &name, dependencies, target, options)?;
+    create_cargo_config(&project, options)?;
+    restore_lockfile(&project, &name, target, options)?;
+    create_source(&project, tokens)?;
+    if options.frozen {
+        lock_generated_package(&project, &name)?;
+    }
 
     // 编译依赖
-    let build_out = Command::new("cargo")
-        .current_dir(&project_path)
-        .arg("build")
-        .output()?;
+    let mut build_cmd = Command::new("cargo");
+    build_cmd.current_dir(project.path()).arg("build");
+    if options.frozen {
+        build_cmd.arg("--frozen");
+    } else if options.offline {
+        build_cmd.arg("--offline");
+    }
+    if target == PluginTarget::Wasm {
+        build_cmd.arg("--target").arg(WASM_TARGET);
+    }
+    let build_out = build_cmd.output()?;
     if !&build_out.status.success() {
-        clean_tmp_folder(&name);
         log::error!("{}", from_utf8(&build_out.stderr).unwrap());
-        return Err(BuildError::BuildProjectError(name));
-    }
-
-    let target_suffix = get_lib_suffix();
-    let target_path = format!(
-        "target/debug/lib_{}.{}",
-        &name.to_lowercase(),
-        target_suffix
-    );
-    // 移动动态链接包
-    let mv_out = Command::new("mv")
-        .current_dir(&project_path)
-        .arg(target_path)
-        .arg("../libs")
-        .output()?;
-    if !&mv_out.status.success() {
-        clean_tmp_folder(&name);
-        log::error!("{}", from_utf8(&mv_out.stderr).unwrap());
-        return Err(BuildError::MoveLibError(name));
-    }
-    // 删除临时目录
-    clean_tmp_folder(&name);
-    Ok(())
+        return Err(BuildError::BuildProjectError(name.to_string()));
+    }
+
+    let target_path = match target {
+        PluginTarget::Native => format!("target/debug/lib_{}.{}", name, get_lib_suffix()),
+        PluginTarget::Wasm => format!("target/{}/debug/_{}.wasm", WASM_TARGET, name),
+    };
+    // 安装动态链接包以及校验文件与签名，运行中的主服务不会加载到写了一半的文件
+    if let Err(e) = install_artifact(
+        &project.path().join(target_path),
+        &options.lib_path(&name, target),
+        options.signing_key.as_deref(),
+    ) {
+        log::error!("{}", e);
+        return Err(match e {
+            e @ BuildError::SignatureError(_) => e,
+            _ => BuildError::MoveLibError(name.to_string()),
+        });
+    }
+    save_lockfile(&project, &name, target, options)
 }
@@ -0,0 +1,67 @@
+use std::{collections::HashSet, fs, path::Path, process::Command, str::from_utf8};
+
+use my_interface::PluginName;
+
+use crate::{errors::BuildError, generate::TmpProject};
+
+// Cargo.lock中的依赖，以名称、版本、来源区分
+type LockedPackage = (String, String, Option<String>);
+
+fn locked_packages(path: &Path) -> Result<HashSet<LockedPackage>, BuildError> {
+    let content = fs::read_to_string(path)?;
+    let lockfile: toml::Table = content
+        .parse()
+        .map_err(|e| BuildError::LockfileError(format!("{}: {}", path.display(), e)))?;
+    let packages = lockfile
+        .get("package")
+        .and_then(|packages| packages.as_array())
+        .map(|packages| packages.as_slice())
+        .unwrap_or_default();
+    Ok(packages
+        .iter()
+        .filter_map(|package| {
+            let field = |key: &str| package.get(key).and_then(|v| v.as_str()).map(String::from);
+            Some((field("name")?, field("version")?, field("source")))
+        })
+        .collect())
+}
+
+/// `frozen`模式下，先把生成的项目写入Cargo.lock，再检查其余依赖是否都已被锁定。
+/// cargo的`--frozen`不允许修改Cargo.lock，而主服务的Cargo.lock中没有生成的项目
+pub(crate) fn lock_generated_package(
+    project: &TmpProject,
+    name: &PluginName,
+) -> Result<(), BuildError> {
+    let project_path = project.path();
+    let lockfile_path = project_path.join("Cargo.lock");
+    if !lockfile_path.exists() {
+        return Err(BuildError::LockfileError(format!(
+            "{}: no lockfile to build with --frozen",
+            name
+        )));
+    }
+    let pinned = locked_packages(&lockfile_path)?;
+    let update_out = Command::new("cargo")
+        .current_dir(project_path)
+        .args(["update", "--workspace", "--offline"])
+        .output()?;
+    if !update_out.status.success() {
+        return Err(BuildError::LockfileError(
+            from_utf8(&update_out.stderr)?.to_string(),
+        ));
+    }
+    let package_name = format!("{}-plugin", name);
+    let unpinned: Vec<String> = locked_packages(&lockfile_path)?
+        .difference(&pinned)
+        .filter(|(name, _, source)| !(name == &package_name && source.is_none()))
+        .map(|(name, version, _)| format!("{} {}", name, version))
+        .collect();
+    if unpinned.is_empty() {
+        Ok(())
+    } else {
+        Err(BuildError::LockfileError(format!(
+            "dependencies not pinned by the lockfile: {}",
+            unpinned.join(", ")
+        )))
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    Signature, Signer, SigningKey, Verifier, VerifyingKey,
+};
+
+use crate::{errors::BuildError, install::read_sidecar};
+
+/// 编译产物的签名文件路径，如`lib_foo.so.sig`
+pub fn signature_path(artifact: &Path) -> PathBuf {
+    let mut path = OsString::from(artifact.as_os_str());
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+// 签名的内容包含稳定版本的文件名与sha256，签名不能被挪用到其他插件。
+// 金丝雀版本`lib_foo@v2.so`按`lib_foo.so`签名，提升为稳定版本时签名依然有效
+fn signed_message(artifact: &Path, checksum: &str) -> Vec<u8> {
+    let file_name = artifact.file_name().unwrap_or_default().to_string_lossy();
+    let file_name = match file_name.split_once('@') {
+        Some((stem, versioned)) => match versioned.rsplit_once('.') {
+            Some((_, suffix)) => format!("{}.{}", stem, suffix),
+            None => stem.to_string(),
+        },
+        None => file_name.to_string(),
+    };
+    format!("my-plugin-artifact\n{}\n{}", file_name, checksum).into_bytes()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 读取PKCS#8 PEM格式的ed25519私钥（如`openssl genpkey -algorithm ed25519`生成）
+pub fn read_signing_key(key_path: &Path) -> Result<SigningKey, BuildError> {
+    SigningKey::read_pkcs8_pem_file(key_path).map_err(|e| {
+        BuildError::SignatureError(format!("read signing key {}: {}", key_path.display(), e))
+    })
+}
+
+/// 以私钥签名即将安装到artifact的编译产物，返回签名文件的路径与内容（十六进制的签名），与编译产物一起安装
+pub fn signature_sidecar(artifact: &Path, checksum: &str, key: &SigningKey) -> (PathBuf, String) {
+    let signature = key.sign(&signed_message(artifact, checksum));
+    (
+        signature_path(artifact),
+        format!("{}\n", hex(&signature.to_bytes())),
+    )
+}
+
+/// 受信任的ed25519公钥，加载插件前以其校验签名
+#[derive(Default)]
+pub struct TrustedKeys {
+    keys: Vec<(String, VerifyingKey)>,
+}
+
+impl TrustedKeys {
+    /// 读取PKCS#8 PEM格式的公钥（如`openssl pkey -pubout`导出），以文件名作为公钥的名称
+    pub fn load(paths: &[PathBuf]) -> Result<Self, BuildError> {
+        let keys = paths
+            .iter()
+            .map(|path| {
+                let key = VerifyingKey::read_public_key_pem_file(path).map_err(|e| {
+                    BuildError::SignatureError(format!(
+                        "read trusted key {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                Ok((name.to_string(), key))
+            })
+            .collect::<Result<_, BuildError>>()?;
+        Ok(Self { keys })
+    }
+
+    /// 未配置公钥时不校验签名
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// 以已计算的sha256校验编译产物的签名，返回签名所用公钥的名称；没有签名或签名无效时返回错误
+    pub fn verify(&self, artifact: &Path, checksum: &str) -> Result<&str, BuildError> {
+        let message = signed_message(artifact, checksum);
+        let signer = |content: &str| {
+            let signature =
+                from_hex(content.trim()).and_then(|bytes| Signature::from_slice(&bytes).ok())?;
+            self.keys
+                .iter()
+                .find(|(_, key)| key.verify(&message, &signature).is_ok())
+                .map(|(name, _)| name.as_str())
+        };
+        let path = signature_path(artifact);
+        let content = read_sidecar(&path, checksum, |content| signer(content).is_some())?
+            .ok_or_else(|| {
+                BuildError::SignatureError(format!("{} is unsigned", artifact.display()))
+            })?;
+        signer(&content).ok_or_else(|| {
+            BuildError::SignatureError(format!(
+                "{} is not signed by a trusted key",
+                artifact.display()
+            ))
+        })
+    }
+}
@@ -0,0 +1,146 @@
+//! 插件额外依赖的白名单校验
+use std::collections::HashSet;
+
+use my_plugin_builder::{
+    deps::{validate_dependencies, Dependency},
+    errors::BuildError,
+    PluginTarget,
+};
+
+fn allowed(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+// 校验失败时返回错误信息
+fn reject(dependencies: &[Dependency], target: PluginTarget) -> String {
+    let allowed = allowed(&["itoa", "warp", "regex"]);
+    match validate_dependencies(dependencies, &allowed, target) {
+        Err(BuildError::DependencyError(message)) => message,
+        other => panic!("{:?} should be rejected, got {:?}", dependencies, other),
+    }
+}
+
+#[test]
+fn allowed_dependencies_pass() {
+    let dependencies = [
+        Dependency::new("itoa", "1"),
+        Dependency::new("regex", "^1.5")
+            .with_features(&["unicode", "perf/inline"])
+            .without_default_features(),
+    ];
+    let allowed = allowed(&["itoa", "regex"]);
+    validate_dependencies(&dependencies, &allowed, PluginTarget::Native).unwrap();
+    validate_dependencies(&[], &HashSet::new(), PluginTarget::Native).unwrap();
+}
+
+#[test]
+fn disallowed_crate_is_rejected() {
+    assert_eq!(
+        reject(&[Dependency::new("rand", "0.8")], PluginTarget::Native),
+        "rand: not in the allow-list"
+    );
+    // 白名单区分大小写与连字符
+    assert_eq!(
+        reject(&[Dependency::new("Itoa", "1")], PluginTarget::Native),
+        "Itoa: not in the allow-list"
+    );
+}
+
+#[test]
+fn builtin_dependencies_cannot_be_redeclared() {
+    // 生成项目固定的依赖即使在白名单中也不能重新声明
+    assert_eq!(
+        reject(&[Dependency::new("warp", "0.3")], PluginTarget::Native),
+        "warp: conflicts with a built-in dependency"
+    );
+    // wasm插件不依赖warp
+    let allowed = allowed(&["warp"]);
+    validate_dependencies(
+        &[Dependency::new("warp", "0.3")],
+        &allowed,
+        PluginTarget::Wasm,
+    )
+    .unwrap();
+    assert_eq!(
+        reject(&[Dependency::new("my-interface", "*")], PluginTarget::Wasm),
+        "my-interface: conflicts with a built-in dependency"
+    );
+}
+
+#[test]
+fn duplicate_dependency_is_rejected() {
+    let dependencies = [Dependency::new("itoa", "1"), Dependency::new("itoa", "0.4")];
+    assert_eq!(
+        reject(&dependencies, PluginTarget::Native),
+        "itoa: declared more than once"
+    );
+}
+
+#[test]
+fn version_requirements() {
+    let allowed = allowed(&["itoa"]);
+    for version in [
+        "1",
+        "=1.0.2",
+        "^0.4",
+        "~1.2",
+        ">=1, <2",
+        "1.*",
+        "1.0.0-beta.1+build",
+    ] {
+        validate_dependencies(
+            &[Dependency::new("itoa", version)],
+            &allowed,
+            PluginTarget::Native,
+        )
+        .unwrap_or_else(|e| panic!("{} should be valid: {}", version, e));
+    }
+    for version in ["", "  ", "1\n", "1\"", "{ version = \"1\" }"] {
+        assert_eq!(
+            reject(&[Dependency::new("itoa", version)], PluginTarget::Native),
+            "itoa: invalid version requirement",
+            "{:?}",
+            version
+        );
+    }
+}
+
+#[test]
+fn git_and_path_sources_are_rejected() {
+    // 只支持crates.io中的依赖，不能通过版本要求注入git或path来源
+    for version in [
+        "1\", git = \"https://example.com/itoa.git",
+        "1\", path = \"../itoa",
+        "https://example.com/itoa.git",
+        "../itoa",
+    ] {
+        assert_eq!(
+            reject(&[Dependency::new("itoa", version)], PluginTarget::Native),
+            "itoa: invalid version requirement",
+            "{:?}",
+            version
+        );
+    }
+    // 名称与feature同样不能注入
+    assert_eq!(
+        reject(
+            &[Dependency::new("itoa = { path = \"..\" }\nx", "1")],
+            PluginTarget::Native
+        ),
+        "itoa = { path = \"..\" }\nx: invalid crate name"
+    );
+    assert_eq!(
+        reject(
+            &[Dependency::new("itoa", "1").with_features(&["std\"], path = \"..\""])],
+            PluginTarget::Native
+        ),
+        "itoa: invalid feature std\"], path = \"..\""
+    );
+    assert_eq!(
+        reject(
+            &[Dependency::new("itoa", "1").with_features(&[""])],
+            PluginTarget::Native
+        ),
+        "itoa: invalid feature "
+    );
+}
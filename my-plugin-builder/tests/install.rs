@@ -0,0 +1,311 @@
+//! 编译产物与sha256校验文件、接口版本文件、签名的安装、校验与金丝雀版本的提升
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use ed25519_dalek::{
+    pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey, EncodePublicKey},
+    SigningKey,
+};
+use my_interface::{PluginName, INTERFACE_VERSION};
+use my_plugin_builder::{
+    errors::BuildError,
+    install::{
+        checksum_path, install_artifact, install_with_sidecars, interface_version_of,
+        interface_version_path, promote_artifact, sha256_file, verify_artifact,
+    },
+    signing::{signature_path, TrustedKeys},
+    BuildOptions, PluginTarget,
+};
+
+static TEST_DIR_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 每个测试独立的目录，释放时删除
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "my-plugin-builder-install-{}-{}",
+            std::process::id(),
+            TEST_DIR_SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn pending_checksum(lib: &Path) -> PathBuf {
+    let checksum = sha256_file(lib).unwrap();
+    lib.with_file_name(format!(".lib_foo.so.sha256.{}", checksum))
+}
+
+#[test]
+fn install_writes_checksum() {
+    let dir = TestDir::new();
+    let src = dir.write("build.so", "v1");
+    let dest = dir.0.join("lib_foo.so");
+    install_artifact(&src, &dest, None).unwrap();
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "v1");
+    let checksum = fs::read_to_string(checksum_path(&dest)).unwrap();
+    assert_eq!(
+        checksum,
+        format!("{}  lib_foo.so\n", sha256_file(&src).unwrap())
+    );
+    assert!(verify_artifact(&dest).unwrap());
+    // 安装后不遗留以内容命名的校验文件
+    assert_eq!(
+        dir.files(),
+        [
+            "build.so",
+            "lib_foo.so",
+            "lib_foo.so.interface",
+            "lib_foo.so.sha256"
+        ]
+    );
+
+    let src = dir.write("build.so", "v2");
+    install_artifact(&src, &dest, None).unwrap();
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "v2");
+    assert!(verify_artifact(&dest).unwrap());
+}
+
+// 接口版本与当前不一致或者没有接口版本文件的编译产物需要重新编译
+#[test]
+fn interface_version_decides_rebuild() {
+    let dir = TestDir::new();
+    let options = BuildOptions {
+        libs_dir: dir.0.clone(),
+        ..BuildOptions::default()
+    };
+    let name = PluginName::new("foo").unwrap();
+    let dest = options.lib_path(&name, PluginTarget::Native);
+    assert!(!options.is_up_to_date(&name, PluginTarget::Native));
+    install_artifact(&dir.write("build.so", "v1"), &dest, None).unwrap();
+    assert_eq!(interface_version_of(&dest), Some(INTERFACE_VERSION));
+    assert!(options.is_up_to_date(&name, PluginTarget::Native));
+
+    fs::write(
+        interface_version_path(&dest),
+        format!("{}\n", INTERFACE_VERSION + 1),
+    )
+    .unwrap();
+    assert!(!options.is_up_to_date(&name, PluginTarget::Native));
+    fs::remove_file(interface_version_path(&dest)).unwrap();
+    assert!(!options.is_up_to_date(&name, PluginTarget::Native));
+}
+
+// 安装的各个阶段加载插件，都能读到与编译产物匹配的校验文件
+#[test]
+fn verify_during_install() {
+    let dir = TestDir::new();
+    let dest = dir.0.join("lib_foo.so");
+    install_artifact(&dir.write("build.so", "v1"), &dest, None).unwrap();
+    let old_checksum = fs::read_to_string(checksum_path(&dest)).unwrap();
+
+    // 已写入新版本以内容命名的校验文件，编译产物尚未替换
+    let new = dir.write("build.so", "v2");
+    let pending = pending_checksum(&new);
+    fs::write(
+        &pending,
+        format!("{}  lib_foo.so\n", sha256_file(&new).unwrap()),
+    )
+    .unwrap();
+    assert!(verify_artifact(&dest).unwrap());
+
+    // 编译产物已替换，正式的校验文件依然是旧版本的
+    fs::copy(&new, &dest).unwrap();
+    assert_eq!(
+        fs::read_to_string(checksum_path(&dest)).unwrap(),
+        old_checksum
+    );
+    assert!(verify_artifact(&dest).unwrap());
+
+    // 以内容命名的校验文件重命名为正式的文件
+    fs::rename(&pending, checksum_path(&dest)).unwrap();
+    assert!(verify_artifact(&dest).unwrap());
+}
+
+#[test]
+fn tampered_artifact_is_rejected() {
+    let dir = TestDir::new();
+    let dest = dir.0.join("lib_foo.so");
+    install_artifact(&dir.write("build.so", "v1"), &dest, None).unwrap();
+    fs::write(&dest, "tampered").unwrap();
+    assert!(matches!(
+        verify_artifact(&dest),
+        Err(BuildError::ChecksumMismatch(_))
+    ));
+
+    // 没有校验文件时交由调用方决定是否加载
+    fs::remove_file(checksum_path(&dest)).unwrap();
+    assert!(!verify_artifact(&dest).unwrap());
+}
+
+#[test]
+fn failed_install_leaves_nothing_behind() {
+    let dir = TestDir::new();
+    let dest = dir.0.join("lib_foo.so");
+    // 校验文件写入后复制编译产物失败
+    let sidecars = [(checksum_path(&dest), "0000  lib_foo.so\n".to_string())];
+    assert!(install_with_sidecars(&dir.0.join("missing.so"), &dest, "0000", &sidecars).is_err());
+    assert!(dir.files().is_empty());
+}
+
+// 以固定的种子生成ed25519密钥，私钥与公钥以PKCS#8 PEM格式写入测试目录，返回两者的路径
+fn key_pair(dir: &TestDir, name: &str, seed: u8) -> (PathBuf, PathBuf) {
+    let key = SigningKey::from_bytes(&[seed; 32]);
+    let private = dir.write(
+        &format!("{}.pem", name),
+        &key.to_pkcs8_pem(LineEnding::LF).unwrap(),
+    );
+    let public = dir.write(
+        &format!("{}.pub.pem", name),
+        &key.verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap(),
+    );
+    (private, public)
+}
+
+fn verify(keys: &TrustedKeys, lib: &Path) -> Result<String, BuildError> {
+    keys.verify(lib, &sha256_file(lib).unwrap())
+        .map(String::from)
+}
+
+#[test]
+fn signature_round_trip() {
+    let dir = TestDir::new();
+    let (private, public) = key_pair(&dir, "signer", 1);
+    let dest = dir.0.join("lib_foo.so");
+    install_artifact(&dir.write("build.so", "v1"), &dest, Some(&private)).unwrap();
+    assert!(signature_path(&dest).exists());
+    let keys = TrustedKeys::load(&[public]).unwrap();
+    assert_eq!(verify(&keys, &dest).unwrap(), "signer.pub.pem");
+
+    // 签名包含文件名，不能挪用到其他插件
+    let other = dir.0.join("lib_bar.so");
+    fs::copy(&dest, &other).unwrap();
+    fs::copy(signature_path(&dest), signature_path(&other)).unwrap();
+    assert!(matches!(
+        verify(&keys, &other),
+        Err(BuildError::SignatureError(_))
+    ));
+}
+
+#[test]
+fn tampered_artifact_fails_signature() {
+    let dir = TestDir::new();
+    let (private, public) = key_pair(&dir, "signer", 1);
+    let dest = dir.0.join("lib_foo.so");
+    install_artifact(&dir.write("build.so", "v1"), &dest, Some(&private)).unwrap();
+    let keys = TrustedKeys::load(&[public]).unwrap();
+
+    fs::write(&dest, "tampered").unwrap();
+    assert!(matches!(
+        verify(&keys, &dest),
+        Err(BuildError::SignatureError(_))
+    ));
+    // 篡改签名文件同样无法通过
+    install_artifact(&dir.write("build.so", "v1"), &dest, Some(&private)).unwrap();
+    fs::write(signature_path(&dest), "00").unwrap();
+    assert!(matches!(
+        verify(&keys, &dest),
+        Err(BuildError::SignatureError(_))
+    ));
+    fs::remove_file(signature_path(&dest)).unwrap();
+    assert!(matches!(
+        verify(&keys, &dest),
+        Err(BuildError::SignatureError(_))
+    ));
+}
+
+#[test]
+fn untrusted_key_is_rejected() {
+    let dir = TestDir::new();
+    let (private, _) = key_pair(&dir, "signer", 1);
+    let (_, other_public) = key_pair(&dir, "other", 2);
+    let dest = dir.0.join("lib_foo.so");
+    install_artifact(&dir.write("build.so", "v1"), &dest, Some(&private)).unwrap();
+    let keys = TrustedKeys::load(&[other_public]).unwrap();
+    assert!(matches!(
+        verify(&keys, &dest),
+        Err(BuildError::SignatureError(_))
+    ));
+}
+
+// 签名在安装前完成，私钥无效时不安装编译产物
+#[test]
+fn invalid_signing_key_installs_nothing() {
+    let dir = TestDir::new();
+    let private = dir.write("signer.pem", "not a key");
+    let dest = dir.0.join("lib_foo.so");
+    assert!(matches!(
+        install_artifact(&dir.write("build.so", "v1"), &dest, Some(&private)),
+        Err(BuildError::SignatureError(_))
+    ));
+    assert!(!dest.exists());
+    assert!(!signature_path(&dest).exists());
+}
+
+// 提升时金丝雀版本的编译产物连同附属文件安装为稳定版本，签名按稳定版本的文件名校验依然有效
+#[test]
+fn promote_installs_canary_as_stable() {
+    let dir = TestDir::new();
+    let (private, public) = key_pair(&dir, "signer", 1);
+    let keys = TrustedKeys::load(&[public]).unwrap();
+    let stable = dir.0.join("lib_foo.so");
+    install_artifact(&dir.write("build.so", "v1"), &stable, Some(&private)).unwrap();
+    let canary = dir.0.join("lib_foo@1.2.0.so");
+    install_artifact(&dir.write("build.so", "v2"), &canary, Some(&private)).unwrap();
+    assert_eq!(verify(&keys, &canary).unwrap(), "signer.pub.pem");
+    // 以金丝雀版本的签名不能挪用到其他插件
+    let other = dir.0.join("lib_bar@1.2.0.so");
+    fs::copy(&canary, &other).unwrap();
+    fs::copy(signature_path(&canary), signature_path(&other)).unwrap();
+    assert!(verify(&keys, &other).is_err());
+
+    let checksum = sha256_file(&canary).unwrap();
+    promote_artifact(&canary, &stable, &checksum).unwrap();
+    assert_eq!(fs::read_to_string(&stable).unwrap(), "v2");
+    assert!(verify_artifact(&stable).unwrap());
+    assert_eq!(verify(&keys, &stable).unwrap(), "signer.pub.pem");
+    assert_eq!(interface_version_of(&stable), Some(INTERFACE_VERSION));
+
+    // 未签名的金丝雀版本提升后不会遗留旧版本的签名
+    let unsigned = dir.0.join("lib_foo@v3.so");
+    install_artifact(&dir.write("build.so", "v3"), &unsigned, None).unwrap();
+    promote_artifact(&unsigned, &stable, &sha256_file(&unsigned).unwrap()).unwrap();
+    assert!(!signature_path(&stable).exists());
+
+    // 加载后被重新编译的金丝雀版本拒绝提升，稳定版本不变
+    install_artifact(&dir.write("build.so", "v4"), &canary, None).unwrap();
+    assert!(matches!(
+        promote_artifact(&canary, &stable, &checksum),
+        Err(BuildError::ChecksumMismatch(_))
+    ));
+    assert_eq!(fs::read_to_string(&stable).unwrap(), "v3");
+}
@@ -0,0 +1,252 @@
+//! 插件的测试工具：将插件加载到内存中的`HandlerStorage`，以主服务路由使用的`GraphqlRequestHandler`方法执行查询，
+//! 并对响应做快照断言
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+use juniper::{http::GraphQLBatchRequest, DefaultScalarValue};
+use my_interface::{plugin_panic_response, DataContext, Identity, PluginMetadata, PluginName};
+use my_master::{health::catch_panic, Error, HandlerStorage, SharedHandler};
+use my_plugin_builder::{
+    build_plugin_for, deps::Dependency, errors::BuildError, BuildOptions, PluginTarget,
+};
+use proc_macro2::TokenStream;
+use sha2::{Digest, Sha256};
+use warp::{http, Rejection};
+
+mod response;
+mod snapshot;
+
+pub use response::{sorted_ids, TestResponse};
+pub use snapshot::assert_json_snapshot;
+
+// 同一进程中的编译按顺序执行，多个测试同时加载同一插件时只编译一次
+static BUILD_LOCK: Mutex<()> = Mutex::new(());
+
+/// 测试用的插件宿主，插件加载到内存中的处理器存储器
+pub struct TestKit {
+    storage: HandlerStorage,
+    build: BuildOptions,
+}
+
+impl Default for TestKit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestKit {
+    /// 编译目录默认为临时目录下的`my-plugin-testkit`，可以通过环境变量`TESTKIT_DIR`指定。
+    /// 存在工作区的Cargo.lock时以其编译，插件的依赖版本与测试程序一致
+    pub fn new() -> Self {
+        let dir = std::env::var("TESTKIT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("my-plugin-testkit"));
+        Self::in_dir(&dir)
+    }
+
+    /// 在指定的目录中编译插件，其余选项与`new`一致
+    pub fn in_dir(dir: &Path) -> Self {
+        let repo = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        let lockfile = repo.join("Cargo.lock");
+        Self::with_build_options(BuildOptions {
+            workspace: dir.join("projects"),
+            libs_dir: dir.join("libs"),
+            interface_path: repo.join("my-interface"),
+            lockfile: Some(lockfile).filter(|path| path.exists()),
+            ..Default::default()
+        })
+    }
+
+    /// 指定编译插件的选项，编译产物按插件的代码缓存在`libs_dir`的子目录中
+    pub fn with_build_options(build: BuildOptions) -> Self {
+        Self {
+            storage: HandlerStorage::new(),
+            build,
+        }
+    }
+
+    /// 以`--offline`编译，依赖需要已在本地的cargo缓存中
+    pub fn offline(mut self) -> Self {
+        self.build.offline = true;
+        self
+    }
+
+    pub fn storage(&self) -> &HandlerStorage {
+        &self.storage
+    }
+
+    /// 编译token stream生成的原生插件并加载，返回处理器的id。代码与依赖不变时复用之前的编译产物
+    pub fn load_tokens(
+        &mut self,
+        name: &PluginName,
+        tokens: TokenStream,
+        dependencies: &[Dependency],
+    ) -> Result<String, Error> {
+        let path = self.build_tokens(name, tokens, dependencies)?;
+        self.load_lib(&path)
+    }
+
+    /// 从动态链接包中加载插件，返回处理器的id
+    pub fn load_lib(&mut self, path: &Path) -> Result<String, Error> {
+        self.storage.load_lib(path)
+    }
+
+    /// 已加载插件的客户端，使用默认的数据上下文
+    pub fn client(&self, id: &str) -> Result<PluginClient, Error> {
+        let handler = self
+            .storage
+            .get_handler(id.to_string())
+            .ok_or(Error::HandlerNotFound)?;
+        Ok(PluginClient {
+            handler,
+            context: DataContext::new(),
+        })
+    }
+
+    /// 编译token stream生成的原生插件，返回动态链接包的路径
+    pub fn build_tokens(
+        &self,
+        name: &PluginName,
+        tokens: TokenStream,
+        dependencies: &[Dependency],
+    ) -> Result<PathBuf, Error> {
+        let mut hasher = Sha256::new();
+        hasher.update(tokens.to_string());
+        for dependency in dependencies {
+            hasher.update(format!("{:?}", dependency));
+        }
+        // 插件与测试程序共用my-interface，其源码或依赖的版本变化后需要重新编译
+        hash_files(&mut hasher, &self.build.interface_path)?;
+        if let Some(lockfile) = &self.build.lockfile {
+            hash_files(&mut hasher, lockfile)?;
+        }
+        let digest: String = hasher
+            .finalize()
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let options = BuildOptions {
+            libs_dir: self.build.libs_dir.join(format!("{}-{}", name, digest)),
+            allowed_dependencies: dependencies.iter().map(|d| d.name.clone()).collect(),
+            ..self.build.clone()
+        };
+        let path = options.lib_path(name, PluginTarget::Native);
+        let _guard = BUILD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if !path.exists() {
+            build_plugin_for(
+                name.clone(),
+                tokens,
+                dependencies,
+                PluginTarget::Native,
+                &options,
+            )?;
+        }
+        Ok(path)
+    }
+}
+
+// 按路径顺序计算目录下所有文件的内容，忽略target目录
+fn hash_files(hasher: &mut Sha256, path: &Path) -> Result<(), Error> {
+    let io_error = |e: std::io::Error| Error::BuildError(BuildError::IOError(e));
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .map_err(io_error)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io_error)?;
+        entries.sort();
+        for entry in entries.iter().filter(|p| !p.ends_with("target")) {
+            hash_files(hasher, entry)?;
+        }
+    } else {
+        hasher.update(fs::read(path).map_err(io_error)?);
+    }
+    Ok(())
+}
+
+/// 插件的客户端，以指定的数据上下文调用处理器
+#[derive(Clone)]
+pub struct PluginClient {
+    handler: SharedHandler,
+    context: DataContext,
+}
+
+impl PluginClient {
+    /// 指定请求使用的数据上下文
+    pub fn with_context(mut self, context: DataContext) -> Self {
+        self.context = context;
+        self
+    }
+    pub fn with_flag(mut self, flag: bool) -> Self {
+        self.context.flag(flag);
+        self
+    }
+    /// 指定请求的租户以及租户使用的数据集
+    pub fn with_tenant(mut self, tenant: &str, dataset: &str) -> Self {
+        self.context.tenant(tenant, dataset);
+        self
+    }
+    /// 指定调用方的身份
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.context.identity(Some(identity));
+        self
+    }
+    pub fn handler(&self) -> &SharedHandler {
+        &self.handler
+    }
+    pub fn metadata(&self) -> PluginMetadata {
+        self.handler.metadata()
+    }
+    pub fn schema_sdl(&self) -> String {
+        self.handler.schema_sdl()
+    }
+
+    /// 以GET请求执行查询，对应`GET /api/:name/graphql/:flag?query=...`
+    pub async fn get(&self, query: &str) -> Result<TestResponse, Rejection> {
+        let mut qry = HashMap::new();
+        qry.insert("query".to_string(), query.to_string());
+        self.get_with(qry).await
+    }
+
+    /// 以GET请求执行，qry为请求的查询参数，如`query`、`operation_name`、`variables`
+    pub async fn get_with(&self, qry: HashMap<String, String>) -> Result<TestResponse, Rejection> {
+        let context = self.context.clone();
+        self.execute(self.handler.get_request_handle(context, qry))
+            .await
+    }
+
+    /// 以json请求执行，body为单个请求或批量请求，对应`POST /api/:name/graphql/:flag`
+    pub async fn post_json(&self, body: serde_json::Value) -> Result<TestResponse, Rejection> {
+        let req: GraphQLBatchRequest<DefaultScalarValue> =
+            serde_json::from_value(body).expect("body is not a graphql request");
+        let context = self.context.clone();
+        self.execute(self.handler.post_json_request_handle(context, req))
+            .await
+    }
+
+    /// 以`application/graphql`请求执行，body为查询字符串
+    pub async fn post_graphql(&self, query: &str) -> Result<TestResponse, Rejection> {
+        let body = Bytes::from(query.to_string());
+        let context = self.context.clone();
+        self.execute(self.handler.post_grqphql_request_handle(context, body))
+            .await
+    }
+
+    // 与主服务一样捕获插件中的panic，并复制插件构造的响应
+    async fn execute<F>(&self, fut: F) -> Result<TestResponse, Rejection>
+    where
+        F: std::future::Future<Output = Result<http::Response<Vec<u8>>, Rejection>>,
+    {
+        let response = match catch_panic(fut).await {
+            Ok(result) => result?,
+            Err(message) => plugin_panic_response(message),
+        };
+        Ok(TestResponse::from_response(response))
+    }
+}
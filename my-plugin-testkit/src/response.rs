@@ -0,0 +1,109 @@
+use my_interface::PLUGIN_PANIC_HEADER;
+use serde_json::{json, Value};
+use warp::http::{self, HeaderMap, StatusCode};
+
+use crate::snapshot::assert_json_snapshot;
+
+/// 列表中各项的`id`，按升序排列。示例插件的数据存放在HashMap中，列表的顺序不固定
+pub fn sorted_ids(list: &Value) -> Vec<i64> {
+    let mut ids: Vec<i64> = list
+        .as_array()
+        .expect("list")
+        .iter()
+        .map(|v| v["id"].as_i64().expect("id"))
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// 插件的响应，从插件构造的响应中复制而来，插件卸载后依然可以使用
+#[derive(Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub(crate) fn from_response(response: http::Response<Vec<u8>>) -> Self {
+        let (parts, body) = response.into_parts();
+        let mut headers = HeaderMap::new();
+        for (name, value) in parts.headers.iter() {
+            let name = http::header::HeaderName::from_bytes(name.as_str().as_bytes())
+                .expect("header name is valid");
+            let value = http::header::HeaderValue::from_bytes(value.as_bytes())
+                .expect("header value is valid");
+            headers.append(name, value);
+        }
+        Self {
+            status: parts.status,
+            headers,
+            body,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// 响应体的json
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            panic!(
+                "response body is not json ({}): {}",
+                e,
+                String::from_utf8_lossy(&self.body)
+            )
+        })
+    }
+    /// 响应中的`data`
+    pub fn data(&self) -> Value {
+        self.json()["data"].clone()
+    }
+    /// 响应中的`errors`，没有错误时为空
+    pub fn errors(&self) -> Vec<Value> {
+        match self.json().get("errors") {
+            Some(Value::Array(errors)) => errors.clone(),
+            _ => Vec::new(),
+        }
+    }
+    /// 插件内部捕获的panic信息
+    pub fn panic_message(&self) -> Option<String> {
+        self.headers
+            .get(PLUGIN_PANIC_HEADER)
+            .map(|v| v.to_str().unwrap_or_default().to_string())
+    }
+
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(
+            self.status,
+            status,
+            "unexpected status, body: {}",
+            String::from_utf8_lossy(&self.body)
+        );
+        self
+    }
+    /// 断言响应成功且没有graphql错误
+    pub fn assert_ok(&self) -> &Self {
+        self.assert_status(StatusCode::OK);
+        assert_eq!(self.errors(), Vec::<Value>::new(), "unexpected errors");
+        self
+    }
+
+    /// 断言响应的状态码与响应体与快照一致，响应体不是json时以字符串记录
+    pub fn assert_snapshot(&self, name: &str) -> &Self {
+        let body = serde_json::from_slice(&self.body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&self.body).to_string()));
+        assert_json_snapshot(
+            name,
+            &json!({ "status": self.status.as_u16(), "body": body }),
+        );
+        self
+    }
+}
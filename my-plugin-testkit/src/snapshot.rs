@@ -0,0 +1,46 @@
+use std::{fs, path::PathBuf};
+
+use serde_json::Value;
+
+// 快照保存在调用方crate的tests/snapshots目录中
+fn snapshot_path(name: &str) -> PathBuf {
+    let dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    dir.join("tests")
+        .join("snapshots")
+        .join(format!("{}.json", name))
+}
+
+/// 断言json与快照一致，设置了环境变量`UPDATE_SNAPSHOTS=1`时写入快照。
+/// 快照不存在时断言失败，避免遗漏提交快照的测试总是通过
+pub fn assert_json_snapshot(name: &str, value: &Value) {
+    let path = snapshot_path(name);
+    let actual = format!(
+        "{}\n",
+        serde_json::to_string_pretty(value).expect("value is serializable")
+    );
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok_and(|v| v == "1") {
+        fs::create_dir_all(path.parent().expect("snapshot has a parent dir"))
+            .expect("unable to create snapshot dir");
+        fs::write(&path, &actual).expect("unable to write snapshot");
+        eprintln!("snapshot {} written to {}", name, path.display());
+        return;
+    }
+    assert!(
+        path.exists(),
+        "snapshot {} not found ({}), run with UPDATE_SNAPSHOTS=1 to create it\n--- actual\n{}",
+        name,
+        path.display(),
+        actual
+    );
+    let expected = fs::read_to_string(&path).expect("unable to read snapshot");
+    assert!(
+        expected == actual,
+        "snapshot {} mismatch ({}), run with UPDATE_SNAPSHOTS=1 to update\n--- expected\n{}\n--- actual\n{}",
+        name,
+        path.display(),
+        expected,
+        actual
+    );
+}
@@ -0,0 +1,109 @@
+use my_interface::PluginName;
+use my_plugin_builder::demo;
+use my_plugin_testkit::{assert_json_snapshot, sorted_ids, PluginClient, TestKit};
+use serde_json::json;
+use warp::http::StatusCode;
+
+fn bar() -> PluginClient {
+    let mut kit = TestKit::new();
+    let id = kit
+        .load_tokens(
+            &PluginName::new("bar").unwrap(),
+            demo::bar::genernate_tokens(),
+            &[],
+        )
+        .expect("unable to load bar");
+    kit.client(&id).unwrap()
+}
+
+#[test]
+fn metadata() {
+    let client = bar();
+    assert_eq!(client.handler().id(), "bar");
+    assert_json_snapshot("bar_metadata", &json!(client.metadata()));
+}
+
+#[test]
+fn schema() {
+    let sdl = bar().schema_sdl();
+    assert_json_snapshot("bar_schema", &json!(sdl.lines().collect::<Vec<_>>()));
+}
+
+#[tokio::test]
+async fn get_bar() {
+    bar()
+        .get("{ bar(id: 2) { id light } }")
+        .await
+        .unwrap()
+        .assert_ok()
+        .assert_snapshot("bar_get_bar");
+}
+
+#[tokio::test]
+async fn get_missing_bar() {
+    let response = bar().get("{ bar(id: 42) { id } }").await.unwrap();
+    assert_eq!(response.assert_ok().data(), json!({ "bar": null }));
+}
+
+#[tokio::test]
+async fn bars_filtered_by_flag() {
+    let query = "{ bars { id light } }";
+    let response = bar().get(query).await.unwrap();
+    assert_eq!(
+        sorted_ids(&response.assert_ok().data()["bars"]),
+        [1, 2, 3, 4, 6, 7]
+    );
+    let response = bar().with_flag(true).get(query).await.unwrap();
+    assert_eq!(sorted_ids(&response.assert_ok().data()["bars"]), [5, 8]);
+}
+
+#[tokio::test]
+async fn invalid_query() {
+    bar()
+        .post_graphql("{ bar { id } }")
+        .await
+        .unwrap()
+        .assert_status(StatusCode::BAD_REQUEST)
+        .assert_snapshot("bar_invalid_query");
+}
+
+#[tokio::test]
+async fn post_json() {
+    bar()
+        .with_flag(true)
+        .post_json(json!({ "query": "{ bar(id: 5) { id light } }" }))
+        .await
+        .unwrap()
+        .assert_ok()
+        .assert_snapshot("bar_post_json");
+}
+
+#[tokio::test]
+async fn post_json_batch() {
+    let response = bar()
+        .post_json(json!([
+            { "query": "{ bar(id: 1) { light } }" },
+            { "query": "{ bar(id: 2) { light } }" },
+        ]))
+        .await
+        .unwrap();
+    response.assert_status(StatusCode::OK);
+    assert_eq!(
+        response.json(),
+        json!([
+            { "data": { "bar": { "light": "BRIGHT" } } },
+            { "data": { "bar": { "light": "DARK" } } },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn post_graphql() {
+    bar()
+        .with_flag(true)
+        .post_graphql("{ bar(id: 8) { id light } }")
+        .await
+        .unwrap()
+        .assert_ok()
+        .assert_snapshot("bar_post_graphql");
+}
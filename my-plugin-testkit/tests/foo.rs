@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+
+use my_interface::{Identity, PluginName};
+use my_plugin_builder::demo;
+use my_plugin_testkit::{assert_json_snapshot, sorted_ids, PluginClient, TestKit};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+fn foo() -> PluginClient {
+    let mut kit = TestKit::new();
+    let id = kit
+        .load_tokens(
+            &PluginName::new("foo").unwrap(),
+            demo::foo::genernate_tokens(),
+            &[],
+        )
+        .expect("unable to load foo");
+    kit.client(&id).unwrap()
+}
+
+fn identity(scopes: &[&str]) -> Identity {
+    Identity::new(
+        "tester".to_string(),
+        scopes.iter().map(|s| s.to_string()).collect::<HashSet<_>>(),
+    )
+}
+
+#[test]
+fn metadata() {
+    let client = foo();
+    assert_eq!(client.handler().id(), "foo");
+    assert_json_snapshot("foo_metadata", &json!(client.metadata()));
+}
+
+#[test]
+fn schema() {
+    let sdl = foo().schema_sdl();
+    assert_json_snapshot("foo_schema", &json!(sdl.lines().collect::<Vec<_>>()));
+}
+
+#[tokio::test]
+async fn get_foo() {
+    foo()
+        .get("{ foo(id: 1) { id name } }")
+        .await
+        .unwrap()
+        .assert_ok()
+        .assert_snapshot("foo_get_foo");
+}
+
+#[tokio::test]
+async fn get_missing_foo() {
+    let response = foo().get("{ foo(id: 42) { id } }").await.unwrap();
+    response.assert_ok();
+    assert_eq!(response.data(), json!({ "foo": null }));
+}
+
+// 示例数据中foo2的id同样为1，以名称区分
+fn sorted_names(list: &Value) -> Vec<String> {
+    let mut names: Vec<String> = list
+        .as_array()
+        .expect("list")
+        .iter()
+        .map(|v| v["name"].as_str().expect("name").to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn foos_filtered_by_flag() {
+    let query = "{ foos { id name } }";
+    let response = foo().get(query).await.unwrap();
+    assert_eq!(
+        sorted_names(&response.assert_ok().data()["foos"]),
+        ["foo1", "foo2"]
+    );
+    let response = foo().with_flag(true).get(query).await.unwrap();
+    assert_eq!(
+        sorted_names(&response.assert_ok().data()["foos"]),
+        ["foo3", "foo4"]
+    );
+}
+
+#[tokio::test]
+async fn bar_ids_require_scope() {
+    let query = "{ foo(id: 1) { barIds } }";
+    let response = foo().get(query).await.unwrap();
+    assert_eq!(
+        response.errors()[0]["extensions"]["code"],
+        "UNAUTHENTICATED"
+    );
+
+    let response = foo()
+        .with_identity(identity(&["foo:read"]))
+        .get(query)
+        .await
+        .unwrap();
+    assert_eq!(response.errors()[0]["extensions"]["code"], "FORBIDDEN");
+    assert_eq!(
+        response.errors()[0]["message"],
+        "missing required scope: foo:internal"
+    );
+
+    let response = foo()
+        .with_identity(identity(&["foo:internal"]))
+        .get(query)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.assert_ok().data(),
+        json!({ "foo": { "barIds": [1, 2] } })
+    );
+}
+
+#[tokio::test]
+async fn bars_of_foo_follow_tenant() {
+    let response = foo().get("{ foo(id: 1) { bars { id } } }").await.unwrap();
+    assert_eq!(
+        sorted_ids(&response.assert_ok().data()["foo"]["bars"]),
+        [1, 2]
+    );
+    let query = "{ foo(id: 4) { bars { id } } }";
+    let response = foo().with_tenant("dev", "dev").get(query).await.unwrap();
+    assert_eq!(sorted_ids(&response.assert_ok().data()["foo"]["bars"]), [8]);
+    // 其他租户的数据不可见
+    let response = foo().get(query).await.unwrap();
+    assert_eq!(response.assert_ok().data(), json!({ "foo": null }));
+}
+
+#[tokio::test]
+async fn get_with_variables() {
+    let mut qry = HashMap::new();
+    qry.insert(
+        "query".to_string(),
+        "query Foo($id: Int!) { foo(id: $id) { name } }".to_string(),
+    );
+    qry.insert("operation_name".to_string(), "Foo".to_string());
+    qry.insert("variables".to_string(), r#"{"id": 3}"#.to_string());
+    let response = foo().with_flag(true).get_with(qry).await.unwrap();
+    assert_eq!(
+        response.assert_ok().data(),
+        json!({ "foo": { "name": "foo3" } })
+    );
+}
+
+#[tokio::test]
+async fn get_without_query() {
+    let response = foo().get_with(HashMap::new()).await.unwrap();
+    response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(response.body().is_empty());
+}
+
+#[tokio::test]
+async fn invalid_query() {
+    foo()
+        .get("{ foo(id: 1) { unknown } }")
+        .await
+        .unwrap()
+        .assert_status(StatusCode::BAD_REQUEST)
+        .assert_snapshot("foo_invalid_query");
+}
+
+#[tokio::test]
+async fn post_json() {
+    foo()
+        .post_json(json!({ "query": "query Foo($id: Int!) { foo(id: $id) { id name } }", "variables": { "id": 2 } }))
+        .await
+        .unwrap()
+        .assert_ok()
+        .assert_snapshot("foo_post_json");
+}
+
+#[tokio::test]
+async fn post_json_batch() {
+    let response = foo()
+        .post_json(json!([
+            { "query": "{ foo(id: 1) { name } }" },
+            { "query": "{ foo(id: 2) { name } }" },
+        ]))
+        .await
+        .unwrap();
+    response.assert_status(StatusCode::OK);
+    assert_eq!(
+        response.json(),
+        json!([
+            { "data": { "foo": { "name": "foo1" } } },
+            { "data": { "foo": { "name": "foo2" } } },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn post_graphql() {
+    foo()
+        .with_tenant("dev", "dev")
+        .post_graphql("{ foo(id: 3) { id name } }")
+        .await
+        .unwrap()
+        .assert_ok()
+        .assert_snapshot("foo_post_graphql");
+}
+
+#[tokio::test]
+async fn load_from_lib() {
+    let mut kit = TestKit::new();
+    let path = kit
+        .build_tokens(
+            &PluginName::new("foo").unwrap(),
+            demo::foo::genernate_tokens(),
+            &[],
+        )
+        .unwrap();
+    assert_eq!(path.file_name().unwrap(), "lib_foo.so");
+    let id = kit.load_lib(&path).unwrap();
+    let response = kit
+        .client(&id)
+        .unwrap()
+        .get("{ foo(id: 2) { name } }")
+        .await
+        .unwrap();
+    assert_eq!(
+        response.assert_ok().data(),
+        json!({ "foo": { "name": "foo2" } })
+    );
+    assert!(response.panic_message().is_none());
+}
@@ -0,0 +1,31 @@
+//! 相同的插件定义在不同的目录中编译，产物的sha256一致
+use std::fs;
+
+use my_interface::PluginName;
+use my_plugin_builder::demo;
+use my_plugin_testkit::TestKit;
+
+#[test]
+fn same_definition_builds_same_artifact() {
+    let name = PluginName::new("foo").unwrap();
+    // 第一次编译使用测试共用的缓存目录，第二次在独立的目录中重新编译
+    let cached = TestKit::new()
+        .offline()
+        .build_tokens(&name, demo::foo::genernate_tokens(), &[])
+        .expect("unable to build foo");
+    let dir =
+        std::env::temp_dir().join(format!("my-plugin-testkit-rebuild-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let rebuilt = TestKit::in_dir(&dir)
+        .offline()
+        .build_tokens(&name, demo::foo::genernate_tokens(), &[])
+        .expect("unable to rebuild foo");
+    assert_ne!(cached, rebuilt);
+
+    let checksum = |lib: &std::path::Path| {
+        fs::read_to_string(lib.with_file_name("lib_foo.so.sha256")).expect("checksum is installed")
+    };
+    let (expected, actual) = (checksum(&cached), checksum(&rebuilt));
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(expected, actual);
+}
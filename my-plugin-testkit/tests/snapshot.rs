@@ -0,0 +1,18 @@
+//! 快照断言与测试辅助函数
+
+use my_plugin_testkit::{assert_json_snapshot, sorted_ids};
+use serde_json::json;
+
+#[test]
+#[should_panic(expected = "snapshot missing_snapshot not found")]
+fn missing_snapshot_fails() {
+    // 本文件只有这一个测试读写环境变量
+    std::env::remove_var("UPDATE_SNAPSHOTS");
+    assert_json_snapshot("missing_snapshot", &json!({ "id": 1 }));
+}
+
+#[test]
+fn sorted_ids_ignores_order() {
+    let list = json!([{ "id": 3 }, { "id": 1, "name": "foo" }, { "id": 2 }]);
+    assert_eq!(sorted_ids(&list), [1, 2, 3]);
+}